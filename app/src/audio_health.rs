@@ -0,0 +1,401 @@
+use rodio::{source::SineWave, OutputStream, Sink, Source};
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Duration;
+use tracing::debug;
+
+/// Length of the analysis blocks the monitor works on
+const BLOCK_MS: u32 = 100;
+
+/// Problems the microphone monitor can detect
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HealthWarning {
+    /// The device delivers (near) exact zeros, typically a muted mic
+    DigitalSilence,
+    /// A sustained share of samples sits at full scale
+    Clipping,
+    /// Background noise is loud and speech barely rises above it
+    LowSnr,
+}
+
+impl HealthWarning {
+    /// Stable identifier used in status lines
+    pub fn code(&self) -> &'static str {
+        match self {
+            HealthWarning::DigitalSilence => "mic-silent",
+            HealthWarning::Clipping => "mic-clipping",
+            HealthWarning::LowSnr => "mic-low-snr",
+        }
+    }
+}
+
+impl fmt::Display for HealthWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            HealthWarning::DigitalSilence => {
+                "Microphone is producing digital silence - is it muted?"
+            }
+            HealthWarning::Clipping => "Microphone input is clipping - lower the input gain",
+            HealthWarning::LowSnr => {
+                "Microphone signal is barely above background noise - move closer or reduce noise"
+            }
+        };
+        f.write_str(text)
+    }
+}
+
+/// A warning starting or ending
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthEvent {
+    Raised(HealthWarning),
+    Cleared(HealthWarning),
+}
+
+/// Tunables for the microphone monitor
+#[derive(Debug, Clone)]
+pub struct HealthThresholds {
+    /// Peak level at or below which a block counts as digital silence
+    pub silence_peak: f32,
+    /// How long the signal must stay silent before warning
+    pub silence_duration: Duration,
+    /// Absolute sample level treated as clipped
+    pub clip_level: f32,
+    /// Share of clipped samples over `clip_window` that raises a warning
+    pub clip_fraction: f32,
+    pub clip_window: Duration,
+    /// Window over which noise floor and speech level are estimated
+    pub snr_window: Duration,
+    /// Minimum acceptable ratio between speech level and noise floor
+    pub min_snr_db: f32,
+    /// Low SNR is only reported when the noise floor is at least this loud,
+    /// so a quiet room with nobody talking does not trigger it
+    pub noisy_floor_dbfs: f32,
+    /// Calibrated level where speech starts; when set, low SNR is reported
+    /// once the noise floor comes within `min_snr_db` of it instead of using
+    /// `noisy_floor_dbfs`
+    pub speech_threshold_dbfs: Option<f32>,
+}
+
+impl Default for HealthThresholds {
+    fn default() -> Self {
+        Self {
+            silence_peak: 1e-4,
+            silence_duration: Duration::from_secs(3),
+            clip_level: 0.99,
+            clip_fraction: 0.01,
+            clip_window: Duration::from_secs(2),
+            snr_window: Duration::from_secs(10),
+            min_snr_db: 10.0,
+            noisy_floor_dbfs: -50.0,
+            speech_threshold_dbfs: None,
+        }
+    }
+}
+
+/// Convert a linear amplitude to dBFS
+pub fn to_dbfs(level: f32) -> f32 {
+    20.0 * level.max(1e-9).log10()
+}
+
+/// Play the warning beep: two short low tones, distinct from the start/stop beeps.
+///
+/// Runs on its own thread so the caller (usually the audio worker) never waits
+/// on the output device.
+pub fn play_warning_beep() {
+    std::thread::spawn(|| {
+        let (_stream, handle) = match OutputStream::try_default() {
+            Ok(output) => output,
+            Err(e) => {
+                debug!("No audio output for warning beep: {}", e);
+                return;
+            }
+        };
+        let Ok(sink) = Sink::try_new(&handle) else {
+            return;
+        };
+        for _ in 0..2 {
+            sink.append(
+                SineWave::new(300.0)
+                    .take_duration(Duration::from_millis(120))
+                    .amplify(0.3),
+            );
+            sink.append(
+                SineWave::new(0.0)
+                    .take_duration(Duration::from_millis(80))
+                    .amplify(0.0),
+            );
+        }
+        sink.sleep_until_end();
+    });
+}
+
+/// Statistics for one analysis block
+#[derive(Debug, Clone, Copy, Default)]
+struct BlockStats {
+    rms: f32,
+    peak: f32,
+    clipped: usize,
+    samples: usize,
+}
+
+/// Continuously analyses mono microphone samples and reports health changes
+pub struct HealthMonitor {
+    thresholds: HealthThresholds,
+    block_len: usize,
+    // Accumulators for the block in progress
+    sum_squares: f64,
+    peak: f32,
+    clipped: usize,
+    samples: usize,
+    // Recent block history
+    silent_blocks: usize,
+    clip_history: VecDeque<BlockStats>,
+    rms_history: VecDeque<f32>,
+    blocks_since_snr_check: usize,
+    active: Vec<HealthWarning>,
+}
+
+impl HealthMonitor {
+    pub fn new(sample_rate: u32) -> Self {
+        Self::with_thresholds(sample_rate, HealthThresholds::default())
+    }
+
+    pub fn with_thresholds(sample_rate: u32, thresholds: HealthThresholds) -> Self {
+        let block_len = (sample_rate * BLOCK_MS / 1000).max(1) as usize;
+        Self {
+            thresholds,
+            block_len,
+            sum_squares: 0.0,
+            peak: 0.0,
+            clipped: 0,
+            samples: 0,
+            silent_blocks: 0,
+            clip_history: VecDeque::new(),
+            rms_history: VecDeque::new(),
+            blocks_since_snr_check: 0,
+            active: Vec::new(),
+        }
+    }
+
+    /// Feed mono samples, returning any warnings raised or cleared
+    pub fn process(&mut self, samples: &[f32]) -> Vec<HealthEvent> {
+        let mut events = Vec::new();
+
+        for &sample in samples {
+            let level = sample.abs();
+            self.sum_squares += (sample as f64) * (sample as f64);
+            self.peak = self.peak.max(level);
+            if level >= self.thresholds.clip_level {
+                self.clipped += 1;
+            }
+            self.samples += 1;
+
+            if self.samples == self.block_len {
+                let block = BlockStats {
+                    rms: (self.sum_squares / self.samples as f64).sqrt() as f32,
+                    peak: self.peak,
+                    clipped: self.clipped,
+                    samples: self.samples,
+                };
+                self.sum_squares = 0.0;
+                self.peak = 0.0;
+                self.clipped = 0;
+                self.samples = 0;
+
+                self.analyse_block(block, &mut events);
+            }
+        }
+
+        events
+    }
+
+    fn blocks_in(&self, duration: Duration) -> usize {
+        ((duration.as_millis() as u64 / BLOCK_MS as u64) as usize).max(1)
+    }
+
+    fn analyse_block(&mut self, block: BlockStats, events: &mut Vec<HealthEvent>) {
+        // Digital silence
+        if block.peak <= self.thresholds.silence_peak {
+            self.silent_blocks += 1;
+        } else {
+            self.silent_blocks = 0;
+        }
+        let silent = self.silent_blocks >= self.blocks_in(self.thresholds.silence_duration);
+        self.set_warning(HealthWarning::DigitalSilence, silent, events);
+
+        // Clipping, with hysteresis so a borderline signal does not flap
+        self.clip_history.push_back(block);
+        let clip_blocks = self.blocks_in(self.thresholds.clip_window);
+        while self.clip_history.len() > clip_blocks {
+            self.clip_history.pop_front();
+        }
+        if self.clip_history.len() == clip_blocks {
+            let clipped: usize = self.clip_history.iter().map(|b| b.clipped).sum();
+            let total: usize = self.clip_history.iter().map(|b| b.samples).sum();
+            let fraction = clipped as f32 / total as f32;
+            if fraction > self.thresholds.clip_fraction {
+                self.set_warning(HealthWarning::Clipping, true, events);
+            } else if fraction < self.thresholds.clip_fraction / 10.0 {
+                self.set_warning(HealthWarning::Clipping, false, events);
+            }
+        }
+
+        // Signal-to-noise ratio, re-evaluated once per second
+        self.rms_history.push_back(block.rms);
+        let snr_blocks = self.blocks_in(self.thresholds.snr_window);
+        while self.rms_history.len() > snr_blocks {
+            self.rms_history.pop_front();
+        }
+        self.blocks_since_snr_check += 1;
+        if self.rms_history.len() == snr_blocks && self.blocks_since_snr_check >= 10 {
+            self.blocks_since_snr_check = 0;
+            if silent {
+                self.set_warning(HealthWarning::LowSnr, false, events);
+            } else {
+                let (noise_floor, speech_level) = self.noise_and_speech_levels();
+                let snr_db = to_dbfs(speech_level) - to_dbfs(noise_floor);
+                let noisy = match self.thresholds.speech_threshold_dbfs {
+                    Some(threshold) => {
+                        to_dbfs(noise_floor) > threshold - self.thresholds.min_snr_db
+                    }
+                    None => to_dbfs(noise_floor) > self.thresholds.noisy_floor_dbfs,
+                };
+                if noisy && snr_db < self.thresholds.min_snr_db {
+                    self.set_warning(HealthWarning::LowSnr, true, events);
+                } else if !noisy || snr_db >= self.thresholds.min_snr_db + 3.0 {
+                    self.set_warning(HealthWarning::LowSnr, false, events);
+                }
+            }
+        }
+    }
+
+    /// 10th and 90th percentile block RMS over the SNR window
+    fn noise_and_speech_levels(&self) -> (f32, f32) {
+        let mut levels: Vec<f32> = self.rms_history.iter().copied().collect();
+        levels.sort_by(|a, b| a.total_cmp(b));
+        let at = |fraction: f32| levels[((levels.len() - 1) as f32 * fraction) as usize];
+        (at(0.1), at(0.9))
+    }
+
+    fn set_warning(&mut self, warning: HealthWarning, on: bool, events: &mut Vec<HealthEvent>) {
+        let is_active = self.active.contains(&warning);
+        if on && !is_active {
+            self.active.push(warning);
+            events.push(HealthEvent::Raised(warning));
+        } else if !on && is_active {
+            self.active.retain(|w| *w != warning);
+            events.push(HealthEvent::Cleared(warning));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 16_000;
+
+    /// Deterministic pseudo-random noise in [-amplitude, amplitude]
+    fn noise(len: usize, amplitude: f32, seed: &mut u32) -> Vec<f32> {
+        (0..len)
+            .map(|_| {
+                *seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                ((*seed >> 8) as f32 / (1u32 << 24) as f32 * 2.0 - 1.0) * amplitude
+            })
+            .collect()
+    }
+
+    fn seconds(secs: f32) -> usize {
+        (RATE as f32 * secs) as usize
+    }
+
+    #[test]
+    fn test_digital_silence_raised_and_cleared() {
+        let mut monitor = HealthMonitor::new(RATE);
+
+        let events = monitor.process(&vec![0.0; seconds(2.0)]);
+        assert!(events.is_empty());
+
+        let events = monitor.process(&vec![0.0; seconds(1.5)]);
+        assert_eq!(
+            events,
+            vec![HealthEvent::Raised(HealthWarning::DigitalSilence)]
+        );
+
+        let mut seed = 1;
+        let events = monitor.process(&noise(seconds(0.2), 0.1, &mut seed));
+        assert_eq!(
+            events,
+            vec![HealthEvent::Cleared(HealthWarning::DigitalSilence)]
+        );
+    }
+
+    #[test]
+    fn test_persistent_clipping() {
+        let mut monitor = HealthMonitor::new(RATE);
+        let square: Vec<f32> = (0..seconds(3.0))
+            .map(|i| if (i / 40) % 2 == 0 { 1.0 } else { -1.0 })
+            .collect();
+
+        let events = monitor.process(&square);
+        assert!(events.contains(&HealthEvent::Raised(HealthWarning::Clipping)));
+        assert!(monitor.active.contains(&HealthWarning::Clipping));
+
+        let mut seed = 7;
+        let events = monitor.process(&noise(seconds(3.0), 0.2, &mut seed));
+        assert!(events.contains(&HealthEvent::Cleared(HealthWarning::Clipping)));
+    }
+
+    #[test]
+    fn test_low_snr_with_loud_constant_noise() {
+        let mut monitor = HealthMonitor::new(RATE);
+        let mut seed = 42;
+
+        let events = monitor.process(&noise(seconds(11.0), 0.1, &mut seed));
+        assert!(events.contains(&HealthEvent::Raised(HealthWarning::LowSnr)));
+    }
+
+    #[test]
+    fn test_healthy_speech_pattern_has_no_warnings() {
+        let mut monitor = HealthMonitor::new(RATE);
+        let mut seed = 3;
+        let mut events = Vec::new();
+
+        // Alternate quiet background with louder "speech" bursts
+        for _ in 0..12 {
+            events.extend(monitor.process(&noise(seconds(0.5), 0.001, &mut seed)));
+            events.extend(monitor.process(&noise(seconds(0.5), 0.3, &mut seed)));
+        }
+
+        assert!(events.is_empty(), "unexpected events: {:?}", events);
+        assert!(monitor.active.is_empty());
+    }
+
+    #[test]
+    fn test_calibrated_speech_threshold() {
+        let thresholds = HealthThresholds {
+            speech_threshold_dbfs: Some(-45.0),
+            ..HealthThresholds::default()
+        };
+        let mut calibrated = HealthMonitor::with_thresholds(RATE, thresholds);
+        let mut uncalibrated = HealthMonitor::new(RATE);
+
+        // Noise around -53 dBFS is quiet by default standards but close to a
+        // -45 dBFS speech threshold for a soft-spoken user
+        let mut seed = 11;
+        let samples = noise(seconds(11.0), 0.004, &mut seed);
+        assert!(calibrated
+            .process(&samples)
+            .contains(&HealthEvent::Raised(HealthWarning::LowSnr)));
+        assert!(uncalibrated.process(&samples).is_empty());
+    }
+
+    #[test]
+    fn test_quiet_room_without_speech_is_not_low_snr() {
+        let mut monitor = HealthMonitor::new(RATE);
+        let mut seed = 9;
+
+        let events = monitor.process(&noise(seconds(12.0), 0.001, &mut seed));
+        assert!(events.is_empty(), "unexpected events: {:?}", events);
+    }
+}
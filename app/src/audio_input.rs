@@ -1,16 +1,87 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Device, SampleFormat, Stream};
-use tracing::{debug, error};
+use cpal::{
+    Device, FromSample, SampleFormat, SampleRate, SizedSample, Stream, SupportedStreamConfig,
+    SupportedStreamConfigRange,
+};
+use tracing::{debug, error, warn};
+
+/// Frames converted per pass in non-f32 streams. Larger callback buffers
+/// are converted in several passes so the audio thread never allocates.
+const CONVERT_CHUNK_FRAMES: usize = 4096;
+
+/// Requested capture format; anything left as `None` uses the device default
+#[derive(Debug, Clone, Default)]
+pub struct AudioPreferences {
+    pub sample_format: Option<SampleFormat>,
+    pub sample_rate: Option<u32>,
+}
+
+/// Parse a sample format name as printed by cpal (e.g. "i16", "f32")
+pub fn parse_sample_format(name: &str) -> Result<SampleFormat> {
+    let format = match name.to_ascii_lowercase().as_str() {
+        "i8" => SampleFormat::I8,
+        "i16" => SampleFormat::I16,
+        "i32" => SampleFormat::I32,
+        "i64" => SampleFormat::I64,
+        "u8" => SampleFormat::U8,
+        "u16" => SampleFormat::U16,
+        "u32" => SampleFormat::U32,
+        "u64" => SampleFormat::U64,
+        "f32" => SampleFormat::F32,
+        "f64" => SampleFormat::F64,
+        _ => bail!("Unknown sample format '{name}' (expected one of i8, i16, i32, i64, u8, u16, u32, u64, f32, f64)"),
+    };
+    Ok(format)
+}
+
+/// Pick the supported config that best matches `prefs`.
+///
+/// Ranges not matching the requested format or rate are discarded, and the
+/// remaining ones are ranked with cpal's default heuristics. Without an
+/// explicit rate, `default_rate` is used when the range allows it.
+fn choose_config(
+    ranges: impl IntoIterator<Item = SupportedStreamConfigRange>,
+    prefs: &AudioPreferences,
+    default_rate: u32,
+) -> Option<SupportedStreamConfig> {
+    let best = ranges
+        .into_iter()
+        .filter(|range| {
+            prefs
+                .sample_format
+                .is_none_or(|format| range.sample_format() == format)
+        })
+        .filter(|range| {
+            prefs.sample_rate.is_none_or(|rate| {
+                range.min_sample_rate().0 <= rate && rate <= range.max_sample_rate().0
+            })
+        })
+        .max_by(|a, b| a.cmp_default_heuristics(b))?;
+
+    let rate = prefs.sample_rate.unwrap_or(default_rate);
+    Some(
+        best.try_with_sample_rate(SampleRate(rate))
+            .unwrap_or_else(|| best.with_max_sample_rate()),
+    )
+}
 
 pub struct AudioInput {
     device: Device,
+    device_name: String,
     config: cpal::StreamConfig,
+    sample_format: SampleFormat,
     stream: Option<Stream>,
 }
 
 impl AudioInput {
+    #[allow(dead_code)]
     pub fn new() -> Result<Self> {
+        Self::with_preferences(&AudioPreferences::default())
+    }
+
+    /// Open the default input device, requesting the given format and rate
+    pub fn with_preferences(prefs: &AudioPreferences) -> Result<Self> {
         let host = cpal::default_host();
 
         // Get the default input device
@@ -18,24 +89,7 @@ impl AudioInput {
             .default_input_device()
             .context("Failed to get default input device")?;
 
-        debug!("Using input device: {}", device.name()?);
-
-        // Get the default config for the input device
-        let config = device
-            .default_input_config()
-            .context("Failed to get default input config")?
-            .config();
-
-        debug!(
-            "Input config: {} channels, {} Hz sample rate",
-            config.channels, config.sample_rate.0
-        );
-
-        Ok(Self {
-            device,
-            config,
-            stream: None,
-        })
+        Self::from_device(device, prefs)
     }
 
     #[allow(dead_code)]
@@ -59,22 +113,48 @@ impl AudioInput {
             .next()
             .context(format!("Device '{device_name}' not found"))?;
 
-        debug!("Using input device: {}", device.name()?);
+        Self::from_device(device, &AudioPreferences::default())
+    }
+
+    fn from_device(device: Device, prefs: &AudioPreferences) -> Result<Self> {
+        let device_name = device.name()?;
+        debug!("Using input device: {}", device_name);
 
-        // Get the default config for the input device
-        let config = device
+        let default_config = device
             .default_input_config()
-            .context("Failed to get default input config")?
-            .config();
+            .context("Failed to get default input config")?;
+
+        let supported = if prefs.sample_format.is_none() && prefs.sample_rate.is_none() {
+            default_config
+        } else {
+            let ranges = device
+                .supported_input_configs()
+                .context("Failed to query supported input configs")?;
+            match choose_config(ranges, prefs, default_config.sample_rate().0) {
+                Some(config) => config,
+                None => {
+                    warn!(
+                        "No input config matches {:?}; falling back to the device default",
+                        prefs
+                    );
+                    default_config
+                }
+            }
+        };
+
+        let sample_format = supported.sample_format();
+        let config = supported.config();
 
         debug!(
-            "Input config: {} channels, {} Hz sample rate",
-            config.channels, config.sample_rate.0
+            "Input config: {} channels, {} Hz sample rate, {} samples",
+            config.channels, config.sample_rate.0, sample_format
         );
 
         Ok(Self {
             device,
+            device_name,
             config,
+            sample_format,
             stream: None,
         })
     }
@@ -93,44 +173,41 @@ impl AudioInput {
         Ok(device_names)
     }
 
-    pub fn start_recording<F>(&mut self, mut callback: F) -> Result<()>
+    /// Describe every config the current device supports, one per line
+    pub fn describe_supported_configs(&self) -> Result<Vec<String>> {
+        let configs = self
+            .device
+            .supported_input_configs()
+            .context("Failed to query supported input configs")?
+            .map(|range| {
+                format!(
+                    "{} ch, {}-{} Hz, {}",
+                    range.channels(),
+                    range.min_sample_rate().0,
+                    range.max_sample_rate().0,
+                    range.sample_format()
+                )
+            })
+            .collect();
+        Ok(configs)
+    }
+
+    pub fn start_recording<F>(&mut self, callback: F) -> Result<()>
     where
         F: FnMut(&[f32]) + Send + 'static,
     {
-        let err_fn = |err| error!("An error occurred on the audio stream: {}", err);
-
-        let stream = match self.device.default_input_config()?.sample_format() {
-            SampleFormat::F32 => self.device.build_input_stream(
-                &self.config,
-                move |data: &[f32], _: &_| callback(data),
-                err_fn,
-                None,
-            )?,
-            SampleFormat::I16 => self.device.build_input_stream(
-                &self.config,
-                move |data: &[i16], _: &_| {
-                    // Convert i16 samples to f32
-                    let float_data: Vec<f32> =
-                        data.iter().map(|&s| s as f32 / i16::MAX as f32).collect();
-                    callback(&float_data);
-                },
-                err_fn,
-                None,
-            )?,
-            SampleFormat::U16 => self.device.build_input_stream(
-                &self.config,
-                move |data: &[u16], _: &_| {
-                    // Convert u16 samples to f32
-                    let float_data: Vec<f32> = data
-                        .iter()
-                        .map(|&s| ((s as f32 / u16::MAX as f32) * 2.0) - 1.0)
-                        .collect();
-                    callback(&float_data);
-                },
-                err_fn,
-                None,
-            )?,
-            _ => return Err(anyhow::anyhow!("Unsupported sample format")),
+        let stream = match self.sample_format {
+            SampleFormat::F32 => self.build_f32_stream(callback)?,
+            SampleFormat::F64 => self.build_converting_stream::<f64, _>(callback)?,
+            SampleFormat::I8 => self.build_converting_stream::<i8, _>(callback)?,
+            SampleFormat::I16 => self.build_converting_stream::<i16, _>(callback)?,
+            SampleFormat::I32 => self.build_converting_stream::<i32, _>(callback)?,
+            SampleFormat::I64 => self.build_converting_stream::<i64, _>(callback)?,
+            SampleFormat::U8 => self.build_converting_stream::<u8, _>(callback)?,
+            SampleFormat::U16 => self.build_converting_stream::<u16, _>(callback)?,
+            SampleFormat::U32 => self.build_converting_stream::<u32, _>(callback)?,
+            SampleFormat::U64 => self.build_converting_stream::<u64, _>(callback)?,
+            other => bail!("Unsupported sample format: {other}"),
         };
 
         stream.play()?;
@@ -139,11 +216,47 @@ impl AudioInput {
         Ok(())
     }
 
-    #[allow(dead_code)]
+    fn build_f32_stream<F>(&self, mut callback: F) -> Result<Stream>
+    where
+        F: FnMut(&[f32]) + Send + 'static,
+    {
+        let err_fn = |err| error!("An error occurred on the audio stream: {}", err);
+        let stream = self.device.build_input_stream(
+            &self.config,
+            move |data: &[f32], _: &_| callback(data),
+            err_fn,
+            None,
+        )?;
+        Ok(stream)
+    }
+
+    /// Build a stream for any non-f32 sample type, converting to f32 in place
+    fn build_converting_stream<T, F>(&self, mut callback: F) -> Result<Stream>
+    where
+        T: SizedSample,
+        f32: FromSample<T>,
+        F: FnMut(&[f32]) + Send + 'static,
+    {
+        let err_fn = |err| error!("An error occurred on the audio stream: {}", err);
+        // Allocated once here; whole frames so no pass splits a frame
+        let mut scratch = vec![0.0f32; CONVERT_CHUNK_FRAMES * self.config.channels.max(1) as usize];
+        let stream = self.device.build_input_stream(
+            &self.config,
+            move |data: &[T], _: &_| convert_in_chunks(data, &mut scratch, &mut callback),
+            err_fn,
+            None,
+        )?;
+        Ok(stream)
+    }
+
     pub fn stop_recording(&mut self) {
         self.stream = None;
     }
 
+    pub fn device_name(&self) -> &str {
+        &self.device_name
+    }
+
     pub fn get_sample_rate(&self) -> u32 {
         self.config.sample_rate.0
     }
@@ -151,4 +264,134 @@ impl AudioInput {
     pub fn get_channels(&self) -> u16 {
         self.config.channels
     }
+
+    pub fn get_sample_format(&self) -> SampleFormat {
+        self.sample_format
+    }
+}
+
+/// Convert `data` to f32 through `scratch`, passing each converted chunk to
+/// `callback`
+fn convert_in_chunks<T, F>(data: &[T], scratch: &mut [f32], callback: &mut F)
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+    F: FnMut(&[f32]),
+{
+    for chunk in data.chunks(scratch.len()) {
+        let converted = &mut scratch[..chunk.len()];
+        for (out, &sample) in converted.iter_mut().zip(chunk) {
+            *out = sample.to_sample::<f32>();
+        }
+        callback(converted);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpal::{Sample, SupportedBufferSize};
+
+    fn range(
+        channels: u16,
+        min: u32,
+        max: u32,
+        format: SampleFormat,
+    ) -> SupportedStreamConfigRange {
+        SupportedStreamConfigRange::new(
+            channels,
+            SampleRate(min),
+            SampleRate(max),
+            SupportedBufferSize::Unknown,
+            format,
+        )
+    }
+
+    #[test]
+    fn test_parse_sample_format() {
+        assert_eq!(parse_sample_format("i32").unwrap(), SampleFormat::I32);
+        assert_eq!(parse_sample_format("F64").unwrap(), SampleFormat::F64);
+        assert_eq!(parse_sample_format("u8").unwrap(), SampleFormat::U8);
+        assert!(parse_sample_format("i24").is_err());
+    }
+
+    #[test]
+    fn test_choose_config_by_format() {
+        let ranges = vec![
+            range(2, 8_000, 48_000, SampleFormat::F32),
+            range(2, 8_000, 48_000, SampleFormat::I32),
+        ];
+        let prefs = AudioPreferences {
+            sample_format: Some(SampleFormat::I32),
+            sample_rate: None,
+        };
+
+        let config = choose_config(ranges, &prefs, 44_100).unwrap();
+        assert_eq!(config.sample_format(), SampleFormat::I32);
+        assert_eq!(config.sample_rate().0, 44_100);
+    }
+
+    #[test]
+    fn test_choose_config_by_rate() {
+        let ranges = vec![
+            range(2, 44_100, 48_000, SampleFormat::F32),
+            range(1, 8_000, 16_000, SampleFormat::I16),
+        ];
+        let prefs = AudioPreferences {
+            sample_format: None,
+            sample_rate: Some(16_000),
+        };
+
+        let config = choose_config(ranges, &prefs, 48_000).unwrap();
+        assert_eq!(config.sample_format(), SampleFormat::I16);
+        assert_eq!(config.sample_rate().0, 16_000);
+        assert_eq!(config.channels(), 1);
+    }
+
+    #[test]
+    fn test_choose_config_default_rate_out_of_range() {
+        let ranges = vec![range(1, 8_000, 16_000, SampleFormat::U8)];
+        let prefs = AudioPreferences {
+            sample_format: Some(SampleFormat::U8),
+            sample_rate: None,
+        };
+
+        // Default rate is outside the range, so the maximum rate is used
+        let config = choose_config(ranges, &prefs, 48_000).unwrap();
+        assert_eq!(config.sample_rate().0, 16_000);
+    }
+
+    #[test]
+    fn test_choose_config_no_match() {
+        let ranges = vec![range(2, 8_000, 48_000, SampleFormat::F32)];
+        let prefs = AudioPreferences {
+            sample_format: Some(SampleFormat::I8),
+            sample_rate: None,
+        };
+
+        assert!(choose_config(ranges, &prefs, 48_000).is_none());
+    }
+
+    #[test]
+    fn test_sample_conversion_ranges() {
+        // Every supported format should map its extremes into [-1.0, 1.0]
+        assert_eq!(i8::MIN.to_sample::<f32>(), -1.0);
+        assert_eq!(i32::MIN.to_sample::<f32>(), -1.0);
+        assert_eq!(u8::MIN.to_sample::<f32>(), -1.0);
+        assert_eq!(0.5f64.to_sample::<f32>(), 0.5);
+        assert!((u16::MAX.to_sample::<f32>() - 1.0).abs() < 1e-3);
+        assert!((u64::MAX.to_sample::<f32>() - 1.0).abs() < 1e-3);
+        assert!(128u8.to_sample::<f32>().abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_convert_in_chunks() {
+        let data: Vec<i16> = vec![0, i16::MIN, 0, i16::MIN, 0];
+        let mut scratch = [f32::NAN; 2];
+        let mut chunks = Vec::new();
+        convert_in_chunks(&data, &mut scratch, &mut |chunk: &[f32]| {
+            chunks.push(chunk.to_vec())
+        });
+        assert_eq!(chunks, vec![vec![0.0, -1.0], vec![0.0, -1.0], vec![0.0]]);
+    }
 }
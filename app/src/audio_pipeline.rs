@@ -0,0 +1,421 @@
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc as tokio_mpsc;
+use tracing::{debug, info, warn};
+
+use crate::metrics::METRICS;
+use crate::ring_buffer::{ring_buffer, Consumer, Producer};
+use crate::stt_client::AudioBuffer;
+
+/// How much audio the capture ring buffer can hold before it overruns
+const RING_BUFFER_SECONDS: usize = 2;
+
+/// How long the worker sleeps when the ring buffer is empty
+const WORKER_IDLE_SLEEP: Duration = Duration::from_millis(5);
+
+/// Minimum interval between overrun warnings
+const OVERRUN_LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Duration of each PCM chunk sent to the STT service
+pub const CHUNK_DURATION_MS: u32 = 160;
+
+/// How much audio is queued locally while the STT connection is being set up
+const STARTUP_QUEUE_SECONDS: u32 = 10;
+
+/// Parameters for the audio worker
+#[derive(Debug, Clone, Copy)]
+pub struct WorkerConfig {
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// Linear gain applied to the mono signal, clamped to full scale
+    pub gain: f32,
+}
+
+/// Create the ring buffer that sits between the capture callback and the worker
+pub fn capture_ring(sample_rate: u32, channels: u16) -> (Producer<f32>, Consumer<f32>) {
+    let capacity = sample_rate as usize * channels as usize * RING_BUFFER_SECONDS;
+    debug!("Capture ring buffer capacity: {} samples", capacity);
+    ring_buffer(capacity)
+}
+
+/// Average interleaved frames down to mono, appending the result to `out`
+pub fn downmix_to_mono(data: &[f32], channels: u16, out: &mut Vec<f32>) {
+    let channels = channels.max(1) as usize;
+    if channels == 1 {
+        out.extend_from_slice(data);
+        return;
+    }
+
+    for frame in data.chunks_exact(channels) {
+        out.push(frame.iter().sum::<f32>() / channels as f32);
+    }
+}
+
+/// Scale samples in place, clamping to full scale
+pub fn apply_gain(samples: &mut [f32], gain: f32) {
+    for sample in samples {
+        *sample = (*sample * gain).clamp(-1.0, 1.0);
+    }
+}
+
+/// Spawn the worker thread that drains the capture ring buffer, converts the
+/// samples to mono PCM chunks and hands them to `sink`.
+///
+/// `tap` sees every block of mono samples (after gain) before it is encoded,
+/// which is where analysis such as the microphone health monitor hooks in.
+///
+/// The worker exits when `sink` returns `false` or when the producer side of
+/// the ring buffer has been dropped and all remaining samples are processed.
+pub fn spawn_audio_worker<T, S>(
+    mut consumer: Consumer<f32>,
+    config: WorkerConfig,
+    mut tap: T,
+    mut sink: S,
+) -> thread::JoinHandle<()>
+where
+    T: FnMut(&[f32]) + Send + 'static,
+    S: FnMut(Vec<u8>) -> bool + Send + 'static,
+{
+    thread::Builder::new()
+        .name("audio-worker".to_string())
+        .spawn(move || {
+            let WorkerConfig {
+                channels,
+                sample_rate,
+                gain,
+            } = config;
+            let frame_len = channels.max(1) as usize;
+            let mut scratch = vec![0.0f32; frame_len * 1024];
+            let mut mono = Vec::with_capacity(1024);
+            let mut audio_buffer = AudioBuffer::new(sample_rate, CHUNK_DURATION_MS);
+
+            let mut reported_overruns = 0;
+            let mut reported_dropped = 0;
+            let mut last_overrun_log: Option<Instant> = None;
+
+            loop {
+                let count = consumer.pop_slice(&mut scratch);
+                if count == 0 {
+                    if consumer.is_abandoned() && consumer.is_empty() {
+                        debug!("Capture stream closed; audio worker exiting");
+                        break;
+                    }
+                    thread::sleep(WORKER_IDLE_SLEEP);
+                    continue;
+                }
+
+                mono.clear();
+                downmix_to_mono(&scratch[..count], channels, &mut mono);
+                if gain != 1.0 {
+                    apply_gain(&mut mono, gain);
+                }
+                tap(&mono);
+
+                for chunk in audio_buffer.add_samples(&mono) {
+                    if !sink(chunk) {
+                        debug!("Audio sink closed; audio worker exiting");
+                        return;
+                    }
+                    METRICS.audio_chunks_sent.fetch_add(1, Ordering::Relaxed);
+                }
+
+                // Publish overruns recorded by the capture callback
+                let overruns = consumer.overruns();
+                if overruns != reported_overruns {
+                    let dropped = consumer.dropped();
+                    METRICS
+                        .audio_overruns
+                        .fetch_add(overruns - reported_overruns, Ordering::Relaxed);
+                    METRICS
+                        .audio_samples_dropped
+                        .fetch_add(dropped - reported_dropped, Ordering::Relaxed);
+                    reported_overruns = overruns;
+                    reported_dropped = dropped;
+
+                    let should_log = last_overrun_log
+                        .map(|prev| prev.elapsed() >= OVERRUN_LOG_INTERVAL)
+                        .unwrap_or(true);
+                    if should_log {
+                        warn!(
+                            "Audio capture overrun: {} callbacks dropped ({} samples) so far",
+                            overruns, dropped
+                        );
+                        last_overrun_log = Some(Instant::now());
+                    }
+                }
+            }
+        })
+        .expect("failed to spawn audio worker thread")
+}
+
+/// Bounded queue of PCM chunks captured before the STT connection is ready.
+///
+/// When full the oldest chunk is discarded, so the audio that is eventually
+/// flushed stays contiguous with the live stream that follows it.
+pub struct StartupQueue {
+    chunks: VecDeque<Vec<u8>>,
+    max_chunks: usize,
+    dropped: u64,
+}
+
+impl StartupQueue {
+    pub fn new(max_chunks: usize) -> Self {
+        Self {
+            chunks: VecDeque::with_capacity(max_chunks),
+            max_chunks: max_chunks.max(1),
+            dropped: 0,
+        }
+    }
+
+    /// Queue sized to hold [`STARTUP_QUEUE_SECONDS`] of chunks
+    pub fn for_startup() -> Self {
+        Self::new((STARTUP_QUEUE_SECONDS * 1000 / CHUNK_DURATION_MS) as usize)
+    }
+
+    pub fn push(&mut self, chunk: Vec<u8>) {
+        if self.chunks.len() == self.max_chunks {
+            self.chunks.pop_front();
+            self.dropped += 1;
+        }
+        self.chunks.push_back(chunk);
+    }
+
+    pub fn drain(&mut self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        self.chunks.drain(..)
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+/// Audio sink that queues chunks until the STT connection hands over its
+/// sender, then flushes the queue and forwards chunks directly.
+///
+/// This lets capture start the moment dictation is activated instead of
+/// after the TLS/WebSocket handshake, so the first words are not lost. If
+/// the connection goes away the sink falls back to queueing until a new
+/// sender arrives, which is how a long-running session reconnects.
+pub struct BufferedSender {
+    queue: StartupQueue,
+    ready: mpsc::Receiver<tokio_mpsc::Sender<Vec<u8>>>,
+    sender: Option<tokio_mpsc::Sender<Vec<u8>>>,
+    started: Instant,
+}
+
+impl BufferedSender {
+    /// Create the sink together with the handle used to pass it connection
+    /// senders. Once the handle is dropped and the current connection is
+    /// gone the sink reports itself closed.
+    pub fn new(queue: StartupQueue) -> (Self, mpsc::Sender<tokio_mpsc::Sender<Vec<u8>>>) {
+        let (ready_tx, ready) = mpsc::channel();
+        let sink = Self {
+            queue,
+            ready,
+            sender: None,
+            started: Instant::now(),
+        };
+        (sink, ready_tx)
+    }
+
+    /// Forward or queue `chunk`; returns `false` once no connection can follow
+    pub fn send(&mut self, chunk: Vec<u8>) -> bool {
+        if self.sender.is_none() {
+            // Several connections may have come and gone since the last
+            // chunk; only the newest one is worth flushing to
+            let mut latest = None;
+            loop {
+                match self.ready.try_recv() {
+                    Ok(sender) => latest = Some(sender),
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) if latest.is_none() => {
+                        debug!("No STT connection will follow; dropping queued audio");
+                        return false;
+                    }
+                    Err(mpsc::TryRecvError::Disconnected) => break,
+                }
+            }
+
+            match latest {
+                Some(sender) => {
+                    self.flush_startup_queue(&sender);
+                    self.sender = Some(sender);
+                }
+                None => {
+                    self.queue.push(chunk);
+                    return true;
+                }
+            }
+        }
+
+        let sender = self.sender.as_ref().expect("sender set above");
+        match sender.blocking_send(chunk) {
+            Ok(()) => true,
+            Err(e) => {
+                debug!("STT connection closed; queueing audio until the next one");
+                self.sender = None;
+                self.started = Instant::now();
+                self.queue.push(e.0);
+                true
+            }
+        }
+    }
+
+    fn flush_startup_queue(&mut self, sender: &tokio_mpsc::Sender<Vec<u8>>) {
+        let gap = self.started.elapsed();
+        let queued = self.queue.len() as u64;
+        let dropped = self.queue.dropped();
+        METRICS
+            .startup_gap_ms
+            .store(gap.as_millis() as u64, Ordering::Relaxed);
+        METRICS
+            .startup_chunks_queued
+            .store(queued + dropped, Ordering::Relaxed);
+        METRICS
+            .startup_chunks_dropped
+            .store(dropped, Ordering::Relaxed);
+
+        info!(
+            "STT connection ready after {} ms; flushing {} queued audio chunks",
+            gap.as_millis(),
+            queued
+        );
+        if dropped > 0 {
+            warn!(
+                "Startup audio queue overflowed; {} chunks from the start of dictation were dropped",
+                dropped
+            );
+        }
+
+        for chunk in self.queue.drain() {
+            if sender.blocking_send(chunk).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_downmix_stereo() {
+        let mut out = Vec::new();
+        downmix_to_mono(&[1.0, 0.0, 0.5, 0.5, -1.0, 1.0], 2, &mut out);
+        assert_eq!(out, vec![0.5, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn test_downmix_mono_passthrough() {
+        let mut out = vec![0.25];
+        downmix_to_mono(&[0.1, 0.2], 1, &mut out);
+        assert_eq!(out, vec![0.25, 0.1, 0.2]);
+    }
+
+    #[test]
+    fn test_downmix_multichannel() {
+        let mut out = Vec::new();
+        downmix_to_mono(&[0.3, 0.3, 0.3, 0.9, 0.0, 0.0], 3, &mut out);
+        assert_eq!(out.len(), 2);
+        assert!((out[0] - 0.3).abs() < 1e-6);
+        assert!((out[1] - 0.3).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_apply_gain_clamps() {
+        let mut samples = vec![0.1, -0.4, 0.8];
+        apply_gain(&mut samples, 2.0);
+        assert_eq!(samples, vec![0.2, -0.8, 1.0]);
+    }
+
+    #[test]
+    fn test_worker_produces_chunks_and_exits() {
+        let sample_rate = 16_000;
+        let (mut producer, consumer) = capture_ring(sample_rate, 2);
+        let (tx, rx) = mpsc::channel();
+
+        let config = WorkerConfig {
+            channels: 2,
+            sample_rate,
+            gain: 1.0,
+        };
+        let handle = spawn_audio_worker(
+            consumer,
+            config,
+            |_| {},
+            move |chunk| tx.send(chunk).is_ok(),
+        );
+
+        // One chunk worth of stereo frames
+        let frames = (sample_rate * CHUNK_DURATION_MS / 1000) as usize;
+        let stereo = vec![0.0f32; frames * 2];
+        assert!(producer.push_slice(&stereo));
+        drop(producer);
+
+        handle.join().unwrap();
+        let chunks: Vec<Vec<u8>> = rx.iter().collect();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].len(), frames * 2);
+    }
+
+    #[test]
+    fn test_startup_queue_drops_oldest() {
+        let mut queue = StartupQueue::new(2);
+        queue.push(vec![1]);
+        queue.push(vec![2]);
+        queue.push(vec![3]);
+
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(queue.drain().collect::<Vec<_>>(), vec![vec![2], vec![3]]);
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn test_buffered_sender_flushes_in_order() {
+        let (mut sink, ready) = BufferedSender::new(StartupQueue::new(8));
+        assert!(sink.send(vec![1]));
+        assert!(sink.send(vec![2]));
+
+        let (tx, mut rx) = tokio_mpsc::channel(8);
+        ready.send(tx).unwrap();
+        assert!(sink.send(vec![3]));
+
+        let received: Vec<Vec<u8>> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        assert_eq!(received, vec![vec![1], vec![2], vec![3]]);
+    }
+
+    #[test]
+    fn test_buffered_sender_closes_without_connection() {
+        let (mut sink, ready) = BufferedSender::new(StartupQueue::new(8));
+        drop(ready);
+        assert!(!sink.send(vec![1]));
+    }
+
+    #[test]
+    fn test_buffered_sender_switches_connections() {
+        let (mut sink, ready) = BufferedSender::new(StartupQueue::new(8));
+        let (first_tx, first_rx) = tokio_mpsc::channel(8);
+        ready.send(first_tx).unwrap();
+        assert!(sink.send(vec![1]));
+
+        // The first connection goes away; audio is queued for the next one
+        drop(first_rx);
+        assert!(sink.send(vec![2]));
+
+        let (second_tx, mut second_rx) = tokio_mpsc::channel(8);
+        ready.send(second_tx).unwrap();
+        drop(ready);
+        assert!(sink.send(vec![3]));
+
+        let received: Vec<Vec<u8>> = std::iter::from_fn(|| second_rx.try_recv().ok()).collect();
+        assert_eq!(received, vec![vec![2], vec![3]]);
+    }
+}
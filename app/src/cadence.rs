@@ -0,0 +1,138 @@
+//! How fast keys are sent to the focused window.
+//!
+//! Native applications keep up with keys as fast as the kernel delivers
+//! them, while remote desktop clients and VM consoles drop or reorder keys
+//! that arrive in bursts. A cadence sets how many characters go out in one
+//! batch and how long to pause between batches.
+
+use anyhow::{bail, Context, Result};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cadence {
+    /// Characters (or backspaces) sent together before pausing
+    pub batch_size: usize,
+    /// Pause after each batch of characters
+    pub char_delay: Duration,
+    /// Pause after each batch of backspaces
+    pub backspace_delay: Duration,
+}
+
+impl Cadence {
+    /// For native applications: small bursts with a short pause, so the
+    /// compositor's event queue never overflows
+    pub const FAST: Cadence = Cadence {
+        batch_size: 16,
+        char_delay: Duration::from_millis(2),
+        backspace_delay: Duration::from_millis(2),
+    };
+
+    /// One character every 10 ms
+    pub const NORMAL: Cadence = Cadence {
+        batch_size: 1,
+        char_delay: Duration::from_millis(10),
+        backspace_delay: Duration::from_millis(5),
+    };
+
+    /// For remote desktops, VM consoles and other laggy targets
+    pub const SLOW: Cadence = Cadence {
+        batch_size: 1,
+        char_delay: Duration::from_millis(30),
+        backspace_delay: Duration::from_millis(20),
+    };
+
+    /// Parse `fast`, `normal`, `slow` or custom values as
+    /// `CHAR_MS[,BACKSPACE_MS[,BATCH_SIZE]]`
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "fast" => return Ok(Cadence::FAST),
+            "normal" => return Ok(Cadence::NORMAL),
+            "slow" => return Ok(Cadence::SLOW),
+            _ => {}
+        }
+
+        let numbers = value
+            .split(',')
+            .map(|part| part.trim().parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| {
+                format!(
+                    "Invalid cadence '{}': expected fast, normal, slow or CHAR_MS[,BACKSPACE_MS[,BATCH_SIZE]]",
+                    value
+                )
+            })?;
+        let (char_ms, backspace_ms, batch_size) = match numbers[..] {
+            [char_ms] => (char_ms, char_ms / 2, 1),
+            [char_ms, backspace_ms] => (char_ms, backspace_ms, 1),
+            [char_ms, backspace_ms, batch_size] => (char_ms, backspace_ms, batch_size),
+            _ => bail!("Invalid cadence '{}': too many values", value),
+        };
+        if batch_size == 0 {
+            bail!(
+                "Invalid cadence '{}': the batch size must be at least 1",
+                value
+            );
+        }
+
+        Ok(Cadence {
+            batch_size: batch_size as usize,
+            char_delay: Duration::from_millis(char_ms),
+            backspace_delay: Duration::from_millis(backspace_ms),
+        })
+    }
+
+    /// Pause if `sent` characters complete a batch
+    pub fn pace_chars(&self, sent: usize) {
+        if sent.is_multiple_of(self.batch_size.max(1)) {
+            std::thread::sleep(self.char_delay);
+        }
+    }
+
+    /// Pause if `sent` backspaces complete a batch
+    pub fn pace_backspaces(&self, sent: usize) {
+        if sent.is_multiple_of(self.batch_size.max(1)) {
+            std::thread::sleep(self.backspace_delay);
+        }
+    }
+}
+
+impl Default for Cadence {
+    fn default() -> Self {
+        Cadence::NORMAL
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_presets() {
+        assert_eq!(Cadence::parse("fast").unwrap(), Cadence::FAST);
+        assert_eq!(Cadence::parse(" Slow ").unwrap(), Cadence::SLOW);
+        assert_eq!(Cadence::default(), Cadence::NORMAL);
+    }
+
+    #[test]
+    fn test_parse_custom() {
+        assert_eq!(
+            Cadence::parse("20").unwrap(),
+            Cadence {
+                batch_size: 1,
+                char_delay: Duration::from_millis(20),
+                backspace_delay: Duration::from_millis(10),
+            }
+        );
+        assert_eq!(
+            Cadence::parse("0, 1, 32").unwrap(),
+            Cadence {
+                batch_size: 32,
+                char_delay: Duration::ZERO,
+                backspace_delay: Duration::from_millis(1),
+            }
+        );
+        assert!(Cadence::parse("1,2,0").is_err());
+        assert!(Cadence::parse("1,2,3,4").is_err());
+        assert!(Cadence::parse("brisk").is_err());
+    }
+}
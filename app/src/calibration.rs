@@ -0,0 +1,160 @@
+use anyhow::{bail, Result};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::audio_health::to_dbfs;
+use crate::audio_input::AudioInput;
+use crate::audio_pipeline;
+use crate::config::AudioProfile;
+
+/// Length of the blocks levels are measured over
+const BLOCK_MS: u32 = 50;
+
+/// Speech level the recommended gain aims for
+const TARGET_SPEECH_DBFS: f32 = -20.0;
+
+/// Limits on the recommended gain
+const MIN_GAIN: f32 = 0.25;
+const MAX_GAIN: f32 = 8.0;
+
+/// Speech must be at least this much louder than the noise floor to calibrate
+const MIN_SEPARATION_DB: f32 = 6.0;
+
+/// RMS level of each block of `samples`, in dBFS
+pub fn block_levels(samples: &[f32], sample_rate: u32) -> Vec<f32> {
+    let block_len = (sample_rate * BLOCK_MS / 1000).max(1) as usize;
+    samples
+        .chunks_exact(block_len)
+        .map(|block| {
+            let mean_square = block.iter().map(|&s| s * s).sum::<f32>() / block.len() as f32;
+            to_dbfs(mean_square.sqrt())
+        })
+        .collect()
+}
+
+fn percentile(levels: &[f32], fraction: f32) -> f32 {
+    let mut sorted = levels.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    sorted[((sorted.len() - 1) as f32 * fraction) as usize]
+}
+
+/// Outcome of measuring a device's background noise and speech level
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calibration {
+    pub noise_floor_dbfs: f32,
+    pub speech_level_dbfs: f32,
+    pub gain: f32,
+    pub vad_threshold_dbfs: f32,
+}
+
+impl Calibration {
+    /// Derive gain and VAD threshold from block levels recorded during a quiet
+    /// phase and a speaking phase.
+    ///
+    /// The noise floor is the median quiet level and the speech level the 90th
+    /// percentile of the speaking phase, so pauses between words do not drag
+    /// it down. The VAD threshold sits halfway between the two after gain.
+    pub fn from_levels(noise_levels: &[f32], speech_levels: &[f32]) -> Result<Self> {
+        if noise_levels.is_empty() || speech_levels.is_empty() {
+            bail!("Not enough audio was recorded to calibrate");
+        }
+
+        let noise_floor_dbfs = percentile(noise_levels, 0.5);
+        let speech_level_dbfs = percentile(speech_levels, 0.9);
+        if speech_level_dbfs - noise_floor_dbfs < MIN_SEPARATION_DB {
+            bail!(
+                "Speech ({:.1} dBFS) was not clearly louder than background noise ({:.1} dBFS); check that the microphone is unmuted and try again",
+                speech_level_dbfs,
+                noise_floor_dbfs
+            );
+        }
+
+        let gain_db = TARGET_SPEECH_DBFS - speech_level_dbfs;
+        let gain = 10f32.powf(gain_db / 20.0).clamp(MIN_GAIN, MAX_GAIN);
+        let applied_db = 20.0 * gain.log10();
+        let vad_threshold_dbfs = (noise_floor_dbfs + speech_level_dbfs) / 2.0 + applied_db;
+
+        Ok(Self {
+            noise_floor_dbfs,
+            speech_level_dbfs,
+            gain,
+            vad_threshold_dbfs,
+        })
+    }
+
+    pub fn to_profile(&self) -> AudioProfile {
+        AudioProfile {
+            gain: self.gain,
+            vad_threshold_dbfs: self.vad_threshold_dbfs,
+            noise_floor_dbfs: self.noise_floor_dbfs,
+            speech_level_dbfs: self.speech_level_dbfs,
+        }
+    }
+}
+
+/// Record `duration` of mono audio from an already opened input
+pub fn record_mono(audio_input: &mut AudioInput, duration: Duration) -> Result<Vec<f32>> {
+    let sample_rate = audio_input.get_sample_rate();
+    let channels = audio_input.get_channels();
+    let (mut producer, mut consumer) = audio_pipeline::capture_ring(sample_rate, channels);
+
+    audio_input.start_recording(move |data| {
+        producer.push_slice(data);
+    })?;
+
+    let mut scratch = vec![0.0f32; channels as usize * 1024];
+    let mut mono = Vec::new();
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        let count = consumer.pop_slice(&mut scratch);
+        audio_pipeline::downmix_to_mono(&scratch[..count], channels, &mut mono);
+        thread::sleep(Duration::from_millis(20));
+    }
+    audio_input.stop_recording();
+
+    Ok(mono)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_levels() {
+        let rate = 16_000;
+        let block = (rate * BLOCK_MS / 1000) as usize;
+        let mut samples = vec![0.0; block];
+        samples.extend(std::iter::repeat_n(0.5, block));
+
+        let levels = block_levels(&samples, rate);
+        assert_eq!(levels.len(), 2);
+        assert!(levels[0] < -150.0);
+        assert!((levels[1] - to_dbfs(0.5)).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_quiet_speaker_gets_boosted() {
+        let noise = vec![-70.0; 20];
+        let speech = vec![-38.0; 20];
+
+        let calibration = Calibration::from_levels(&noise, &speech).unwrap();
+        // -38 dBFS needs +18 dB to reach -20 dBFS
+        assert!((calibration.gain - 10f32.powf(18.0 / 20.0)).abs() < 0.01);
+        assert!((calibration.vad_threshold_dbfs - (-54.0 + 18.0)).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_gain_is_clamped() {
+        let calibration = Calibration::from_levels(&[-90.0], &[-70.0]).unwrap();
+        assert_eq!(calibration.gain, MAX_GAIN);
+
+        let calibration = Calibration::from_levels(&[-40.0], &[-1.0]).unwrap();
+        assert_eq!(calibration.gain, MIN_GAIN);
+    }
+
+    #[test]
+    fn test_rejects_speech_not_above_noise() {
+        assert!(Calibration::from_levels(&[-40.0; 10], &[-38.0; 10]).is_err());
+        assert!(Calibration::from_levels(&[], &[-20.0]).is_err());
+    }
+}
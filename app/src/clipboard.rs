@@ -0,0 +1,242 @@
+//! Clipboard access for the paste output mode.
+//!
+//! Typing long turns one key at a time is slow and depends on every
+//! character being reachable on the layout. Pasting puts the whole turn on
+//! the clipboard, sends the paste shortcut and then puts the user's previous
+//! clipboard contents back. The clipboard is driven through `wl-copy` /
+//! `wl-paste` on Wayland and `xclip` on X11.
+
+use anyhow::{bail, Context, Result};
+use std::env;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use crate::input_event::*;
+use crate::keymap::Keymap;
+use crate::output_backend::find_in_path;
+
+/// How long the pasted text stays on the clipboard before the previous
+/// contents are put back
+pub const RESTORE_DELAY: Duration = Duration::from_millis(250);
+
+/// Read and replace the text on the clipboard
+pub trait Clipboard: Send {
+    /// Current text contents, or `None` if the clipboard is empty or holds
+    /// something other than text
+    fn get(&mut self) -> Result<Option<String>>;
+    fn set(&mut self, text: &str) -> Result<()>;
+    fn clear(&mut self) -> Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardTool {
+    /// `wl-copy` / `wl-paste` from wl-clipboard
+    WlClipboard,
+    /// `xclip` on the CLIPBOARD selection
+    Xclip,
+}
+
+impl ClipboardTool {
+    /// Pick the tool for the current session, preferring Wayland
+    pub fn detect() -> Option<Self> {
+        Self::detect_from(|name| env::var(name).ok(), find_in_path)
+    }
+
+    pub fn detect_from<V, F>(var: V, installed: F) -> Option<Self>
+    where
+        V: Fn(&str) -> Option<String>,
+        F: Fn(&str) -> bool,
+    {
+        let set = |name: &str| var(name).is_some_and(|value| !value.is_empty());
+        if set("WAYLAND_DISPLAY") && installed("wl-copy") && installed("wl-paste") {
+            Some(ClipboardTool::WlClipboard)
+        } else if set("DISPLAY") && installed("xclip") {
+            Some(ClipboardTool::Xclip)
+        } else {
+            None
+        }
+    }
+}
+
+/// [`Clipboard`] backed by the command-line clipboard tools
+pub struct CommandClipboard {
+    tool: ClipboardTool,
+}
+
+impl CommandClipboard {
+    pub fn new(tool: ClipboardTool) -> Self {
+        Self { tool }
+    }
+
+    pub fn detect() -> Result<Self> {
+        match ClipboardTool::detect() {
+            Some(tool) => Ok(Self::new(tool)),
+            None => bail!("No clipboard tool found: install wl-clipboard (Wayland) or xclip (X11)"),
+        }
+    }
+
+    pub fn tool(&self) -> ClipboardTool {
+        self.tool
+    }
+
+    /// Run the copy command with `text` on stdin. Both tools fork to serve
+    /// the selection, so their output is discarded rather than waited on.
+    fn copy(&self, text: &str) -> Result<()> {
+        let mut command = match self.tool {
+            ClipboardTool::WlClipboard => Command::new("wl-copy"),
+            ClipboardTool::Xclip => {
+                let mut command = Command::new("xclip");
+                command.args(["-selection", "clipboard", "-in"]);
+                command
+            }
+        };
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("Failed to start the clipboard copy command")?;
+        child
+            .stdin
+            .take()
+            .context("Clipboard copy command has no stdin")?
+            .write_all(text.as_bytes())?;
+        let status = child.wait()?;
+        if !status.success() {
+            bail!("Clipboard copy command failed with {}", status);
+        }
+        Ok(())
+    }
+}
+
+impl Clipboard for CommandClipboard {
+    fn get(&mut self) -> Result<Option<String>> {
+        let output = match self.tool {
+            ClipboardTool::WlClipboard => Command::new("wl-paste")
+                .args(["--no-newline", "--type", "text"])
+                .output(),
+            ClipboardTool::Xclip => Command::new("xclip")
+                .args(["-selection", "clipboard", "-out", "-target", "UTF8_STRING"])
+                .output(),
+        }
+        .context("Failed to run the clipboard paste command")?;
+
+        // Both tools exit with an error when there is no text to hand out
+        if !output.status.success() {
+            return Ok(None);
+        }
+        Ok(String::from_utf8(output.stdout).ok())
+    }
+
+    fn set(&mut self, text: &str) -> Result<()> {
+        self.copy(text)
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        match self.tool {
+            ClipboardTool::WlClipboard => {
+                let status = Command::new("wl-copy").arg("--clear").status()?;
+                if !status.success() {
+                    bail!("wl-copy --clear failed with {}", status);
+                }
+                Ok(())
+            }
+            // xclip cannot drop the selection, so leave it empty instead
+            ClipboardTool::Xclip => self.copy(""),
+        }
+    }
+}
+
+/// Parse a shortcut such as `ctrl+v`, `ctrl+shift+v`, `shift+insert` or
+/// `ctrl+enter` into keycodes, in press order. Letter keys are looked up on `keymap`, so
+/// the shortcut follows the user's layout.
+pub fn parse_key_combo(value: &str, keymap: &Keymap) -> Result<Vec<u16>> {
+    let mut keycodes = Vec::new();
+    for part in value.split('+') {
+        let name = part.trim().to_ascii_lowercase();
+        let keycode = match name.as_str() {
+            "ctrl" | "control" => KEY_LEFTCTRL,
+            "shift" => KEY_LEFTSHIFT,
+            "alt" => KEY_LEFTALT,
+            "super" | "meta" | "win" => KEY_LEFTMETA,
+            "insert" | "ins" => KEY_INSERT,
+            "enter" | "return" => KEY_ENTER,
+            "tab" => KEY_TAB,
+            "escape" | "esc" => KEY_ESC,
+            "space" => KEY_SPACE,
+            "backspace" => KEY_BACKSPACE,
+            _ => {
+                let mut chars = name.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => {
+                        keymap
+                            .lookup(c)
+                            .with_context(|| format!("Key '{}' is not on the keyboard layout", c))?
+                            .keycode
+                    }
+                    _ => bail!("Unknown key '{}' in shortcut '{}'", part.trim(), value),
+                }
+            }
+        };
+        keycodes.push(keycode);
+    }
+    Ok(keycodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key_combo() {
+        let keymap = Keymap::us();
+        assert_eq!(
+            parse_key_combo("ctrl+v", &keymap).unwrap(),
+            vec![KEY_LEFTCTRL, KEY_V]
+        );
+        assert_eq!(
+            parse_key_combo("Ctrl + Shift + V", &keymap).unwrap(),
+            vec![KEY_LEFTCTRL, KEY_LEFTSHIFT, KEY_V]
+        );
+        assert_eq!(
+            parse_key_combo("shift+insert", &keymap).unwrap(),
+            vec![KEY_LEFTSHIFT, KEY_INSERT]
+        );
+        assert_eq!(
+            parse_key_combo("ctrl+Enter", &keymap).unwrap(),
+            vec![KEY_LEFTCTRL, KEY_ENTER]
+        );
+        assert_eq!(parse_key_combo("esc", &keymap).unwrap(), vec![KEY_ESC]);
+        assert!(parse_key_combo("ctrl+paste", &keymap).is_err());
+        assert!(parse_key_combo("ctrl+", &keymap).is_err());
+    }
+
+    #[test]
+    fn test_detect_tool() {
+        let all_installed = |_: &str| true;
+
+        let wayland = |name: &str| match name {
+            "WAYLAND_DISPLAY" => Some("wayland-0".to_string()),
+            "DISPLAY" => Some(":0".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            ClipboardTool::detect_from(wayland, all_installed),
+            Some(ClipboardTool::WlClipboard)
+        );
+        // XWayland sessions without wl-clipboard fall back to xclip
+        assert_eq!(
+            ClipboardTool::detect_from(wayland, |program: &str| program == "xclip"),
+            Some(ClipboardTool::Xclip)
+        );
+
+        let x11 = |name: &str| (name == "DISPLAY").then(|| ":0".to_string());
+        assert_eq!(
+            ClipboardTool::detect_from(x11, all_installed),
+            Some(ClipboardTool::Xclip)
+        );
+
+        assert_eq!(ClipboardTool::detect_from(|_| None, all_installed), None);
+    }
+}
@@ -0,0 +1,343 @@
+//! Dictating code.
+//!
+//! In code mode, casing commands join the words after them into one
+//! identifier ("camel case user id" types `userId`) and symbol commands type
+//! operators and brackets with code spacing ("user dot name equals equals
+//! none" types `user.name == none`). Punctuation added by the STT service is
+//! dropped, since it is never meant as code, and commands are recognized
+//! whatever their case. Other words keep the case they were transcribed in.
+//! Saying "code mode" or "prose mode" as a turn of its own switches modes.
+
+use regex::Regex;
+use std::sync::OnceLock;
+
+use crate::spoken_punctuation::ESCAPE_WORD;
+use crate::turn_format::capitalize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Casing {
+    /// userId
+    Camel,
+    /// UserId
+    Pascal,
+    /// user_id
+    Snake,
+    /// USER_ID
+    Constant,
+    /// user-id
+    Kebab,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    /// Join the following words into one identifier
+    Case(Casing),
+    Symbol {
+        text: &'static str,
+        /// Attach to the text before, without a space
+        join_before: bool,
+        /// Attach to the text after, without a space
+        join_after: bool,
+    },
+    /// A quote that opens towards the following word or closes onto the
+    /// previous one, alternating
+    Quote(&'static str),
+}
+
+const fn spaced(text: &'static str) -> Command {
+    Command::Symbol {
+        text,
+        join_before: false,
+        join_after: false,
+    }
+}
+
+const fn joined(text: &'static str) -> Command {
+    Command::Symbol {
+        text,
+        join_before: true,
+        join_after: true,
+    }
+}
+
+const fn closing(text: &'static str) -> Command {
+    Command::Symbol {
+        text,
+        join_before: true,
+        join_after: false,
+    }
+}
+
+const fn opening(text: &'static str) -> Command {
+    Command::Symbol {
+        text,
+        join_before: false,
+        join_after: true,
+    }
+}
+
+const COMMANDS: &[(&str, Command)] = &[
+    ("camel case", Command::Case(Casing::Camel)),
+    ("pascal case", Command::Case(Casing::Pascal)),
+    ("snake case", Command::Case(Casing::Snake)),
+    ("constant case", Command::Case(Casing::Constant)),
+    ("constant", Command::Case(Casing::Constant)),
+    ("kebab case", Command::Case(Casing::Kebab)),
+    ("dot", joined(".")),
+    ("arrow", joined("->")),
+    ("fat arrow", spaced("=>")),
+    ("equals", spaced("=")),
+    ("equals equals", spaced("==")),
+    ("double equals", spaced("==")),
+    ("triple equals", spaced("===")),
+    ("not equals", spaced("!=")),
+    ("plus", spaced("+")),
+    ("plus equals", spaced("+=")),
+    ("minus", spaced("-")),
+    ("minus equals", spaced("-=")),
+    ("times", spaced("*")),
+    ("star", spaced("*")),
+    ("slash", joined("/")),
+    ("backslash", joined("\\")),
+    ("less than", spaced("<")),
+    ("greater than", spaced(">")),
+    ("and and", spaced("&&")),
+    ("or or", spaced("||")),
+    ("pipe", spaced("|")),
+    ("ampersand", opening("&")),
+    ("bang", opening("!")),
+    ("hash", opening("#")),
+    ("at sign", opening("@")),
+    ("dollar sign", opening("$")),
+    ("underscore", joined("_")),
+    ("double colon", joined("::")),
+    ("colon", closing(":")),
+    ("semicolon", closing(";")),
+    ("comma", closing(",")),
+    ("open paren", joined("(")),
+    ("close paren", closing(")")),
+    ("open bracket", joined("[")),
+    ("close bracket", closing("]")),
+    ("open brace", opening("{")),
+    ("close brace", spaced("}")),
+    ("quote", Command::Quote("\"")),
+    ("single quote", Command::Quote("'")),
+    ("backtick", Command::Quote("`")),
+    ("space", joined(" ")),
+    ("new line", joined("\n")),
+];
+
+/// Switch between code and prose with a whole turn of "code mode" or
+/// "prose mode", returning whether code mode is wanted
+pub fn find_mode_command(text: &str) -> Option<bool> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern =
+        PATTERN.get_or_init(|| Regex::new(r"(?i)^\s*(code|prose)\s+mode[[:punct:]\s]*$").unwrap());
+    let captures = pattern.captures(text)?;
+    Some(captures[1].eq_ignore_ascii_case("code"))
+}
+
+#[derive(Debug, Clone)]
+pub struct CodeFormatter {
+    /// Commands split into lowercase words, most words first so that
+    /// "equals equals" wins over "equals"
+    commands: Vec<(Vec<&'static str>, Command)>,
+}
+
+impl Default for CodeFormatter {
+    fn default() -> Self {
+        let mut commands: Vec<(Vec<&'static str>, Command)> = COMMANDS
+            .iter()
+            .map(|(phrase, command)| (phrase.split(' ').collect(), *command))
+            .collect();
+        commands.sort_by_key(|(words, _)| std::cmp::Reverse(words.len()));
+        Self { commands }
+    }
+}
+
+impl CodeFormatter {
+    /// Interpret the commands in `text` and type everything else as is
+    pub fn apply(&self, text: &str) -> String {
+        let cleaned = strip_prose(text);
+        let words: Vec<&str> = cleaned.split_whitespace().collect();
+        let lower: Vec<String> = words.iter().map(|word| word.to_lowercase()).collect();
+
+        let mut out = Output::default();
+        let mut open_quotes: Vec<&str> = Vec::new();
+        let mut i = 0;
+        while i < words.len() {
+            if lower[i] == ESCAPE_WORD {
+                if let Some((length, _)) = self.command_at(&lower[i + 1..]) {
+                    for word in &words[i + 1..i + 1 + length] {
+                        out.push_word(word);
+                    }
+                    i += 1 + length;
+                    continue;
+                }
+            }
+
+            let Some((length, command)) = self.command_at(&lower[i..]) else {
+                out.push_word(words[i]);
+                i += 1;
+                continue;
+            };
+            i += length;
+            match command {
+                Command::Case(casing) => {
+                    // The identifier runs up to the next command
+                    let start = i;
+                    while i < words.len() && self.command_at(&lower[i..]).is_none() {
+                        i += 1;
+                    }
+                    if i > start {
+                        out.push_word(&apply_casing(casing, &lower[start..i]));
+                    }
+                }
+                Command::Symbol {
+                    text,
+                    join_before,
+                    join_after,
+                } => out.push_symbol(text, join_before, join_after),
+                Command::Quote(quote) => {
+                    if open_quotes.last() == Some(&quote) {
+                        open_quotes.pop();
+                        out.push_symbol(quote, true, false);
+                    } else {
+                        open_quotes.push(quote);
+                        out.push_symbol(quote, false, true);
+                    }
+                }
+            }
+        }
+        out.text
+    }
+
+    /// The command starting at the beginning of `words`, with its length
+    fn command_at(&self, words: &[String]) -> Option<(usize, Command)> {
+        self.commands.iter().find_map(|(phrase, command)| {
+            let matches = words.len() >= phrase.len()
+                && phrase
+                    .iter()
+                    .zip(words)
+                    .all(|(expected, word)| expected == word);
+            matches.then_some((phrase.len(), *command))
+        })
+    }
+}
+
+/// Text built from words and symbols with code spacing
+#[derive(Default)]
+struct Output {
+    text: String,
+    /// The next piece attaches without a space
+    glue: bool,
+}
+
+impl Output {
+    fn push_word(&mut self, word: &str) {
+        if !self.text.is_empty() && !self.glue {
+            self.text.push(' ');
+        }
+        self.text.push_str(word);
+        self.glue = false;
+    }
+
+    fn push_symbol(&mut self, symbol: &str, join_before: bool, join_after: bool) {
+        if join_before {
+            self.text.truncate(self.text.trim_end_matches(' ').len());
+        } else if !self.text.is_empty() && !self.glue {
+            self.text.push(' ');
+        }
+        self.text.push_str(symbol);
+        self.glue = join_after;
+    }
+}
+
+/// Drop the punctuation the STT service adds to prose, keeping dots inside
+/// numbers and names such as "3.14"
+fn strip_prose(text: &str) -> String {
+    static PUNCTUATION: OnceLock<Regex> = OnceLock::new();
+    let punctuation = PUNCTUATION.get_or_init(|| Regex::new(r"[.,?!;:]+(\s|$)").unwrap());
+    punctuation.replace_all(text, "$1").into_owned()
+}
+
+fn apply_casing(casing: Casing, words: &[String]) -> String {
+    let words: Vec<String> = words
+        .iter()
+        .map(|word| word.chars().filter(|c| c.is_alphanumeric()).collect())
+        .filter(|word: &String| !word.is_empty())
+        .collect();
+    match casing {
+        Casing::Camel => words
+            .iter()
+            .enumerate()
+            .map(|(i, word)| {
+                if i == 0 {
+                    word.clone()
+                } else {
+                    capitalize(word)
+                }
+            })
+            .collect(),
+        Casing::Pascal => words.iter().map(|word| capitalize(word)).collect(),
+        Casing::Snake => words.join("_"),
+        Casing::Constant => words.join("_").to_uppercase(),
+        Casing::Kebab => words.join("-"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(text: &str) -> String {
+        CodeFormatter::default().apply(text)
+    }
+
+    #[test]
+    fn test_casing_commands() {
+        assert_eq!(code("camel case user id"), "userId");
+        assert_eq!(code("snake case max retries"), "max_retries");
+        assert_eq!(code("constant api url"), "API_URL");
+        assert_eq!(code("pascal case http client"), "HttpClient");
+        assert_eq!(code("kebab case main menu"), "main-menu");
+        // An identifier ends at the next command
+        assert_eq!(
+            code("constant max retries equals five"),
+            "MAX_RETRIES = five"
+        );
+    }
+
+    #[test]
+    fn test_symbols() {
+        assert_eq!(code("user dot name"), "user.name");
+        assert_eq!(code("self arrow next"), "self->next");
+        assert_eq!(code("a equals equals b"), "a == b");
+        assert_eq!(code("x fat arrow x"), "x => x");
+        assert_eq!(
+            code("print open paren quote hello quote close paren"),
+            "print(\"hello\")"
+        );
+        assert_eq!(code("foo open paren a comma b close paren"), "foo(a, b)");
+        assert_eq!(code("literal dot"), "dot");
+    }
+
+    #[test]
+    fn test_drops_prose_formatting() {
+        assert_eq!(
+            code("Camel case user ID equals snake case user id."),
+            "userId = user_id"
+        );
+        assert_eq!(code("Dot total."), ".total");
+        // Only commands ignore case, so a capitalized identifier survives
+        assert_eq!(code("Foo dot bar."), "Foo.bar");
+        assert_eq!(code("pi equals 3.14"), "pi = 3.14");
+    }
+
+    #[test]
+    fn test_find_mode_command() {
+        assert_eq!(find_mode_command("Code mode."), Some(true));
+        assert_eq!(find_mode_command(" prose  mode"), Some(false));
+        assert_eq!(find_mode_command("switch to code mode"), None);
+    }
+}
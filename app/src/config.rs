@@ -0,0 +1,286 @@
+//! Persistent settings shared by the CLI and the GUI.
+//!
+//! Both binaries read and write the same `config.json`, so every field added
+//! here must have a serde default to keep older files loading.
+
+use anyhow::{Context, Result};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    pub api_key: String,
+    pub hotkey_code: String,
+    pub project_id: String,
+    #[serde(default = "default_health_beep")]
+    pub health_beep: bool,
+    /// Calibrated audio settings keyed by input device name
+    #[serde(default)]
+    pub audio_profiles: BTreeMap<String, AudioProfile>,
+    /// XKB layout to type with, e.g. "de"; detected from the system if unset
+    #[serde(default)]
+    pub keyboard_layout: Option<String>,
+    /// XKB layout variant, e.g. "nodeadkeys"
+    #[serde(default)]
+    pub keyboard_variant: Option<String>,
+    /// Replace characters the keyboard cannot type with ASCII equivalents
+    #[serde(default = "default_transliterate")]
+    pub transliterate: bool,
+    /// Custom replacements applied to every typed character, e.g. "€" -> "EUR"
+    #[serde(default)]
+    pub transliterations: BTreeMap<String, String>,
+    /// How finished text reaches the focused window: "type" or "paste"
+    #[serde(default = "default_output_mode")]
+    pub output_mode: String,
+    /// Shortcut sent to paste in paste mode, e.g. "ctrl+shift+v" for terminals
+    #[serde(default = "default_paste_combo")]
+    pub paste_combo: String,
+    /// Typing speed: "fast", "normal", "slow" or "CHAR_MS,BACKSPACE_MS,BATCH_SIZE"
+    #[serde(default = "default_cadence")]
+    pub cadence: String,
+    /// Type symbols for spoken commands such as "comma" or "new line"
+    #[serde(default)]
+    pub spoken_punctuation: bool,
+    /// Extra spoken commands, e.g. "arrow" -> "->"; these override built-in ones
+    #[serde(default)]
+    pub punctuation_commands: BTreeMap<String, String>,
+    /// Phrases that end a turn with a shortcut when --voice-enter is on,
+    /// e.g. "send it" -> "ctrl+enter"
+    #[serde(default = "default_trigger_phrases")]
+    pub trigger_phrases: BTreeMap<String, String>,
+    /// Space and capitalize each turn to follow on from the previous one
+    #[serde(default = "default_format_turns")]
+    pub format_turns: bool,
+    /// Retract text with "scratch that", "never mind" and "cancel"
+    #[serde(default)]
+    pub voice_undo: bool,
+    /// Most characters a single voice undo may erase
+    #[serde(default = "default_max_undo_chars")]
+    pub max_undo_chars: usize,
+    /// Fixes for words the STT service keeps getting wrong, applied to every
+    /// transcript in order
+    #[serde(default)]
+    pub replacements: Vec<ReplacementRule>,
+    /// Text typed for a turn of "insert <name>", e.g. "signature" ->
+    /// "Best,\nDaniel"; more can be kept as files in `snippets/`
+    #[serde(default)]
+    pub snippets: BTreeMap<String, String>,
+    /// Type spoken numbers, dates and amounts in written form, e.g.
+    /// "twenty dollars" as "$20"
+    #[serde(default)]
+    pub inverse_text_normalization: bool,
+    /// Start in code mode, where "camel case user id" types `userId`
+    #[serde(default)]
+    pub code_mode: bool,
+    /// Named sets of text settings, picked with `profile` or --profile
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Profile applied on startup
+    #[serde(default)]
+    pub profile: Option<String>,
+}
+
+fn default_health_beep() -> bool {
+    true
+}
+
+fn default_transliterate() -> bool {
+    true
+}
+
+fn default_output_mode() -> String {
+    "type".to_string()
+}
+
+fn default_paste_combo() -> String {
+    "ctrl+v".to_string()
+}
+
+fn default_cadence() -> String {
+    "normal".to_string()
+}
+
+fn default_format_turns() -> bool {
+    true
+}
+
+/// Default for the most characters a single undo may erase
+pub const DEFAULT_MAX_UNDO_CHARS: usize = 500;
+
+fn default_max_undo_chars() -> usize {
+    DEFAULT_MAX_UNDO_CHARS
+}
+
+fn default_trigger_phrases() -> BTreeMap<String, String> {
+    BTreeMap::from([("enter".to_string(), "enter".to_string())])
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            api_key: String::new(),
+            hotkey_code: "F13".to_string(),
+            project_id: String::new(),
+            health_beep: default_health_beep(),
+            audio_profiles: BTreeMap::new(),
+            keyboard_layout: None,
+            keyboard_variant: None,
+            transliterate: default_transliterate(),
+            transliterations: BTreeMap::new(),
+            output_mode: default_output_mode(),
+            paste_combo: default_paste_combo(),
+            cadence: default_cadence(),
+            spoken_punctuation: false,
+            punctuation_commands: BTreeMap::new(),
+            trigger_phrases: default_trigger_phrases(),
+            format_turns: default_format_turns(),
+            voice_undo: false,
+            max_undo_chars: default_max_undo_chars(),
+            replacements: Vec::new(),
+            snippets: BTreeMap::new(),
+            inverse_text_normalization: false,
+            code_mode: false,
+            profiles: BTreeMap::new(),
+            profile: None,
+        }
+    }
+}
+
+/// One entry of `replacements`, e.g. "cube control" -> "kubectl"
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplacementRule {
+    /// Text to look for, or a regular expression if `regex` is set
+    pub pattern: String,
+    /// Text to put in its place; regex rules may refer to groups as `$1`
+    pub replacement: String,
+    #[serde(default)]
+    pub regex: bool,
+    #[serde(default)]
+    pub case_sensitive: bool,
+    /// Only match whole words, so "post" does not match inside "poster"
+    #[serde(default = "default_whole_word")]
+    pub whole_word: bool,
+    /// Follow the case of the matched text: "Cube control" at the start of
+    /// a sentence becomes "Kubectl"
+    #[serde(default)]
+    pub preserve_case: bool,
+}
+
+fn default_whole_word() -> bool {
+    true
+}
+
+/// Text settings that override the top-level ones while a profile is in
+/// use, e.g. numbers as digits for notes but not for prose
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(default)]
+    pub spoken_punctuation: Option<bool>,
+    #[serde(default)]
+    pub format_turns: Option<bool>,
+    #[serde(default)]
+    pub voice_undo: Option<bool>,
+    #[serde(default)]
+    pub inverse_text_normalization: Option<bool>,
+    #[serde(default)]
+    pub code_mode: Option<bool>,
+}
+
+/// Per-device result of `--calibrate`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AudioProfile {
+    /// Linear gain applied to captured samples before they are sent
+    pub gain: f32,
+    /// Level separating speech from background, in dBFS after gain
+    pub vad_threshold_dbfs: f32,
+    /// Measured background level, in dBFS before gain
+    pub noise_floor_dbfs: f32,
+    /// Measured speech level, in dBFS before gain
+    pub speech_level_dbfs: f32,
+}
+
+impl Config {
+    /// Directory holding `config.json` and other user files
+    pub fn config_dir() -> Result<PathBuf> {
+        let project_dirs = ProjectDirs::from("com", "deepgram", "voice-keyboard")
+            .context("Failed to get project directories")?;
+        Ok(project_dirs.config_dir().to_path_buf())
+    }
+
+    pub fn config_path() -> Result<PathBuf> {
+        Ok(Self::config_dir()?.join("config.json"))
+    }
+
+    pub fn load() -> Result<Self> {
+        Self::load_from(&Self::config_path()?)
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        if path.exists() {
+            let contents = fs::read_to_string(path)?;
+            Ok(serde_json::from_str(&contents)?)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::config_path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(&path, contents)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loads_config_without_new_fields() {
+        let json = r#"{"api_key":"k","hotkey_code":"F13","project_id":"p"}"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert!(config.health_beep);
+        assert!(config.audio_profiles.is_empty());
+        assert!(config.transliterate);
+        assert_eq!(config.output_mode, "type");
+        assert_eq!(config.paste_combo, "ctrl+v");
+        assert_eq!(config.cadence, "normal");
+        assert!(!config.spoken_punctuation);
+        assert!(config.punctuation_commands.is_empty());
+        assert_eq!(config.trigger_phrases["enter"], "enter");
+        assert!(config.format_turns);
+        assert!(!config.voice_undo);
+        assert_eq!(config.max_undo_chars, 500);
+        assert!(config.replacements.is_empty());
+        assert!(config.snippets.is_empty());
+        assert!(!config.inverse_text_normalization);
+        assert!(!config.code_mode);
+        assert!(config.profiles.is_empty());
+        assert_eq!(config.profile, None);
+    }
+
+    #[test]
+    fn test_audio_profile_roundtrip() {
+        let mut config = Config::default();
+        config.audio_profiles.insert(
+            "USB Mic".to_string(),
+            AudioProfile {
+                gain: 2.0,
+                vad_threshold_dbfs: -42.0,
+                noise_floor_dbfs: -65.0,
+                speech_level_dbfs: -30.0,
+            },
+        );
+
+        let json = serde_json::to_string(&config).unwrap();
+        let parsed: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.audio_profiles, config.audio_profiles);
+    }
+}
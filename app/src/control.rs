@@ -0,0 +1,106 @@
+//! Runtime control of a long-running session.
+//!
+//! Commands arrive as lines on stdin (`mute`, `unmute`, `toggle`, `quit`) or
+//! as signals: SIGUSR1 toggles the microphone, which makes it easy to bind a
+//! hotkey to `pkill -USR1 voice-keyboard`.
+
+use anyhow::{Context, Result};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    Mute,
+    Unmute,
+    Toggle,
+    Quit,
+}
+
+impl Control {
+    pub fn parse(line: &str) -> Option<Self> {
+        match line.trim().to_ascii_lowercase().as_str() {
+            "mute" => Some(Control::Mute),
+            "unmute" => Some(Control::Unmute),
+            "toggle" => Some(Control::Toggle),
+            "quit" | "exit" => Some(Control::Quit),
+            _ => None,
+        }
+    }
+
+    /// Muted state after applying this command to `muted`
+    pub fn apply(&self, muted: bool) -> bool {
+        match self {
+            Control::Mute => true,
+            Control::Unmute => false,
+            Control::Toggle => !muted,
+            Control::Quit => muted,
+        }
+    }
+}
+
+/// Forward commands from stdin and SIGUSR1 to `tx`
+pub fn spawn_listeners(tx: mpsc::UnboundedSender<Control>) -> Result<()> {
+    let mut usr1 = signal(SignalKind::user_defined1()).context("Failed to listen for SIGUSR1")?;
+    let signal_tx = tx.clone();
+    tokio::spawn(async move {
+        while usr1.recv().await.is_some() {
+            if signal_tx.send(Control::Toggle).is_err() {
+                break;
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        loop {
+            match lines.next_line().await {
+                Ok(Some(line)) => match Control::parse(&line) {
+                    Some(control) => {
+                        if tx.send(control).is_err() {
+                            break;
+                        }
+                    }
+                    None if line.trim().is_empty() => {}
+                    None => warn!("Unknown command on stdin: {:?}", line.trim()),
+                },
+                Ok(None) => {
+                    // stdin is often /dev/null when launched from the GUI or
+                    // a service manager; signals still work in that case
+                    debug!("stdin closed; only signals can control the session");
+                    break;
+                }
+                Err(e) => {
+                    warn!("Failed to read stdin: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(Control::parse("mute"), Some(Control::Mute));
+        assert_eq!(Control::parse("  Unmute\n"), Some(Control::Unmute));
+        assert_eq!(Control::parse("TOGGLE"), Some(Control::Toggle));
+        assert_eq!(Control::parse("exit"), Some(Control::Quit));
+        assert_eq!(Control::parse("louder"), None);
+    }
+
+    #[test]
+    fn test_apply() {
+        assert!(Control::Mute.apply(false));
+        assert!(!Control::Unmute.apply(true));
+        assert!(Control::Toggle.apply(false));
+        assert!(!Control::Toggle.apply(true));
+        assert!(Control::Quit.apply(true));
+    }
+}
@@ -1,18 +1,22 @@
-use anyhow::{Context, Result};
-use directories::ProjectDirs;
+// Only the warning beep is used by the GUI
+#[allow(dead_code)]
+mod audio_health;
+mod config;
+mod status;
+
+use anyhow::Result;
 use global_hotkey::{
     hotkey::{Code, HotKey},
     GlobalHotKeyEvent, GlobalHotKeyManager,
 };
 use iced::{
-    widget::{button, column, container, text, text_input},
-    window, Element, Length, Task, Theme,
+    widget::{button, checkbox, column, container, text, text_input},
+    window, Element, Length, Subscription, Task, Theme,
 };
 use rodio::{source::SineWave, OutputStream, Sink, Source};
 use serde::{Deserialize, Serialize};
-use std::fs;
-use std::path::PathBuf;
-use std::process::{Child, Command};
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 // Tray icon disabled - requires GTK which is incompatible with KDE/Wayland
@@ -20,14 +24,9 @@ use std::time::Duration;
 //     menu::{Menu, MenuItem},
 //     TrayIcon, TrayIconBuilder,
 // };
+use config::Config;
 use reqwest::Client;
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Config {
-    api_key: String,
-    hotkey_code: String,
-    project_id: String,
-}
+use status::{StatusLevel, StatusLine};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct BillingBalance {
@@ -41,49 +40,14 @@ struct BillingResponse {
     balances: Vec<BillingBalance>,
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            api_key: String::new(),
-            hotkey_code: "F13".to_string(),
-            project_id: String::new(),
-        }
-    }
-}
-
-impl Config {
-    fn config_path() -> Result<PathBuf> {
-        let project_dirs = ProjectDirs::from("com", "deepgram", "voice-keyboard")
-            .context("Failed to get project directories")?;
-        let config_dir = project_dirs.config_dir();
-        fs::create_dir_all(config_dir)?;
-        Ok(config_dir.join("config.json"))
-    }
-
-    fn load() -> Result<Self> {
-        let path = Self::config_path()?;
-        if path.exists() {
-            let contents = fs::read_to_string(&path)?;
-            Ok(serde_json::from_str(&contents)?)
-        } else {
-            Ok(Self::default())
-        }
-    }
-
-    fn save(&self) -> Result<()> {
-        let path = Self::config_path()?;
-        let contents = serde_json::to_string_pretty(self)?;
-        fs::write(&path, contents)?;
-        Ok(())
-    }
-}
-
 #[derive(Debug, Clone)]
 enum Message {
     ApiKeyChanged(String),
     HotkeyChanged(String),
     ProjectIdChanged(String),
+    HealthBeepToggled(bool),
     SaveConfig,
+    PollStatus,
     ToggleDictation,
     CheckBalance,
     BalanceReceived(Result<BillingResponse, String>),
@@ -101,6 +65,8 @@ struct VoiceKeyboardGui {
     status_message: String,
     balance_info: String,
     voice_keyboard_process: Arc<Mutex<Option<Child>>>,
+    // Status lines reported by the voice-keyboard process, drained on PollStatus
+    pending_status: Arc<Mutex<Vec<StatusLine>>>,
     _hotkey_manager: GlobalHotKeyManager,
     _audio_output_stream: OutputStream,
     audio_sink: Arc<Mutex<Sink>>,
@@ -159,6 +125,7 @@ impl VoiceKeyboardGui {
             status_message: "Ready".to_string(),
             balance_info: "Click 'Check Balance' to view billing info".to_string(),
             voice_keyboard_process: Arc::new(Mutex::new(None)),
+            pending_status: Arc::new(Mutex::new(Vec::new())),
             _hotkey_manager: hotkey_manager,
             _audio_output_stream: stream,
             audio_sink: Arc::new(Mutex::new(sink)),
@@ -169,6 +136,7 @@ impl VoiceKeyboardGui {
         // Start hotkey listener
         let process_clone = gui.voice_keyboard_process.clone();
         let audio_sink_clone = gui.audio_sink.clone();
+        let pending_status_clone = gui.pending_status.clone();
         std::thread::spawn(move || {
             let receiver = GlobalHotKeyEvent::receiver();
             loop {
@@ -255,8 +223,10 @@ impl VoiceKeyboardGui {
                                 }
 
                                 cmd.arg(&exe_path).arg("--test-stt");
+                                cmd.stderr(Stdio::piped());
 
-                                if let Ok(child) = cmd.spawn() {
+                                if let Ok(mut child) = cmd.spawn() {
+                                    forward_status_lines(&mut child, pending_status_clone.clone());
                                     *process_lock = Some(child);
                                 }
                             }
@@ -339,10 +309,12 @@ impl VoiceKeyboardGui {
         }
 
         cmd.arg(&exe_path).arg("--test-stt");
+        cmd.stderr(Stdio::piped());
 
         match cmd.spawn()
         {
-            Ok(child) => {
+            Ok(mut child) => {
+                forward_status_lines(&mut child, self.pending_status.clone());
                 *self.voice_keyboard_process.lock().unwrap() = Some(child);
                 self.is_recording = true;
                 self.status_message = "Recording...".to_string();
@@ -388,12 +360,41 @@ impl VoiceKeyboardGui {
             Message::ProjectIdChanged(value) => {
                 self.project_id_input = value;
             }
+            Message::HealthBeepToggled(value) => {
+                self.config.health_beep = value;
+            }
+            Message::PollStatus => {
+                let lines: Vec<StatusLine> =
+                    std::mem::take(&mut *self.pending_status.lock().unwrap());
+                for line in lines {
+                    match line.level {
+                        StatusLevel::Warning => {
+                            self.status_message = format!("Warning: {}", line.message);
+                            if self.config.health_beep {
+                                audio_health::play_warning_beep();
+                            }
+                        }
+                        StatusLevel::Info => {
+                            self.status_message = if self.is_recording {
+                                "Recording...".to_string()
+                            } else {
+                                line.message
+                            };
+                        }
+                    }
+                }
+            }
             Message::SaveConfig => {
-                self.config.api_key = self.api_key_input.clone();
-                self.config.hotkey_code = self.hotkey_input.clone();
-                self.config.project_id = self.project_id_input.clone();
-                match self.config.save() {
+                // Start from the file so settings written since it was
+                // loaded, such as a calibration from the CLI, are kept
+                let mut config = Config::load().unwrap_or_else(|_| self.config.clone());
+                config.api_key = self.api_key_input.clone();
+                config.hotkey_code = self.hotkey_input.clone();
+                config.project_id = self.project_id_input.clone();
+                config.health_beep = self.config.health_beep;
+                match config.save() {
                     Ok(_) => {
+                        self.config = config;
                         self.status_message = "Configuration saved!".to_string();
                     }
                     Err(e) => {
@@ -467,6 +468,11 @@ impl VoiceKeyboardGui {
         Task::none()
     }
 
+    fn subscription(&self) -> Subscription<Message> {
+        // Pick up status lines from the voice-keyboard process
+        iced::time::every(Duration::from_millis(250)).map(|_| Message::PollStatus)
+    }
+
     fn view(&self) -> Element<Message> {
         let title = text("Voice Keyboard Control").size(32);
 
@@ -488,6 +494,9 @@ impl VoiceKeyboardGui {
             .padding(10)
             .size(20);
 
+        let health_beep_toggle = checkbox("Beep on microphone warnings", self.config.health_beep)
+            .on_toggle(Message::HealthBeepToggled);
+
         let save_button = button("Save Configuration")
             .on_press(Message::SaveConfig)
             .padding(10);
@@ -539,6 +548,8 @@ impl VoiceKeyboardGui {
             hotkey_label,
             hotkey_field,
             text("").size(10),
+            health_beep_toggle,
+            text("").size(10),
             save_button,
             text("").size(20),
             toggle_button,
@@ -563,8 +574,25 @@ impl VoiceKeyboardGui {
     }
 }
 
+/// Read the child's stderr on a background thread, queueing status lines for
+/// the GUI and passing everything else through to our own stderr
+fn forward_status_lines(child: &mut Child, pending: Arc<Mutex<Vec<StatusLine>>>) {
+    let Some(stderr) = child.stderr.take() else {
+        return;
+    };
+    std::thread::spawn(move || {
+        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+            match line.parse::<StatusLine>() {
+                Ok(status) => pending.lock().unwrap().push(status),
+                Err(_) => eprintln!("{}", line),
+            }
+        }
+    });
+}
+
 fn main() -> iced::Result {
     iced::application("Voice Keyboard", VoiceKeyboardGui::update, VoiceKeyboardGui::view)
+        .subscription(VoiceKeyboardGui::subscription)
         .window_size((500.0, 600.0))
         .centered()
         .run_with(VoiceKeyboardGui::new)
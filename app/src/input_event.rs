@@ -103,6 +103,14 @@ pub const KEY_RIGHTSHIFT: u16 = 54;
 pub const KEY_LEFTALT: u16 = 56;
 pub const KEY_SPACE: u16 = 57;
 pub const KEY_CAPSLOCK: u16 = 58;
+pub const KEY_SCROLLLOCK: u16 = 70;
+pub const KEY_RIGHTCTRL: u16 = 97;
+pub const KEY_RIGHTALT: u16 = 100;
+pub const KEY_INSERT: u16 = 110;
+pub const KEY_PAUSE: u16 = 119;
+pub const KEY_LEFTMETA: u16 = 125;
+pub const KEY_RIGHTMETA: u16 = 126;
+pub const KEY_COMPOSE: u16 = 127;
 
 // Function keys
 pub const KEY_F1: u16 = 59;
@@ -129,6 +137,17 @@ pub const UI_SET_KEYBIT: libc::c_ulong = 0x40045565;
 pub const UI_DEV_CREATE: libc::c_ulong = 0x5501;
 pub const UI_DEV_DESTROY: libc::c_ulong = 0x5502;
 
+// First uinput version supporting UI_DEV_SETUP (Linux 4.5)
+pub const UINPUT_VERSION_DEV_SETUP: u32 = 5;
+
+// Bus types from linux/input.h
+pub const BUS_PCI: u16 = 0x01;
+pub const BUS_USB: u16 = 0x03;
+pub const BUS_BLUETOOTH: u16 = 0x05;
+pub const BUS_VIRTUAL: u16 = 0x06;
+pub const BUS_I8042: u16 = 0x11;
+pub const BUS_I2C: u16 = 0x18;
+
 // uinput device setup structure
 #[repr(C)]
 #[derive(Debug)]
@@ -139,14 +158,9 @@ pub struct UInputSetup {
 }
 
 impl UInputSetup {
-    pub fn new(name: &str) -> Self {
+    pub fn new(name: &str, id: InputId) -> Self {
         let mut setup = Self {
-            id: InputId {
-                bustype: 0x03, // USB
-                vendor: 0x1234,
-                product: 0x5678,
-                version: 1,
-            },
+            id,
             name: [0; UINPUT_MAX_NAME_SIZE],
             ff_effects_max: 0,
         };
@@ -199,7 +213,9 @@ pub fn char_to_keycode(c: char) -> Option<(u16, bool)> {
         'y' | 'Y' => Some((KEY_Y, c.is_uppercase())),
         'z' | 'Z' => Some((KEY_Z, c.is_uppercase())),
         // Numbers and special characters
-        '0'..='9' => Some((KEY_0 + (c as u16 - '0' as u16), false)),
+        // KEY_1..KEY_9 are consecutive but KEY_0 comes after KEY_9
+        '0' => Some((KEY_0, false)),
+        '1'..='9' => Some((KEY_1 + (c as u16 - '1' as u16), false)),
         ' ' => Some((KEY_SPACE, false)),
         '\n' => Some((KEY_ENTER, false)),
         '\t' => Some((KEY_TAB, false)),
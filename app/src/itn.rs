@@ -0,0 +1,698 @@
+//! Inverse text normalization: spoken numbers to written form.
+//!
+//! Rewrites English number words the way they are usually written, e.g.
+//! "twenty three dollars fifty on march fourth" becomes "$23.50 on March 4".
+//! Handles cardinals, ordinals, decimals, currency, percentages, times,
+//! dates and phone numbers. Numbers below ten stay as words unless they are
+//! part of one of those forms, so "one of them" is left alone.
+
+use regex::Regex;
+use std::sync::OnceLock;
+
+use crate::turn_format::capitalize;
+
+const MONTHS: &[&str] = &[
+    "january",
+    "february",
+    "march",
+    "april",
+    "may",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+];
+
+/// Role of a number word, deciding which words may follow it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// zero to nine
+    Unit,
+    /// ten to nineteen
+    Teen,
+    /// twenty, thirty, ... ninety
+    Tens,
+    Hundred,
+    /// thousand, million, billion
+    Scale,
+    And,
+}
+
+fn cardinal_word(word: &str) -> Option<(u64, Kind)> {
+    Some(match word {
+        "zero" => (0, Kind::Unit),
+        "one" => (1, Kind::Unit),
+        "two" => (2, Kind::Unit),
+        "three" => (3, Kind::Unit),
+        "four" => (4, Kind::Unit),
+        "five" => (5, Kind::Unit),
+        "six" => (6, Kind::Unit),
+        "seven" => (7, Kind::Unit),
+        "eight" => (8, Kind::Unit),
+        "nine" => (9, Kind::Unit),
+        "ten" => (10, Kind::Teen),
+        "eleven" => (11, Kind::Teen),
+        "twelve" => (12, Kind::Teen),
+        "thirteen" => (13, Kind::Teen),
+        "fourteen" => (14, Kind::Teen),
+        "fifteen" => (15, Kind::Teen),
+        "sixteen" => (16, Kind::Teen),
+        "seventeen" => (17, Kind::Teen),
+        "eighteen" => (18, Kind::Teen),
+        "nineteen" => (19, Kind::Teen),
+        "twenty" => (20, Kind::Tens),
+        "thirty" => (30, Kind::Tens),
+        "forty" => (40, Kind::Tens),
+        "fifty" => (50, Kind::Tens),
+        "sixty" => (60, Kind::Tens),
+        "seventy" => (70, Kind::Tens),
+        "eighty" => (80, Kind::Tens),
+        "ninety" => (90, Kind::Tens),
+        "hundred" => (100, Kind::Hundred),
+        "thousand" => (1_000, Kind::Scale),
+        "million" => (1_000_000, Kind::Scale),
+        "billion" => (1_000_000_000, Kind::Scale),
+        _ => return None,
+    })
+}
+
+fn ordinal_word(word: &str) -> Option<(u64, Kind)> {
+    let cardinal = match word {
+        "first" => "one",
+        "second" => "two",
+        "third" => "three",
+        "fifth" => "five",
+        "eighth" => "eight",
+        "ninth" => "nine",
+        "twelfth" => "twelve",
+        _ => match word.strip_suffix("ieth") {
+            Some(tens) => return cardinal_word(&format!("{}y", tens)),
+            None => word.strip_suffix("th")?,
+        },
+    };
+    cardinal_word(cardinal)
+}
+
+/// Single digits as read out in phone numbers, where "oh" means zero
+fn digit_word(word: &str) -> Option<u64> {
+    match word {
+        "oh" => Some(0),
+        _ => match cardinal_word(word)? {
+            (digit, Kind::Unit) => Some(digit),
+            _ => None,
+        },
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Number {
+    value: u64,
+    /// Digits after "point", e.g. "14" for "three point one four"
+    fraction: Option<String>,
+    ordinal: bool,
+    /// Words the number was spoken as
+    words: usize,
+}
+
+impl Number {
+    fn digits(&self) -> String {
+        let mut out = group_thousands(self.value);
+        if let Some(fraction) = &self.fraction {
+            out.push('.');
+            out.push_str(fraction);
+        }
+        if self.ordinal {
+            out.push_str(ordinal_suffix(self.value));
+        }
+        out
+    }
+}
+
+/// Parse the longest number at the start of `words`
+fn parse_number(words: &[&str]) -> Option<Number> {
+    let mut total = 0;
+    let mut current = 0;
+    let mut last: Option<Kind> = None;
+    let mut ordinal = false;
+    let mut n = 0;
+    while let Some(&word) = words.get(n) {
+        if word == "and" {
+            let next_is_small = words.get(n + 1).is_some_and(|next| {
+                cardinal_word(next)
+                    .or_else(|| ordinal_word(next))
+                    .is_some_and(|(_, kind)| matches!(kind, Kind::Unit | Kind::Teen | Kind::Tens))
+            });
+            if matches!(last, Some(Kind::Hundred | Kind::Scale)) && next_is_small {
+                last = Some(Kind::And);
+                n += 1;
+                continue;
+            }
+            break;
+        }
+
+        let (value, kind, is_ordinal) = match cardinal_word(word) {
+            Some((value, kind)) => (value, kind, false),
+            None => match ordinal_word(word) {
+                Some((value, kind)) => (value, kind, true),
+                None => break,
+            },
+        };
+        let follows = match kind {
+            Kind::Unit if last == Some(Kind::Tens) => value > 0,
+            Kind::Unit | Kind::Teen | Kind::Tens => {
+                matches!(last, None | Some(Kind::Hundred | Kind::Scale | Kind::And))
+            }
+            Kind::Hundred => matches!(last, Some(Kind::Unit | Kind::Teen)) && current < 100,
+            Kind::Scale => current > 0,
+            Kind::And => false,
+        };
+        if !follows {
+            break;
+        }
+
+        match kind {
+            Kind::Hundred => current *= 100,
+            Kind::Scale => {
+                total += current * value;
+                current = 0;
+            }
+            _ => current += value,
+        }
+        last = Some(kind);
+        n += 1;
+        if is_ordinal {
+            ordinal = true;
+            break;
+        }
+    }
+    if n == 0 {
+        return None;
+    }
+
+    let mut number = Number {
+        value: total + current,
+        fraction: None,
+        ordinal,
+        words: n,
+    };
+    if !ordinal && words.get(n) == Some(&"point") {
+        let fraction: String = words[n + 1..]
+            .iter()
+            .map_while(|word| digit_word(word))
+            .map(|digit| char::from(b'0' + digit as u8))
+            .collect();
+        if !fraction.is_empty() {
+            number.words += 1 + fraction.len();
+            number.fraction = Some(fraction);
+        }
+    }
+    Some(number)
+}
+
+/// A number that is not an ordinal, e.g. an amount of money
+fn parse_cardinal(words: &[&str]) -> Option<Number> {
+    parse_number(words).filter(|number| !number.ordinal)
+}
+
+/// A year such as "twenty twenty four", "nineteen oh five" or
+/// "two thousand ten"
+fn parse_year(words: &[&str]) -> Option<(u64, usize)> {
+    if let Some(number) = parse_cardinal(words) {
+        if number.fraction.is_none() && (1000..3000).contains(&number.value) {
+            return Some((number.value, number.words));
+        }
+    }
+
+    let century = match cardinal_word(words.first()?)? {
+        (value @ 11..=19, Kind::Teen) | (value @ 20, Kind::Tens) => value,
+        _ => return None,
+    };
+    let rest = &words[1..];
+    if rest.first() == Some(&"hundred") {
+        return Some((century * 100, 2));
+    }
+    if rest.first() == Some(&"oh") {
+        let digit = rest
+            .get(1)
+            .and_then(|word| digit_word(word))
+            .filter(|d| *d > 0)?;
+        return Some((century * 100 + digit, 3));
+    }
+    let number = parse_cardinal(rest)?;
+    if number.fraction.is_some() || !(10..100).contains(&number.value) {
+        return None;
+    }
+    Some((century * 100 + number.value, 1 + number.words))
+}
+
+fn group_thousands(value: u64) -> String {
+    let digits = value.to_string();
+    // Four-digit numbers read like years, so they are left ungrouped
+    if value < 10_000 {
+        return digits;
+    }
+    let mut out = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn ordinal_suffix(value: u64) -> &'static str {
+    match (value % 10, value % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    }
+}
+
+/// A word of the input with its byte span
+struct Word<'a> {
+    text: &'a str,
+    lower: String,
+    start: usize,
+    end: usize,
+}
+
+/// Replacement for a span of the input
+struct Rewrite {
+    /// Number of words replaced
+    words: usize,
+    /// Byte offset the replaced span ends at, when it extends past the last
+    /// word (e.g. over "p.m.")
+    end: Option<usize>,
+    text: String,
+}
+
+impl Rewrite {
+    fn new(words: usize, text: String) -> Self {
+        Self {
+            words,
+            end: None,
+            text,
+        }
+    }
+}
+
+struct Input<'a> {
+    text: &'a str,
+    words: Vec<Word<'a>>,
+}
+
+impl<'a> Input<'a> {
+    fn new(text: &'a str) -> Self {
+        static WORD: OnceLock<Regex> = OnceLock::new();
+        let word = WORD.get_or_init(|| Regex::new(r"\b[A-Za-z]+(?:'[A-Za-z]+)?\b").unwrap());
+        let words = word
+            .find_iter(text)
+            .map(|m| Word {
+                text: m.as_str(),
+                lower: m.as_str().to_lowercase(),
+                start: m.start(),
+                end: m.end(),
+            })
+            .collect();
+        Self { text, words }
+    }
+
+    /// Text between word `i` and the next one
+    fn gap_after(&self, i: usize) -> &str {
+        match self.words.get(i + 1) {
+            Some(next) => &self.text[self.words[i].end..next.start],
+            None => &self.text[self.words[i].end..],
+        }
+    }
+
+    /// Lowercase words from `i` that are only separated by spaces or
+    /// hyphens, so a number never runs across punctuation
+    fn run(&self, i: usize) -> Vec<&str> {
+        let mut run = Vec::new();
+        for j in i..self.words.len() {
+            run.push(self.words[j].lower.as_str());
+            let gap = self.gap_after(j);
+            let joined = gap == "-" || (!gap.is_empty() && gap.chars().all(char::is_whitespace));
+            if !joined || j + 1 == self.words.len() {
+                break;
+            }
+        }
+        run
+    }
+}
+
+/// Rewrite spoken numbers in `text` in their written form
+pub fn normalize(text: &str) -> String {
+    let input = Input::new(text);
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    let mut i = 0;
+    while i < input.words.len() {
+        let rewrite = phone_number(&input, i)
+            .or_else(|| date(&input, i))
+            .or_else(|| currency(&input, i))
+            .or_else(|| percentage(&input, i))
+            .or_else(|| time(&input, i))
+            .or_else(|| standalone_number(&input, i));
+        match rewrite {
+            Some(rewrite) => {
+                out.push_str(&text[last..input.words[i].start]);
+                out.push_str(&rewrite.text);
+                last = rewrite
+                    .end
+                    .unwrap_or(input.words[i + rewrite.words - 1].end);
+                i += rewrite.words;
+            }
+            None => i += skip_small_number(&input, i),
+        }
+    }
+    out.push_str(&text[last..]);
+    out
+}
+
+/// Seven, ten or eleven digits read one at a time
+fn phone_number(input: &Input, i: usize) -> Option<Rewrite> {
+    let run = input.run(i);
+    if run.first() == Some(&"oh") {
+        return None;
+    }
+    let digits: String = run
+        .iter()
+        .map_while(|word| digit_word(word))
+        .map(|digit| char::from(b'0' + digit as u8))
+        .collect();
+    let text = match digits.len() {
+        7 => format!("{}-{}", &digits[..3], &digits[3..]),
+        10 => format!("{}-{}-{}", &digits[..3], &digits[3..6], &digits[6..]),
+        11 if digits.starts_with('1') => {
+            format!("1-{}-{}-{}", &digits[1..4], &digits[4..7], &digits[7..])
+        }
+        _ => return None,
+    };
+    Some(Rewrite::new(digits.len(), text))
+}
+
+/// "march fourth", "march the fourth, twenty twenty four" or "the fourth
+/// of march"
+fn date(input: &Input, i: usize) -> Option<Rewrite> {
+    let run = input.run(i);
+    let month_at = |index: usize| -> Option<String> {
+        let word = *run.get(index)?;
+        // "may" is usually a verb, so only the capitalized month counts
+        if word == "may" && input.words[i + index].text != "May" {
+            return None;
+        }
+        MONTHS.contains(&word).then(|| capitalize(word))
+    };
+
+    let (month, day, used) = if let Some(month) = month_at(0) {
+        let skip = usize::from(run.get(1) == Some(&"the"));
+        let day = parse_number(&run[1 + skip..])?;
+        (month, day, 1 + skip)
+    } else if run.first() == Some(&"the") {
+        let day = parse_number(&run[1..]).filter(|day| day.ordinal)?;
+        if run.get(1 + day.words) != Some(&"of") {
+            return None;
+        }
+        (month_at(2 + day.words)?, day, 3)
+    } else {
+        return None;
+    };
+    if day.fraction.is_some() || !(1..=31).contains(&day.value) {
+        return None;
+    }
+    let mut words = used + day.words;
+
+    // The year may follow a comma, so it starts a run of its own
+    let last_word = i + words - 1;
+    let gap = input.gap_after(last_word).trim();
+    let year = if (gap.is_empty() || gap == ",") && last_word + 1 < input.words.len() {
+        parse_year(&input.run(last_word + 1))
+    } else {
+        None
+    };
+
+    // A spoken "march four" is only a date with a year after it
+    if !day.ordinal && year.is_none() {
+        return None;
+    }
+    let mut text = format!("{} {}", month, day.value);
+    if let Some((year, year_words)) = year {
+        text.push_str(&format!(", {}", year));
+        words += year_words;
+    }
+    Some(Rewrite::new(words, text))
+}
+
+/// "twenty three dollars fifty" or "five euros and ten cents"
+fn currency(input: &Input, i: usize) -> Option<Rewrite> {
+    let run = input.run(i);
+    let amount = parse_cardinal(&run)?;
+    let symbol = match *run.get(amount.words)? {
+        "dollar" | "dollars" | "bucks" => "$",
+        "euro" | "euros" => "€",
+        _ => return None,
+    };
+    let mut words = amount.words + 1;
+
+    let mut cents = None;
+    let and = usize::from(run.get(words) == Some(&"and"));
+    if let Some(number) = parse_cardinal(&run[(words + and).min(run.len())..]) {
+        let named = matches!(
+            run.get(words + and + number.words),
+            Some(&"cent" | &"cents")
+        );
+        if number.value < 100 && number.fraction.is_none() && (named || and == 0) {
+            cents = Some(number.value);
+            words += and + number.words + usize::from(named);
+        }
+    }
+
+    let mut text = format!("{}{}", symbol, group_thousands(amount.value));
+    match (cents, &amount.fraction) {
+        (Some(cents), _) => text.push_str(&format!(".{:02}", cents)),
+        (None, Some(fraction)) => text.push_str(&format!(".{:0<2}", fraction)),
+        (None, None) => {}
+    }
+    Some(Rewrite::new(words, text))
+}
+
+/// "five percent" or "three point five per cent"
+fn percentage(input: &Input, i: usize) -> Option<Rewrite> {
+    let run = input.run(i);
+    let number = parse_cardinal(&run)?;
+    let words = match (run.get(number.words), run.get(number.words + 1)) {
+        (Some(&"percent"), _) => 1,
+        (Some(&"per"), Some(&"cent")) => 2,
+        _ => return None,
+    };
+    Some(Rewrite::new(
+        number.words + words,
+        format!("{}%", number.digits()),
+    ))
+}
+
+/// "three thirty p m", "ten oh five am" or "seven o'clock"
+fn time(input: &Input, i: usize) -> Option<Rewrite> {
+    let run = input.run(i);
+    let hour = match cardinal_word(run.first()?)? {
+        (hour @ 1..=12, Kind::Unit | Kind::Teen) => hour,
+        _ => return None,
+    };
+    if run.get(1) == Some(&"o'clock") {
+        return Some(Rewrite::new(2, format!("{} o'clock", hour)));
+    }
+
+    let (minutes, minute_words) = match run.get(1).copied() {
+        Some("oh") => match run.get(2).and_then(|word| digit_word(word)) {
+            Some(digit) if digit > 0 => (Some(digit), 2),
+            _ => return None,
+        },
+        Some(_) => match parse_cardinal(&run[1..]) {
+            Some(number) if number.fraction.is_none() && (10..60).contains(&number.value) => {
+                (Some(number.value), number.words)
+            }
+            _ => (None, 0),
+        },
+        None => (None, 0),
+    };
+    let mut words = 1 + minute_words;
+
+    // "am" / "pm", or "a m" as separate words when said slowly
+    let meridiem = match (run.get(words).copied(), run.get(words + 1).copied()) {
+        (Some("am"), _) => Some(("AM", 1)),
+        (Some("pm"), _) => Some(("PM", 1)),
+        (Some("a"), Some("m")) => Some(("AM", 2)),
+        (Some("p"), Some("m")) => Some(("PM", 2)),
+        _ => None,
+    };
+    let mut end = None;
+    let meridiem = match meridiem {
+        Some((meridiem, meridiem_words)) => {
+            words += meridiem_words;
+            meridiem
+        }
+        None => {
+            // "a.m." is split into words by its periods
+            let (meridiem, meridiem_end) = dotted_meridiem(input, i + words - 1)?;
+            words += 2;
+            end = Some(meridiem_end);
+            meridiem
+        }
+    };
+
+    let text = match minutes {
+        Some(minutes) => format!("{}:{:02} {}", hour, minutes, meridiem),
+        None => format!("{} {}", hour, meridiem),
+    };
+    Some(Rewrite { words, end, text })
+}
+
+/// Match "a.m." or "p.m." after word `i`, returning it and where it ends.
+/// A final period that also ends the sentence is left in place.
+fn dotted_meridiem(input: &Input, i: usize) -> Option<(&'static str, usize)> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| Regex::new(r"^\s+([AaPp])\.\s?[Mm]\b(\.)?").unwrap());
+    let rest = &input.text[input.words[i].end..];
+    let captures = pattern.captures(rest)?;
+    let meridiem = if captures[1].eq_ignore_ascii_case("a") {
+        "AM"
+    } else {
+        "PM"
+    };
+
+    let mut end = captures.get(0).unwrap().end();
+    if let Some(period) = captures.get(2) {
+        let after = &rest[end..];
+        let ends_sentence = after.trim().is_empty()
+            || (after.starts_with(char::is_whitespace)
+                && after.trim_start().starts_with(char::is_uppercase));
+        if ends_sentence {
+            end = period.start();
+        }
+    }
+    Some((meridiem, input.words[i].end + end))
+}
+
+/// Numbers from ten up, and ordinals from "tenth" up; smaller ones read
+/// better as words in running text
+fn standalone_number(input: &Input, i: usize) -> Option<Rewrite> {
+    let number = parse_number(&input.run(i))?;
+    if number.value < 10 && number.fraction.is_none() {
+        return None;
+    }
+    Some(Rewrite::new(number.words, number.digits()))
+}
+
+/// Words to leave alone at `i`: a small number that stays a word takes the
+/// number words right after it along, so "three thirty" never becomes
+/// "three 30"
+fn skip_small_number(input: &Input, i: usize) -> usize {
+    let run = input.run(i);
+    match parse_number(&run) {
+        Some(number) if number.fraction.is_none() => run
+            .iter()
+            .take_while(|word| cardinal_word(word).is_some())
+            .count()
+            .max(1),
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_example() {
+        assert_eq!(
+            normalize("twenty three dollars fifty on march fourth"),
+            "$23.50 on March 4"
+        );
+    }
+
+    #[test]
+    fn test_cardinals() {
+        assert_eq!(normalize("I have twenty three apples"), "I have 23 apples");
+        assert_eq!(normalize("one hundred and five"), "105");
+        assert_eq!(normalize("Twenty-one"), "21");
+        assert_eq!(normalize("two thousand twenty four people"), "2024 people");
+        assert_eq!(normalize("three million two hundred thousand"), "3,200,000");
+        assert_eq!(normalize("pi is three point one four"), "pi is 3.14");
+        // Small numbers stay words in running text
+        assert_eq!(
+            normalize("one of the two options"),
+            "one of the two options"
+        );
+        // Punctuation separates numbers
+        assert_eq!(normalize("twenty, three"), "20, three");
+    }
+
+    #[test]
+    fn test_ordinals() {
+        assert_eq!(normalize("the twenty first century"), "the 21st century");
+        assert_eq!(normalize("her thirteenth birthday"), "her 13th birthday");
+        assert_eq!(normalize("the one hundredth time"), "the 100th time");
+        assert_eq!(
+            normalize("first of all, wait a second"),
+            "first of all, wait a second"
+        );
+    }
+
+    #[test]
+    fn test_currency_and_percentages() {
+        assert_eq!(normalize("it costs five dollars"), "it costs $5");
+        assert_eq!(normalize("ten euros and five cents"), "€10.05");
+        assert_eq!(normalize("three point five dollars"), "$3.50");
+        assert_eq!(normalize("one thousand dollars"), "$1000");
+        assert_eq!(normalize("up five percent"), "up 5%");
+        assert_eq!(normalize("two point five per cent"), "2.5%");
+    }
+
+    #[test]
+    fn test_times() {
+        assert_eq!(normalize("at three thirty p m"), "at 3:30 PM");
+        assert_eq!(normalize("ten oh five am"), "10:05 AM");
+        assert_eq!(normalize("Meet at seven PM."), "Meet at 7 PM.");
+        assert_eq!(normalize("at eleven a.m. tomorrow"), "at 11 AM tomorrow");
+        assert_eq!(normalize("at nine p.m. Then home"), "at 9 PM. Then home");
+        assert_eq!(normalize("at nine p.m."), "at 9 PM.");
+        assert_eq!(normalize("ten o'clock"), "10 o'clock");
+        // Without am or pm it is not clearly a time, so it stays in words
+        assert_eq!(normalize("meet at three thirty"), "meet at three thirty");
+        assert_eq!(normalize("three thirty or forty"), "three thirty or 40");
+    }
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(normalize("on March fourth."), "on March 4.");
+        assert_eq!(
+            normalize("march the twenty first, twenty twenty four"),
+            "March 21, 2024"
+        );
+        assert_eq!(normalize("the fourth of july"), "July 4");
+        assert_eq!(normalize("June three nineteen oh five"), "June 3, 1905");
+        assert_eq!(normalize("you may first check"), "you may first check");
+        assert_eq!(normalize("May first"), "May 1");
+    }
+
+    #[test]
+    fn test_phone_numbers() {
+        assert_eq!(
+            normalize("call five five five one two three four"),
+            "call 555-1234"
+        );
+        assert_eq!(
+            normalize("four one five five five five oh one nine nine"),
+            "415-555-0199"
+        );
+        // Too few digits for a phone number
+        assert_eq!(normalize("one two three"), "one two three");
+    }
+}
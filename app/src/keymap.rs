@@ -0,0 +1,673 @@
+//! Character to keycode mapping for the user's keyboard layout.
+//!
+//! uinput sends physical keycodes and the desktop translates them with the
+//! active XKB layout, so a US table types scrambled text on German, AZERTY
+//! or Dvorak layouts. Instead the table is built at startup from the layout's
+//! keymap as compiled by libxkbcommon, with the built-in US mapping as
+//! fallback. Which modifiers select a shift level depends on each key's type,
+//! so every key is read from an XKB state with Shift and the layout's
+//! `ISO_Level3_Shift` key held rather than assuming a fixed level order.
+
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::ops::RangeInclusive;
+use std::process::Command;
+use std::ptr;
+use tracing::{info, warn};
+use xkbcommon_dl::{
+    xkb_context, xkb_context_flags, xkb_key_direction, xkb_keymap, xkb_keymap_compile_flags,
+    xkb_rule_names, xkbcommon_option, XkbCommon,
+};
+use xkeysym::Keysym;
+
+use crate::input_event::{char_to_keycode, KEY_ENTER, KEY_LEFTSHIFT, KEY_RIGHTALT, KEY_TAB};
+
+/// Evdev keycodes are XKB keycodes minus this offset
+const XKB_KEYCODE_OFFSET: u32 = 8;
+
+/// Highest keycode the virtual keyboard enables
+const MAX_KEYCODE: u32 = 255;
+
+/// How to produce a character: a key plus the modifiers for its shift level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyMapping {
+    pub keycode: u16,
+    pub shift: bool,
+    /// The layout's level three shift key (usually AltGr), if it must be held
+    pub level3: Option<u16>,
+}
+
+impl KeyMapping {
+    fn modifier_count(&self) -> u8 {
+        self.shift as u8 + self.level3.is_some() as u8
+    }
+}
+
+/// XKB layout and variant, e.g. `de` / `nodeadkeys`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutSpec {
+    pub layout: String,
+    pub variant: Option<String>,
+}
+
+impl LayoutSpec {
+    pub fn new(layout: &str, variant: Option<&str>) -> Self {
+        // Multi-layout settings such as "us,de" list the primary layout first
+        let first = |value: &str| value.split(',').next().unwrap_or("").trim().to_string();
+        Self {
+            layout: first(layout),
+            variant: variant.map(first).filter(|v| !v.is_empty()),
+        }
+    }
+
+    pub fn is_us(&self) -> bool {
+        self.layout == "us" && self.variant.is_none()
+    }
+
+    /// Find the system layout from `XKB_DEFAULT_LAYOUT`, `/etc/default/keyboard`
+    /// or `localectl`
+    pub fn detect() -> Option<Self> {
+        if let Ok(layout) = std::env::var("XKB_DEFAULT_LAYOUT") {
+            if !layout.is_empty() {
+                let variant = std::env::var("XKB_DEFAULT_VARIANT").ok();
+                return Some(Self::new(&layout, variant.as_deref()));
+            }
+        }
+
+        if let Ok(contents) = std::fs::read_to_string("/etc/default/keyboard") {
+            if let Some(spec) = Self::from_default_keyboard(&contents) {
+                return Some(spec);
+            }
+        }
+
+        let output = Command::new("localectl").arg("status").output().ok()?;
+        Self::from_localectl(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// Parse Debian-style `/etc/default/keyboard`
+    pub fn from_default_keyboard(contents: &str) -> Option<Self> {
+        let value = |key: &str| {
+            contents.lines().find_map(|line| {
+                let rest = line.trim().strip_prefix(key)?.strip_prefix('=')?;
+                Some(rest.trim().trim_matches('"').trim_matches('\'').to_string())
+            })
+        };
+        let layout = value("XKBLAYOUT").filter(|l| !l.is_empty())?;
+        Some(Self::new(&layout, value("XKBVARIANT").as_deref()))
+    }
+
+    /// Parse the output of `localectl status`
+    pub fn from_localectl(output: &str) -> Option<Self> {
+        let value = |key: &str| {
+            output.lines().find_map(|line| {
+                let rest = line.trim().strip_prefix(key)?.strip_prefix(':')?;
+                Some(rest.trim().to_string())
+            })
+        };
+        let layout = value("X11 Layout").filter(|l| !l.is_empty() && l != "n/a")?;
+        Some(Self::new(&layout, value("X11 Variant").as_deref()))
+    }
+}
+
+impl std::fmt::Display for LayoutSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.variant {
+            Some(variant) => write!(f, "{} ({})", self.layout, variant),
+            None => write!(f, "{}", self.layout),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    name: String,
+    chars: HashMap<char, KeyMapping>,
+}
+
+impl Keymap {
+    /// The built-in US QWERTY mapping
+    pub fn us() -> Self {
+        let chars = (' '..='~')
+            .chain(['\n', '\t'])
+            .filter_map(|c| {
+                let (keycode, shift) = char_to_keycode(c)?;
+                Some((
+                    c,
+                    KeyMapping {
+                        keycode,
+                        shift,
+                        level3: None,
+                    },
+                ))
+            })
+            .collect();
+        Self {
+            name: "us".to_string(),
+            chars,
+        }
+    }
+
+    /// Compile and load the keymap for `spec`, falling back to US if
+    /// libxkbcommon is missing or the layout cannot be compiled
+    pub fn load(spec: &LayoutSpec) -> Self {
+        if spec.is_us() {
+            return Self::us();
+        }
+
+        match XkbKeymap::from_names(spec)
+            .and_then(|xkb| Self::from_compiled(&spec.to_string(), &xkb))
+        {
+            Ok(keymap) => {
+                info!(
+                    "Using keyboard layout {} ({} characters mapped)",
+                    spec,
+                    keymap.chars.len()
+                );
+                keymap
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to load keyboard layout {}: {:#}; falling back to US",
+                    spec, e
+                );
+                Self::us()
+            }
+        }
+    }
+
+    /// Build a mapping from XKB keymap source, such as the output of
+    /// `xkbcli compile-keymap`
+    #[cfg(test)]
+    pub fn from_xkb(name: &str, text: &str) -> Result<Self> {
+        Self::from_compiled(name, &XkbKeymap::from_string(text)?)
+    }
+
+    /// Read every key with Shift and the level three shift key held in turn.
+    ///
+    /// The key types decide which of those reach which level, so this works
+    /// for custom types as well as the usual four-level ones. When a
+    /// character can be typed in several ways the one with the fewest
+    /// modifiers wins.
+    fn from_compiled(name: &str, xkb: &XkbKeymap) -> Result<Self> {
+        let shift = KEY_LEFTSHIFT as u32 + XKB_KEYCODE_OFFSET;
+        // Prefer AltGr, but layouts may put the third level on another key
+        let level3_keys: Vec<u32> = xkb.keys_for(Keysym::ISO_Level3_Shift).collect();
+        let level3 = level3_keys
+            .iter()
+            .copied()
+            .find(|&key| key == KEY_RIGHTALT as u32 + XKB_KEYCODE_OFFSET)
+            .or(level3_keys.first().copied());
+
+        let mut holds = vec![(false, None), (true, None)];
+        if let Some(key) = level3 {
+            holds.extend([(false, Some(key)), (true, Some(key))]);
+        }
+
+        let mut chars: HashMap<char, KeyMapping> = HashMap::new();
+        for (with_shift, level3) in holds {
+            let held: Vec<u32> = with_shift
+                .then_some(shift)
+                .into_iter()
+                .chain(level3)
+                .collect();
+            for (key, keysym) in xkb.keysyms_while_holding(&held) {
+                let Some(c) = keysym_char(keysym) else {
+                    continue;
+                };
+                let mapping = KeyMapping {
+                    keycode: (key - XKB_KEYCODE_OFFSET) as u16,
+                    shift: with_shift,
+                    level3: level3.map(|key| (key - XKB_KEYCODE_OFFSET) as u16),
+                };
+                let better = chars.get(&c).is_none_or(|existing| {
+                    (mapping.modifier_count(), mapping.keycode)
+                        < (existing.modifier_count(), existing.keycode)
+                });
+                if better {
+                    chars.insert(c, mapping);
+                }
+            }
+        }
+
+        if chars.is_empty() {
+            bail!("keymap has no keys that type characters");
+        }
+
+        // Control characters are produced by dedicated keys, not keysyms
+        for (c, keycode) in [('\n', KEY_ENTER), ('\t', KEY_TAB)] {
+            chars.insert(
+                c,
+                KeyMapping {
+                    keycode,
+                    shift: false,
+                    level3: None,
+                },
+            );
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            chars,
+        })
+    }
+
+    pub fn lookup(&self, c: char) -> Option<KeyMapping> {
+        self.chars.get(&c).copied()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::us()
+    }
+}
+
+/// A keymap compiled by libxkbcommon, which is loaded at runtime
+struct XkbKeymap {
+    xkb: &'static XkbCommon,
+    context: *mut xkb_context,
+    keymap: *mut xkb_keymap,
+}
+
+impl XkbKeymap {
+    /// Compile the keymap the XKB rules give for `spec`
+    fn from_names(spec: &LayoutSpec) -> Result<Self> {
+        let layout = CString::new(spec.layout.as_str())?;
+        let variant = spec.variant.as_deref().map(CString::new).transpose()?;
+        let names = xkb_rule_names {
+            rules: ptr::null(),
+            model: ptr::null(),
+            layout: layout.as_ptr(),
+            variant: variant.as_ref().map_or(ptr::null(), |v| v.as_ptr()),
+            options: ptr::null(),
+        };
+        Self::compile(|xkb, context| unsafe {
+            (xkb.xkb_keymap_new_from_names)(
+                context,
+                &names,
+                xkb_keymap_compile_flags::XKB_KEYMAP_COMPILE_NO_FLAGS,
+            )
+        })
+    }
+
+    /// Compile keymap source text
+    #[cfg(test)]
+    fn from_string(text: &str) -> Result<Self> {
+        let text = CString::new(text)?;
+        Self::compile(|xkb, context| unsafe {
+            (xkb.xkb_keymap_new_from_string)(
+                context,
+                text.as_ptr(),
+                xkbcommon_dl::xkb_keymap_format::XKB_KEYMAP_FORMAT_TEXT_V1,
+                xkb_keymap_compile_flags::XKB_KEYMAP_COMPILE_NO_FLAGS,
+            )
+        })
+    }
+
+    fn compile(
+        compile: impl FnOnce(&XkbCommon, *mut xkb_context) -> *mut xkb_keymap,
+    ) -> Result<Self> {
+        let xkb = xkbcommon_option().context("libxkbcommon is not installed")?;
+        let context = unsafe { (xkb.xkb_context_new)(xkb_context_flags::XKB_CONTEXT_NO_FLAGS) };
+        if context.is_null() {
+            bail!("Failed to create an XKB context");
+        }
+        let keymap = compile(xkb, context);
+        if keymap.is_null() {
+            unsafe { (xkb.xkb_context_unref)(context) };
+            bail!("libxkbcommon could not compile the keymap");
+        }
+        Ok(Self {
+            xkb,
+            context,
+            keymap,
+        })
+    }
+
+    /// XKB keycodes of the keys the virtual keyboard can send
+    fn keycodes(&self) -> RangeInclusive<u32> {
+        let (min, max) = unsafe {
+            (
+                (self.xkb.xkb_keymap_min_keycode)(self.keymap),
+                (self.xkb.xkb_keymap_max_keycode)(self.keymap),
+            )
+        };
+        min.max(XKB_KEYCODE_OFFSET)..=max.min(MAX_KEYCODE + XKB_KEYCODE_OFFSET)
+    }
+
+    /// Keys whose first level is `keysym`
+    fn keys_for(&self, keysym: Keysym) -> impl Iterator<Item = u32> + '_ {
+        self.keycodes().filter(move |&key| {
+            let mut syms = ptr::null();
+            let count = unsafe {
+                (self.xkb.xkb_keymap_key_get_syms_by_level)(self.keymap, key, 0, 0, &mut syms)
+            };
+            count == 1 && unsafe { *syms } == keysym.raw()
+        })
+    }
+
+    /// The keysym every key produces while the `held` keys are down
+    fn keysyms_while_holding(&self, held: &[u32]) -> Vec<(u32, Keysym)> {
+        unsafe {
+            let state = (self.xkb.xkb_state_new)(self.keymap);
+            if state.is_null() {
+                return Vec::new();
+            }
+            for &key in held {
+                (self.xkb.xkb_state_update_key)(state, key, xkb_key_direction::XKB_KEY_DOWN);
+            }
+            let keysyms = self
+                .keycodes()
+                .map(|key| {
+                    let raw = (self.xkb.xkb_state_key_get_one_sym)(state, key);
+                    (key, Keysym::new(raw))
+                })
+                .collect();
+            (self.xkb.xkb_state_unref)(state);
+            keysyms
+        }
+    }
+}
+
+impl Drop for XkbKeymap {
+    fn drop(&mut self) {
+        unsafe {
+            (self.xkb.xkb_keymap_unref)(self.keymap);
+            (self.xkb.xkb_context_unref)(self.context);
+        }
+    }
+}
+
+/// Character a keysym types, if any
+fn keysym_char(keysym: Keysym) -> Option<char> {
+    // Keypad digits depend on NumLock, so never type through them
+    if keysym.is_keypad_key() {
+        return None;
+    }
+    keysym.key_char().filter(|c| !c.is_control())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_event::*;
+
+    /// Keycodes, types and compat shared by the test keymaps, trimmed from
+    /// the output of `xkbcli compile-keymap`
+    const KEYMAP_PRELUDE: &str = r#"
+xkb_keycodes "evdev+aliases(qwertz)" {
+	minimum = 8;
+	maximum = 255;
+	<AE01>               = 10;
+	<AE02>               = 11;
+	<AE11>               = 20;
+	<AD01>               = 24;
+	<AD03>               = 26;
+	<AD06>               = 29;
+	<AC01>               = 38;
+	<AC11>               = 48;
+	<LFSH>               = 50;
+	<BKSL>               = 51;
+	<AB01>               = 52;
+	<LALT>               = 64;
+	<CAPS>               = 66;
+	<KP1>                = 87;
+	<RALT>               = 108;
+	alias <AC12>         = <BKSL>;
+};
+
+xkb_types "complete" {
+	virtual_modifiers NumLock,LevelThree;
+	type "ONE_LEVEL" {
+		modifiers= none;
+		level_name[1]= "Any";
+	};
+	type "TWO_LEVEL" {
+		modifiers= Shift;
+		map[Shift]= 2;
+		level_name[1]= "Base";
+		level_name[2]= "Shift";
+	};
+	type "ALPHABETIC" {
+		modifiers= Shift+Lock;
+		map[Shift]= 2;
+		map[Lock]= 2;
+		level_name[1]= "Base";
+		level_name[2]= "Caps";
+	};
+	type "KEYPAD" {
+		modifiers= Shift+NumLock;
+		map[Shift]= 2;
+		map[NumLock]= 2;
+		level_name[1]= "Base";
+		level_name[2]= "Number";
+	};
+	type "FOUR_LEVEL" {
+		modifiers= Shift+LevelThree;
+		map[Shift]= 2;
+		map[LevelThree]= 3;
+		map[Shift+LevelThree]= 4;
+		level_name[1]= "Base";
+		level_name[2]= "Shift";
+		level_name[3]= "AltGr";
+		level_name[4]= "Shift AltGr";
+	};
+	type "FOUR_LEVEL_ALPHABETIC" {
+		modifiers= Shift+Lock+LevelThree;
+		map[Shift]= 2;
+		map[Lock]= 2;
+		map[LevelThree]= 3;
+		map[Shift+LevelThree]= 4;
+		level_name[1]= "Base";
+		level_name[2]= "Shift";
+		level_name[3]= "AltGr";
+		level_name[4]= "Shift AltGr";
+	};
+	type "FOUR_LEVEL_SEMIALPHABETIC" {
+		modifiers= Shift+Lock+LevelThree;
+		map[Shift]= 2;
+		map[Lock]= 2;
+		map[LevelThree]= 3;
+		map[Shift+LevelThree]= 4;
+		level_name[1]= "Base";
+		level_name[2]= "Shift";
+		level_name[3]= "AltGr";
+		level_name[4]= "Shift AltGr";
+	};
+	type "THIRD_LEVEL_FIRST" {
+		modifiers= Shift+LevelThree;
+		map[LevelThree]= 2;
+		map[Shift]= 3;
+		map[Shift+LevelThree]= 4;
+		level_name[1]= "Base";
+		level_name[2]= "AltGr";
+		level_name[3]= "Shift";
+		level_name[4]= "Shift AltGr";
+	};
+};
+
+xkb_compatibility "complete" {
+	virtual_modifiers NumLock,LevelThree;
+	interpret ISO_Level3_Shift+AnyOf(all) {
+		virtualModifier= LevelThree;
+		useModMapMods=level1;
+		action= SetMods(modifiers=LevelThree,clearLocks);
+	};
+	interpret Any+AnyOf(all) {
+		action= SetMods(modifiers=modMapMods,clearLocks);
+	};
+};
+"#;
+
+    /// Excerpt of the German layout, with AltGr as the level three shift
+    const DE_SYMBOLS: &str = r#"
+xkb_symbols "pc+de+inet(evdev)" {
+	name[group1]="German";
+
+	key <AE01>               {	[               1,          exclam,     onesuperior,      exclamdown ] };
+	key <AE02>               {	[               2,        quotedbl,     twosuperior,       oneeighth ] };
+	key <AE11>               {	[          ssharp,        question,       backslash,    questiondown ] };
+	key <AD01>               {	[               q,               Q,              at,     Greek_OMEGA ] };
+	key <AD03>               {
+		type= "FOUR_LEVEL_SEMIALPHABETIC",
+		symbols[Group1]= [               e,               E,        EuroSign,        EuroSign ]
+	};
+	key <AD06>               {	[               z,               Z,       leftarrow,             yen ] };
+	key <AC01>               {	[               a,               A,              ae,              AE ] };
+	key <AC11>               {	[      adiaeresis,      Adiaeresis,     dead_circumflex,     dead_caron ] };
+	key <AB01>               {	[               y,               Y,         guillemotright,   U203A ] };
+	key <AC12>               {	[      numbersign,      apostrophe,     rightsinglequotemark,       grave ] };
+	key <KP1>                {	[         KP_End,            KP_1 ] };
+	key <LALT>               {	[           Alt_L,          Meta_L ] };
+	key <LFSH>               {	[         Shift_L ] };
+	key <RALT>               {
+		type= "ONE_LEVEL",
+		symbols[Group1]= [ ISO_Level3_Shift ]
+	};
+	modifier_map Shift { <LFSH> };
+	modifier_map Mod1 { <LALT> };
+	modifier_map Mod5 { <RALT> };
+};
+"#;
+
+    /// Level three shift on Caps Lock and a key whose type swaps levels 2
+    /// and 3, so Shift selects the third level
+    const CAPS_LEVEL3_SYMBOLS: &str = r#"
+xkb_symbols "custom" {
+	name[group1]="Custom";
+
+	key <AD01>               {
+		type= "THIRD_LEVEL_FIRST",
+		symbols[Group1]= [               q,              at,               Q,     Greek_OMEGA ]
+	};
+	key <AC01>               {	[               a,               A,              ae,              AE ] };
+	key <LFSH>               {	[         Shift_L ] };
+	key <CAPS>               {
+		type= "ONE_LEVEL",
+		symbols[Group1]= [ ISO_Level3_Shift ]
+	};
+	key <RALT>               {	[           Alt_R,          Meta_R ] };
+	modifier_map Shift { <LFSH> };
+	modifier_map Mod1 { <RALT> };
+	modifier_map Mod5 { <CAPS> };
+};
+"#;
+
+    fn keymap(name: &str, symbols: &str) -> Keymap {
+        let text = format!("xkb_keymap {{\n{}\n{}\n}};\n", KEYMAP_PRELUDE, symbols);
+        Keymap::from_xkb(name, &text).unwrap()
+    }
+
+    fn mapping(keycode: u16, shift: bool, level3: Option<u16>) -> Option<KeyMapping> {
+        Some(KeyMapping {
+            keycode,
+            shift,
+            level3,
+        })
+    }
+
+    #[test]
+    fn test_us_fallback_matches_builtin_table() {
+        let keymap = Keymap::us();
+        assert_eq!(keymap.lookup('a'), mapping(KEY_A, false, None));
+        assert_eq!(keymap.lookup('?'), mapping(KEY_SLASH, true, None));
+        assert_eq!(keymap.lookup('\n'), mapping(KEY_ENTER, false, None));
+        assert_eq!(keymap.lookup('ä'), None);
+    }
+
+    #[test]
+    fn test_german_layout() {
+        let keymap = keymap("de", DE_SYMBOLS);
+        let altgr = Some(KEY_RIGHTALT);
+
+        // y and z swap places on QWERTZ
+        assert_eq!(keymap.lookup('z'), mapping(KEY_Y, false, None));
+        assert_eq!(keymap.lookup('Y'), mapping(KEY_Z, true, None));
+        assert_eq!(keymap.lookup('ä'), mapping(KEY_APOSTROPHE, false, None));
+        assert_eq!(keymap.lookup('ß'), mapping(KEY_MINUS, false, None));
+        assert_eq!(keymap.lookup('"'), mapping(KEY_2, true, None));
+
+        // AltGr levels
+        assert_eq!(keymap.lookup('@'), mapping(KEY_Q, false, altgr));
+        assert_eq!(keymap.lookup('€'), mapping(KEY_E, false, altgr));
+        assert_eq!(keymap.lookup('¡'), mapping(KEY_1, true, altgr));
+        assert_eq!(keymap.lookup('›'), mapping(KEY_Z, true, altgr));
+    }
+
+    #[test]
+    fn test_aliases_and_dead_keys() {
+        let keymap = keymap("de", DE_SYMBOLS);
+
+        // <AC12> is an alias for <BKSL>
+        assert_eq!(keymap.lookup('#'), mapping(KEY_BACKSLASH, false, None));
+        // Dead keys compose with the next key instead of typing themselves
+        assert_eq!(keymap.lookup('^'), None);
+    }
+
+    #[test]
+    fn test_prefers_fewest_modifiers_and_skips_keypad() {
+        let keymap = keymap("de", DE_SYMBOLS);
+
+        // Backslash only exists on AltGr+ß here
+        assert_eq!(
+            keymap.lookup('\\'),
+            mapping(KEY_MINUS, false, Some(KEY_RIGHTALT))
+        );
+        // '1' must come from the number row, not the keypad
+        assert_eq!(keymap.lookup('1'), mapping(KEY_1, false, None));
+    }
+
+    #[test]
+    fn test_levels_follow_key_types_and_level3_key() {
+        let keymap = keymap("custom", CAPS_LEVEL3_SYMBOLS);
+        let caps = Some(KEY_CAPSLOCK);
+
+        // THIRD_LEVEL_FIRST puts the second level behind the level three shift
+        assert_eq!(keymap.lookup('@'), mapping(KEY_Q, false, caps));
+        assert_eq!(keymap.lookup('Q'), mapping(KEY_Q, true, None));
+        assert_eq!(keymap.lookup('Ω'), mapping(KEY_Q, true, caps));
+        // The usual four-level type, but reached through Caps Lock
+        assert_eq!(keymap.lookup('æ'), mapping(KEY_A, false, caps));
+        assert_eq!(keymap.lookup('Æ'), mapping(KEY_A, true, caps));
+    }
+
+    #[test]
+    fn test_rejects_non_keymap_text() {
+        assert!(Keymap::from_xkb("bogus", "not a keymap").is_err());
+    }
+
+    #[test]
+    fn test_keysym_char() {
+        assert_eq!(keysym_char(Keysym::adiaeresis), Some('ä'));
+        assert_eq!(keysym_char(Keysym::EuroSign), Some('€'));
+        assert_eq!(keysym_char(Keysym::Cyrillic_zhe), Some('ж'));
+        assert_eq!(keysym_char(Keysym::new(0x0100_203a)), Some('›'));
+        assert_eq!(keysym_char(Keysym::dead_acute), None);
+        assert_eq!(keysym_char(Keysym::Shift_L), None);
+        assert_eq!(keysym_char(Keysym::KP_1), None);
+    }
+
+    #[test]
+    fn test_layout_detection_sources() {
+        let default_keyboard = "XKBMODEL=\"pc105\"\nXKBLAYOUT=\"fr,us\"\nXKBVARIANT=\"azerty\"\n";
+        assert_eq!(
+            LayoutSpec::from_default_keyboard(default_keyboard),
+            Some(LayoutSpec::new("fr", Some("azerty")))
+        );
+
+        let localectl = "   System Locale: LANG=en_US.UTF-8\n       X11 Layout: us\n      X11 Variant: dvorak\n";
+        assert_eq!(
+            LayoutSpec::from_localectl(localectl),
+            Some(LayoutSpec::new("us", Some("dvorak")))
+        );
+        assert_eq!(LayoutSpec::from_localectl("X11 Layout: n/a"), None);
+
+        assert!(LayoutSpec::new("us", Some("")).is_us());
+        assert!(!LayoutSpec::new("us", Some("dvorak")).is_us());
+    }
+}
@@ -1,22 +1,76 @@
 use anyhow::{Context, Result};
 use clap::{Arg, Command};
 use nix::unistd::{getgid, getuid, setgid, setuid, Gid, Uid};
+use std::collections::BTreeMap;
 use std::env;
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
 use std::thread;
 use std::time::Duration;
-use tracing::{debug, error, info};
+use tracing::{debug, info, warn};
 
+mod audio_health;
 mod audio_input;
+mod audio_pipeline;
+mod cadence;
+mod calibration;
+mod clipboard;
+mod code_mode;
+mod config;
+mod control;
 mod input_event;
+mod itn;
+mod keymap;
+mod metrics;
+mod output_backend;
+mod profile;
+mod replacements;
+mod ring_buffer;
+mod snippets;
+mod spoken_punctuation;
+mod status;
 mod stt_client;
+mod transliterate;
+mod triggers;
+mod turn_format;
+mod typing_worker;
+mod undo;
+mod unicode_input;
 mod virtual_keyboard;
+mod wayland_keyboard;
+mod xtest;
 
-use audio_input::AudioInput;
-use stt_client::{AudioBuffer, SttClient};
-use virtual_keyboard::{RealKeyboardHardware, VirtualKeyboard};
+use audio_health::{HealthEvent, HealthMonitor, HealthThresholds};
+use audio_input::{AudioInput, AudioPreferences};
+use audio_pipeline::{BufferedSender, StartupQueue, WorkerConfig};
+use cadence::Cadence;
+use calibration::Calibration;
+use clipboard::{ClipboardTool, CommandClipboard};
+use config::{AudioProfile, Config};
+use keymap::{Keymap, LayoutSpec};
+use metrics::METRICS;
+use output_backend::OutputBackend;
+use profile::TextSettings;
+use replacements::{ReplacementRules, Replacements};
+use snippets::Snippets;
+use spoken_punctuation::SpokenPunctuation;
+use status::{StatusLevel, StatusLine};
+use stt_client::SttClient;
+use transliterate::Transliterator;
+use triggers::Triggers;
+use unicode_input::UnicodeInput;
+use virtual_keyboard::{
+    DeviceIdentity, KeyboardHardware, PasteOutput, RealKeyboardHardware, VirtualKeyboard,
+};
 use std::time::Instant;
 
+/// Delay before retrying a failed STT connection in long-running mode
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// A connection that lasted this long counts as healthy and resets the backoff
+const RECONNECT_STABLE_AFTER: Duration = Duration::from_secs(30);
+
 #[derive(Debug)]
 struct OriginalUser {
     uid: Uid,
@@ -102,9 +156,26 @@ impl OriginalUser {
     }
 }
 
+/// Capture settings shared by every mode that records audio
+struct CaptureOptions {
+    prefs: AudioPreferences,
+    health_beep: bool,
+    /// Calibrated settings keyed by input device name
+    profiles: BTreeMap<String, AudioProfile>,
+}
+
+impl CaptureOptions {
+    fn profile_for(&self, device_name: &str) -> Option<&AudioProfile> {
+        self.profiles.get(device_name)
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
+    // Log to stderr so the stdout output backend only prints typed text
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
 
     info!("Starting Voice Keyboard v{}", env!("CARGO_PKG_VERSION"));
 
@@ -141,43 +212,369 @@ async fn main() -> Result<()> {
         .arg(
             Arg::new("voice-enter")
                 .long("voice-enter")
-                .help("Interpret the word 'enter' at end-of-turn as an Enter key press")
+                .help("Interpret trigger phrases at end-of-turn ('enter' presses Enter; more in trigger_phrases in the config)")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-format-turns")
+                .long("no-format-turns")
+                .help("Type each turn as transcribed, without a separating space or sentence-aware capitalization")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("voice-undo")
+                .long("voice-undo")
+                .help("Say 'scratch that' to erase the previous turn, or end a turn with 'never mind' or 'cancel' to erase it")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("spoken-punctuation")
+                .long("spoken-punctuation")
+                .help("Type symbols for spoken commands such as 'comma', 'question mark' or 'new line'")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("test-rules")
+                .long("test-rules")
+                .help("Show how the replacement rules in the config rewrite TEXT, then exit")
+                .value_name("TEXT"),
+        )
+        .arg(
+            Arg::new("itn")
+                .long("itn")
+                .help("Type spoken numbers, dates, times, amounts and phone numbers in written form, e.g. '$23.50 on March 4'")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("code-mode")
+                .long("code-mode")
+                .help("Start in code mode: 'camel case user id' types userId, 'dot' types '.'; say 'prose mode' or 'code mode' to switch")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .help("Apply a named set of text settings from profiles in the config")
+                .value_name("NAME"),
+        )
         .arg(
             Arg::new("uppercase")
                 .long("uppercase")
                 .help("Convert all typed text to uppercase")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("sample-format")
+                .long("sample-format")
+                .help("Request a capture sample format (i8, i16, i32, i64, u8, u16, u32, u64, f32, f64)")
+                .value_name("FORMAT"),
+        )
+        .arg(
+            Arg::new("sample-rate")
+                .long("sample-rate")
+                .help("Request a capture sample rate in Hz")
+                .value_name("HZ")
+                .value_parser(clap::value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("calibrate")
+                .long("calibrate")
+                .help("Measure noise floor and speech level, then save gain and VAD threshold for the input device")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("playback")
+                .long("playback")
+                .help("With --calibrate, play back the recorded speech sample with the new gain")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("long-running")
+                .long("long-running")
+                .help("Stay running with a pre-warmed STT connection; start muted and toggle via stdin (mute/unmute/toggle/quit) or SIGUSR1")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("device-name")
+                .long("device-name")
+                .help("Name of the virtual keyboard device")
+                .value_name("NAME"),
+        )
+        .arg(
+            Arg::new("device-vendor")
+                .long("device-vendor")
+                .help("USB-style vendor id of the virtual keyboard, in hex (default 0x1234)")
+                .value_name("ID"),
+        )
+        .arg(
+            Arg::new("device-product")
+                .long("device-product")
+                .help("Product id of the virtual keyboard, in hex (default 0x5678)")
+                .value_name("ID"),
+        )
+        .arg(
+            Arg::new("device-bus")
+                .long("device-bus")
+                .help("Bus type of the virtual keyboard: usb, bluetooth, virtual, pci, i8042, i2c or a hex value (default usb)")
+                .value_name("BUS"),
+        )
+        .arg(
+            Arg::new("layout")
+                .long("layout")
+                .help("XKB keyboard layout to type with, e.g. de or fr (default: detected from the system)")
+                .value_name("LAYOUT"),
+        )
+        .arg(
+            Arg::new("variant")
+                .long("variant")
+                .help("XKB layout variant, e.g. nodeadkeys or dvorak")
+                .value_name("VARIANT"),
+        )
+        .arg(
+            Arg::new("no-transliterate")
+                .long("no-transliterate")
+                .help("Skip untypeable characters instead of replacing them with ASCII equivalents")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("unicode-input")
+                .long("unicode-input")
+                .help("How to type characters missing from the US keymap: auto, none, ctrl-shift-u (GTK/IBus) or compose")
+                .value_name("METHOD"),
+        )
+        .arg(
+            Arg::new("compose-key")
+                .long("compose-key")
+                .help("Key configured as Compose for --unicode-input compose: menu, rightalt, rightctrl, rightmeta, leftmeta, capslock, scrolllock or pause (default menu)")
+                .value_name("KEY"),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .value_name("BACKEND")
+                .help("How keys are sent: auto, uinput, xtest, wayland, wtype, ydotool, xdotool or stdout"),
+        )
+        .arg(
+            Arg::new("output-mode")
+                .long("output-mode")
+                .value_name("MODE")
+                .help("How text is entered: type (as you speak) or paste (each finished turn via the clipboard)"),
+        )
+        .arg(
+            Arg::new("paste-combo")
+                .long("paste-combo")
+                .value_name("KEYS")
+                .help("Shortcut used in paste mode, e.g. ctrl+v, ctrl+shift+v or shift+insert"),
+        )
+        .arg(
+            Arg::new("cadence")
+                .long("cadence")
+                .value_name("SPEED")
+                .help("Typing speed: fast (native apps), normal, slow (remote desktops, VMs) or CHAR_MS,BACKSPACE_MS,BATCH_SIZE"),
+        )
+        .arg(
+            Arg::new("health-beep")
+                .long("health-beep")
+                .help("Play a warning beep when the microphone looks muted, clips or is too noisy")
+                .action(clap::ArgAction::SetTrue),
+        )
         .get_matches();
 
-    let device_name = "Voice Keyboard";
+    if let Some(text) = matches.get_one::<String>("test-rules") {
+        // Nothing to type, so read the invoking user's config and stop there
+        original_user
+            .drop_privileges()
+            .context("Failed to drop root privileges")?;
+        return test_rules(&Config::load()?, text);
+    }
+
+    let audio_prefs = AudioPreferences {
+        sample_format: matches
+            .get_one::<String>("sample-format")
+            .map(|s| audio_input::parse_sample_format(s))
+            .transpose()?,
+        sample_rate: matches.get_one::<u32>("sample-rate").copied(),
+    };
+
+    let compose_key = matches
+        .get_one::<String>("compose-key")
+        .map(|key| unicode_input::parse_compose_key(key))
+        .transpose()?
+        .unwrap_or(input_event::KEY_COMPOSE);
+    let unicode_input = match matches.get_one::<String>("unicode-input") {
+        Some(mode) if mode != "auto" => UnicodeInput::parse(mode, compose_key)?,
+        _ => UnicodeInput::detect(),
+    };
+
+    let mut identity = DeviceIdentity::default();
+    if let Some(name) = matches.get_one::<String>("device-name") {
+        identity.name = name.clone();
+    }
+    if let Some(vendor) = matches.get_one::<String>("device-vendor") {
+        identity.vendor = virtual_keyboard::parse_device_id(vendor)?;
+    }
+    if let Some(product) = matches.get_one::<String>("device-product") {
+        identity.product = virtual_keyboard::parse_device_id(product)?;
+    }
+    if let Some(bus) = matches.get_one::<String>("device-bus") {
+        identity.bustype = virtual_keyboard::parse_bus_type(bus)?;
+    }
+
+    let backend = match matches.get_one::<String>("output") {
+        Some(backend) if backend != "auto" => OutputBackend::parse(backend)?,
+        _ => OutputBackend::detect(),
+    };
+    info!("Using the {} output backend", backend.name());
 
     // Step 1: Create virtual keyboard while we have root privileges
-    debug!("Creating virtual keyboard device (requires root privileges)...");
-    let hardware =
-        RealKeyboardHardware::new(device_name).context("Failed to create keyboard hardware")?;
-    let mut keyboard = VirtualKeyboard::new(hardware);
-    let voice_enter_enabled = matches.get_flag("voice-enter");
-    let uppercase_enabled = matches.get_flag("uppercase");
-    keyboard.set_voice_enter_enabled(voice_enter_enabled);
-    keyboard.set_uppercase_enabled(uppercase_enabled);
-    debug!("Virtual keyboard created successfully");
+    let mut uinput = None;
+    if backend == OutputBackend::Uinput {
+        debug!("Creating virtual keyboard device (requires root privileges)...");
+        let mut hardware =
+            RealKeyboardHardware::new(&identity).context("Failed to create keyboard hardware")?;
+        hardware.set_unicode_input(unicode_input);
+        uinput = Some(hardware);
+        debug!("Virtual keyboard created successfully");
+    }
 
     // Step 2: Drop root privileges before initializing audio
     original_user
         .drop_privileges()
         .context("Failed to drop root privileges")?;
 
-    if matches.get_flag("test-audio") {
-        test_audio().await?;
+    // Load settings as the original user so paths resolve to their home
+    let config = Config::load().unwrap_or_else(|e| {
+        warn!("Failed to load config, using defaults: {}", e);
+        Config::default()
+    });
+    let profile = matches
+        .get_one::<String>("profile")
+        .or(config.profile.as_ref());
+    let text_settings = TextSettings::for_profile(&config, profile.map(|p| p.as_str()))?;
+    if let Some(profile) = profile {
+        info!("Using the '{}' profile", profile);
+    }
+
+    // Resolve the keyboard layout as the original user, whose session the
+    // typed text goes to
+    let layout = matches
+        .get_one::<String>("layout")
+        .or(config.keyboard_layout.as_ref())
+        .map(|layout| {
+            let variant = matches
+                .get_one::<String>("variant")
+                .or(config.keyboard_variant.as_ref());
+            LayoutSpec::new(layout, variant.map(|v| v.as_str()))
+        })
+        .or_else(LayoutSpec::detect);
+    // Only uinput sends physical keys; the other backends type characters
+    if let Some(hardware) = uinput.as_mut() {
+        match layout {
+            Some(layout) => hardware.set_keymap(Keymap::load(&layout)),
+            None => debug!("Could not determine the keyboard layout; assuming US"),
+        }
+    }
+    let shortcut_keymap = uinput
+        .as_ref()
+        .map_or_else(Keymap::us, |hardware| hardware.keymap().clone());
+
+    let hardware: Box<dyn KeyboardHardware + Send> = match uinput {
+        Some(hardware) => Box::new(hardware),
+        None => backend.create()?,
+    };
+    let mut keyboard = VirtualKeyboard::new(hardware);
+    let cadence = matches
+        .get_one::<String>("cadence")
+        .unwrap_or(&config.cadence);
+    keyboard
+        .hardware_mut()
+        .set_cadence(Cadence::parse(cadence)?);
+    keyboard.set_voice_enter_enabled(matches.get_flag("voice-enter"));
+    keyboard.set_triggers(Triggers::new(&config.trigger_phrases, &shortcut_keymap)?);
+    keyboard.set_turn_formatting_enabled(
+        text_settings.format_turns && !matches.get_flag("no-format-turns"),
+    );
+    keyboard.set_voice_undo_enabled(text_settings.voice_undo || matches.get_flag("voice-undo"));
+    keyboard.set_max_undo_chars(config.max_undo_chars);
+    keyboard.set_uppercase_enabled(matches.get_flag("uppercase"));
+    keyboard.set_code_mode_enabled(text_settings.code_mode || matches.get_flag("code-mode"));
+    keyboard.set_inverse_text_normalization_enabled(
+        text_settings.inverse_text_normalization || matches.get_flag("itn"),
+    );
+    let rules = ReplacementRules::new(&config.replacements).unwrap_or_else(|e| {
+        warn!("Ignoring replacement rules: {:#}", e);
+        ReplacementRules::default()
+    });
+    keyboard.set_replacements(Some(match Config::config_path() {
+        Ok(path) => Replacements::watch(rules, path),
+        Err(_) => Replacements::fixed(rules),
+    }));
+    let mut snippets = Config::config_dir()
+        .and_then(|dir| Snippets::load(&dir.join("snippets"), &config.snippets))
+        .unwrap_or_else(|e| {
+            warn!("Ignoring snippet files: {:#}", e);
+            Snippets::new(&config.snippets)
+        });
+    if !snippets.is_empty() {
+        info!("Loaded {} snippets", snippets.len());
+        if let Some(tool) = ClipboardTool::detect() {
+            snippets.set_clipboard(Box::new(CommandClipboard::new(tool)));
+        }
+        keyboard.set_snippets(Some(snippets));
+    }
+    if text_settings.spoken_punctuation || matches.get_flag("spoken-punctuation") {
+        keyboard.set_spoken_punctuation(Some(SpokenPunctuation::new(&config.punctuation_commands)));
+    }
+
+    keyboard.set_transliterator(Transliterator::new(
+        config.transliterate && !matches.get_flag("no-transliterate"),
+        &config.transliterations,
+    ));
+
+    let output_mode = matches
+        .get_one::<String>("output-mode")
+        .unwrap_or(&config.output_mode);
+    match output_mode.as_str() {
+        "type" => {}
+        "paste" => {
+            if backend == OutputBackend::Stdout {
+                anyhow::bail!(
+                    "Paste mode presses a shortcut that the stdout backend cannot send; use --output-mode type"
+                );
+            }
+            let combo = matches
+                .get_one::<String>("paste-combo")
+                .unwrap_or(&config.paste_combo);
+            let combo = clipboard::parse_key_combo(combo, &shortcut_keymap)?;
+            let clipboard = CommandClipboard::detect()?;
+            info!("Pasting finished turns via {:?}", clipboard.tool());
+            keyboard.set_paste_output(Some(PasteOutput::new(
+                Box::new(clipboard),
+                combo,
+                clipboard::RESTORE_DELAY,
+            )));
+        }
+        other => anyhow::bail!("Unknown output mode '{}': expected type or paste", other),
+    }
+
+    let capture = CaptureOptions {
+        prefs: audio_prefs,
+        health_beep: matches.get_flag("health-beep"),
+        profiles: config.audio_profiles.clone(),
+    };
+
+    let long_running = matches.get_flag("long-running");
+
+    if matches.get_flag("calibrate") {
+        calibrate(&capture, matches.get_flag("playback")).await?;
+    } else if matches.get_flag("test-audio") {
+        test_audio(&capture).await?;
     } else if matches.get_flag("test-stt") {
         let stt_url = matches
             .get_one::<String>("stt-url")
             .map(|s| s.as_str())
             .unwrap_or(stt_client::STT_URL);
-        test_stt(keyboard, stt_url).await?;
+        test_stt(keyboard, stt_url, &capture, long_running).await?;
     } else {
         let debug_mode = matches.get_flag("debug-stt");
         let stt_url = matches
@@ -186,16 +583,43 @@ async fn main() -> Result<()> {
             .unwrap_or(stt_client::STT_URL);
 
         if debug_mode {
-            debug_stt(stt_url).await?;
+            debug_stt(stt_url, &capture, long_running).await?;
         } else {
-            test_stt(keyboard, stt_url).await?;
+            test_stt(keyboard, stt_url, &capture, long_running).await?;
         }
     }
 
     Ok(())
 }
 
-async fn test_audio() -> Result<()> {
+fn test_rules(config: &Config, text: &str) -> Result<()> {
+    let rules = ReplacementRules::new(&config.replacements)?;
+    if rules.rules().is_empty() {
+        println!(
+            "No replacement rules in {}",
+            Config::config_path()?.display()
+        );
+    }
+
+    let mut current = text.to_string();
+    for rule in rules.rules() {
+        let next = rule.apply(&current);
+        if next != current {
+            println!(
+                "'{}' -> '{}': {}",
+                rule.rule().pattern,
+                rule.rule().replacement,
+                next
+            );
+            current = next;
+        }
+    }
+    println!("Input:  {}", text);
+    println!("Output: {}", current);
+    Ok(())
+}
+
+async fn test_audio(capture: &CaptureOptions) -> Result<()> {
     info!("Testing audio input...");
 
     // List available devices
@@ -206,26 +630,44 @@ async fn test_audio() -> Result<()> {
     }
 
     // Create audio input
-    let mut audio_input = AudioInput::new()?;
-    debug!(
-        "Using audio device with {} channels at {} Hz",
+    let mut audio_input = AudioInput::with_preferences(&capture.prefs)?;
+    info!("Supported configs for the selected device:");
+    for config in audio_input.describe_supported_configs()? {
+        info!("  {}", config);
+    }
+    info!(
+        "Using audio device with {} channels at {} Hz ({} samples)",
         audio_input.get_channels(),
-        audio_input.get_sample_rate()
+        audio_input.get_sample_rate(),
+        audio_input.get_sample_format()
     );
 
     // Test recording for 5 seconds
     let (tx, rx) = mpsc::channel();
 
     audio_input.start_recording(move |data| {
-        let level = data.iter().map(|&x| x.abs()).sum::<f32>() / data.len() as f32;
-        let _ = tx.send(level);
+        let _ = tx.send(data.to_vec());
     })?;
 
+    let channels = audio_input.get_channels();
+    let mut monitor = HealthMonitor::new(audio_input.get_sample_rate());
+    let mut mono = Vec::new();
+
     info!("Recording for 5 seconds...");
     let start = std::time::Instant::now();
 
     while start.elapsed() < Duration::from_secs(5) {
-        if let Ok(level) = rx.try_recv() {
+        let mut latest_level = None;
+        while let Ok(data) = rx.try_recv() {
+            latest_level = Some(data.iter().map(|&x| x.abs()).sum::<f32>() / data.len() as f32);
+
+            mono.clear();
+            audio_pipeline::downmix_to_mono(&data, channels, &mut mono);
+            for event in monitor.process(&mono) {
+                report_health_event(event, capture.health_beep);
+            }
+        }
+        if let Some(level) = latest_level {
             let bar_length = (level * 50.0) as usize;
             let bar: String = "#".repeat(bar_length);
             info!("Level: {:.2} [{}]", level, bar);
@@ -237,17 +679,79 @@ async fn test_audio() -> Result<()> {
     Ok(())
 }
 
-async fn test_stt(keyboard: VirtualKeyboard<RealKeyboardHardware>, stt_url: &str) -> Result<()> {
+async fn calibrate(capture: &CaptureOptions, playback: bool) -> Result<()> {
+    info!("Calibrating audio input...");
+
+    let mut audio_input = AudioInput::with_preferences(&capture.prefs)?;
+    let sample_rate = audio_input.get_sample_rate();
+    let device_name = audio_input.device_name().to_string();
+    info!("Calibrating device: {}", device_name);
+
+    info!("Step 1/2: stay quiet for 3 seconds to measure background noise...");
+    thread::sleep(Duration::from_secs(1));
+    let noise = calibration::record_mono(&mut audio_input, Duration::from_secs(3))?;
+
+    info!("Step 2/2: speak normally for 5 seconds (e.g. read a sentence aloud)...");
+    thread::sleep(Duration::from_secs(1));
+    let speech = calibration::record_mono(&mut audio_input, Duration::from_secs(5))?;
+
+    let result = Calibration::from_levels(
+        &calibration::block_levels(&noise, sample_rate),
+        &calibration::block_levels(&speech, sample_rate),
+    )?;
+
+    info!("Noise floor:   {:.1} dBFS", result.noise_floor_dbfs);
+    info!("Speech level:  {:.1} dBFS", result.speech_level_dbfs);
+    info!(
+        "Recommended gain: {:.2}x ({:+.1} dB)",
+        result.gain,
+        20.0 * result.gain.log10()
+    );
+    info!("VAD threshold: {:.1} dBFS", result.vad_threshold_dbfs);
+
+    if playback {
+        info!("Playing back the speech sample with the recommended gain...");
+        let mut sample = speech;
+        audio_pipeline::apply_gain(&mut sample, result.gain);
+        let (_stream, handle) =
+            rodio::OutputStream::try_default().context("Failed to open audio output")?;
+        let sink = rodio::Sink::try_new(&handle).context("Failed to create audio sink")?;
+        sink.append(rodio::buffer::SamplesBuffer::new(1, sample_rate, sample));
+        sink.sleep_until_end();
+    }
+
+    // Re-read the file rather than saving the startup config, which falls
+    // back to defaults when the file does not parse
+    let mut config = Config::load().context("Failed to load config, calibration not saved")?;
+    config
+        .audio_profiles
+        .insert(device_name.clone(), result.to_profile());
+    config.save().context("Failed to save calibration")?;
+    info!(
+        "Saved calibration for '{}' to {}",
+        device_name,
+        Config::config_path()?.display()
+    );
+
+    Ok(())
+}
+
+async fn test_stt(
+    keyboard: VirtualKeyboard<Box<dyn KeyboardHardware + Send>>,
+    stt_url: &str,
+    capture: &CaptureOptions,
+    long_running: bool,
+) -> Result<()> {
     info!("Testing speech-to-text functionality...");
 
-    // Wrap keyboard in a mutex to allow mutable access from the closure
-    let keyboard = std::sync::Arc::new(std::sync::Mutex::new(keyboard));
-    let keyboard_clone = keyboard.clone();
+    // Type on a worker thread so slow keystrokes never stall the receive task
+    let (typing, typing_worker) = typing_worker::spawn_typing_worker(keyboard);
+    let typing_clone = typing.clone();
     // Rate-limit plain Update logs to at most once per second
     let last_update_log = std::sync::Arc::new(std::sync::Mutex::new(None::<Instant>));
     let last_update_log_cloned = last_update_log.clone();
 
-    run_stt(stt_url, move |result| {
+    let result = run_stt(stt_url, capture, long_running, move |result| {
         if !result.transcript.is_empty() {
             if result.event == "Update" {
                 let now = Instant::now();
@@ -266,33 +770,25 @@ async fn test_stt(keyboard: VirtualKeyboard<RealKeyboardHardware>, stt_url: &str
             }
         }
 
-        let mut kb = keyboard_clone.lock().unwrap();
-
         // Handle different event types
         match result.event.as_str() {
-            "EndOfTurn" => {
-                // Finalize the transcript; treat failure as fatal
-                if let Err(e) = kb.finalize_transcript() {
-                    error!("Failed to finalize transcript: {}", e);
-                    std::process::exit(1);
-                }
-            }
-            _ => {
-                // Handle incremental updates; treat failure as fatal
-                if let Err(e) = kb.update_transcript(&result.transcript) {
-                    error!("Failed to update transcript: {}", e);
-                    std::process::exit(1);
-                }
-            }
+            "EndOfTurn" => typing_clone.end_of_turn(),
+            _ => typing_clone.update(result.turn_index, &result.transcript),
         }
-    }).await
+    })
+    .await;
+
+    // Let the worker type whatever is still queued before returning
+    typing.close();
+    let _ = typing_worker.join();
+    result
 }
 
-async fn debug_stt(stt_url: &str) -> Result<()> {
+async fn debug_stt(stt_url: &str, capture: &CaptureOptions, long_running: bool) -> Result<()> {
     info!("Debugging speech-to-text functionality...");
     info!("STT Service URL: {}", stt_url);
 
-    run_stt(stt_url, |result| {
+    run_stt(stt_url, capture, long_running, |result| {
         // Only show non-empty transcriptions
         if !result.transcript.is_empty() {
             info!("Transcription [{}]: {}", result.event, result.transcript);
@@ -301,18 +797,22 @@ async fn debug_stt(stt_url: &str) -> Result<()> {
     .await
 }
 
-async fn run_stt<F>(stt_url: &str, on_transcription: F) -> Result<()>
+async fn run_stt<F>(
+    stt_url: &str,
+    capture: &CaptureOptions,
+    long_running: bool,
+    on_transcription: F,
+) -> Result<()>
 where
-    F: Fn(stt_client::TranscriptionResult) + Send + 'static,
+    F: Fn(stt_client::TranscriptionResult) + Send + Sync + 'static,
 {
-    let mut audio_input = AudioInput::new()?;
-    debug!(
-        "Using audio device with {} channels at {} Hz",
-        audio_input.get_channels(),
-        audio_input.get_sample_rate()
-    );
+    if long_running {
+        return run_stt_long_running(stt_url, capture, on_transcription).await;
+    }
 
-    let mut audio_buffer = AudioBuffer::new(audio_input.get_sample_rate(), 160);
+    // Start capturing straight away and queue audio locally while the
+    // TLS/WebSocket handshake runs, so the first words are not lost.
+    let (audio_input, connection_ready) = start_capture(capture, Arc::new(AtomicBool::new(false)))?;
     let stt_client = SttClient::new(stt_url, audio_input.get_sample_rate());
 
     info!(?stt_url, "Connecting to STT service...");
@@ -320,42 +820,205 @@ where
         .connect_and_transcribe(on_transcription)
         .await
         .context("Failed to connect to STT service")?;
+    // The worker only drops its end if it has already exited
+    let _ = connection_ready.send(audio_tx);
+    drop(connection_ready);
 
     info!("Listening for speech... Speak into your microphone!");
     info!("Press Ctrl+C to stop.");
 
-    let audio_tx = std::sync::Arc::new(audio_tx);
-    let audio_tx_clone = audio_tx.clone();
-    let channels = audio_input.get_channels();
+    // Just wait for the STT client to finish (will be interrupted by Ctrl+C)
+    let result = handle.await?;
+    drop(audio_input);
+    METRICS.log_summary();
+    result?;
 
-    // Start recording
-    audio_input.start_recording(move |data| {
-        debug!("Received audio data: {} samples", data.len());
+    Ok(())
+}
+
+/// Keep a connection open across mute/unmute cycles so that unmuting starts
+/// transcription without a fresh TLS/WebSocket handshake. The idle socket is
+/// kept alive with KeepAlive messages and reopened whenever it closes.
+async fn run_stt_long_running<F>(
+    stt_url: &str,
+    capture: &CaptureOptions,
+    on_transcription: F,
+) -> Result<()>
+where
+    F: Fn(stt_client::TranscriptionResult) + Send + Sync + 'static,
+{
+    let muted = Arc::new(AtomicBool::new(true));
+    let (audio_input, connection_ready) = start_capture(capture, muted.clone())?;
+    let stt_client = SttClient::new(stt_url, audio_input.get_sample_rate());
+    let on_transcription = Arc::new(on_transcription);
+
+    let (control_tx, mut control_rx) = tokio::sync::mpsc::unbounded_channel();
+    control::spawn_listeners(control_tx)?;
+
+    info!("Long-running mode: microphone muted, STT connection kept warm");
+    info!("Type mute/unmute/toggle/quit on stdin or send SIGUSR1 to toggle.");
+    emit_status(StatusLevel::Info, "muted", "Microphone muted");
 
-        // Average stereo channels to mono
-        let mono_data: Vec<f32> = if channels == 2 {
-            let mut mono = Vec::with_capacity(data.len() / 2);
-            for chunk in data.chunks_exact(2) {
-                mono.push((chunk[0] + chunk[1]) / 2.0);
+    let connection_loop = async {
+        let mut backoff = RECONNECT_MIN_DELAY;
+        loop {
+            info!(?stt_url, "Connecting to STT service...");
+            let callback = on_transcription.clone();
+            let connected_at = Instant::now();
+            match stt_client
+                .connect_and_transcribe(move |result| callback(result))
+                .await
+            {
+                Ok((audio_tx, handle)) => {
+                    info!("STT connection ready");
+                    if connection_ready.send(audio_tx).is_err() {
+                        anyhow::bail!("Audio worker stopped");
+                    }
+                    match handle.await? {
+                        Ok(()) => info!("STT connection closed by server; reconnecting"),
+                        Err(e) => warn!("STT connection lost: {:#}; reconnecting", e),
+                    }
+                    // Only back off when connections keep dropping straight away
+                    if connected_at.elapsed() >= RECONNECT_STABLE_AFTER {
+                        backoff = RECONNECT_MIN_DELAY;
+                        continue;
+                    }
+                }
+                Err(e) => warn!("Failed to connect to STT service: {:#}", e),
             }
-            debug!("Averaged samples: {}", mono.len());
-            mono
-        } else {
-            data.to_vec()
-        };
 
-        // Create audio chunks and send them
-        let chunks = audio_buffer.add_samples(&mono_data);
-        for chunk in chunks {
-            debug!("Sending audio chunk: {} bytes", chunk.len());
-            if let Err(e) = audio_tx_clone.blocking_send(chunk) {
-                error!("Failed to send audio chunk: {}", e);
+            info!("Retrying in {:?}", backoff);
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(RECONNECT_MAX_DELAY);
+        }
+    };
+
+    let control_loop = async {
+        while let Some(command) = control_rx.recv().await {
+            if command == control::Control::Quit {
+                break;
             }
+            let was_muted = muted.load(Ordering::Relaxed);
+            let now_muted = command.apply(was_muted);
+            muted.store(now_muted, Ordering::Relaxed);
+            if now_muted != was_muted {
+                if now_muted {
+                    info!("Microphone muted");
+                    emit_status(StatusLevel::Info, "muted", "Microphone muted");
+                } else {
+                    info!("Microphone live");
+                    emit_status(StatusLevel::Info, "listening", "Listening");
+                }
+            }
+        }
+    };
+
+    let result = tokio::select! {
+        result = connection_loop => result,
+        _ = control_loop => {
+            info!("Quit requested; stopping");
+            Ok(())
         }
+    };
+
+    drop(audio_input);
+    METRICS.log_summary();
+    result
+}
+
+/// Open the input device and start the capture pipeline.
+///
+/// The capture callback only copies samples into a lock-free ring buffer;
+/// mono conversion, PCM encoding and the (possibly blocking) channel send
+/// happen on the worker thread instead of the real-time audio thread. Audio
+/// is queued until an STT connection sender is passed through the returned
+/// handle, and discarded while `muted` is set.
+fn start_capture(
+    capture: &CaptureOptions,
+    muted: Arc<AtomicBool>,
+) -> Result<(AudioInput, mpsc::Sender<tokio::sync::mpsc::Sender<Vec<u8>>>)> {
+    let mut audio_input = AudioInput::with_preferences(&capture.prefs)?;
+    debug!(
+        "Using audio device with {} channels at {} Hz ({} samples)",
+        audio_input.get_channels(),
+        audio_input.get_sample_rate(),
+        audio_input.get_sample_format()
+    );
+
+    let sample_rate = audio_input.get_sample_rate();
+    let channels = audio_input.get_channels();
+
+    let profile = capture.profile_for(audio_input.device_name());
+    let mut thresholds = HealthThresholds::default();
+    let mut gain = 1.0;
+    match profile {
+        Some(profile) => {
+            info!(
+                "Using calibrated profile: gain {:.2}x, VAD threshold {:.1} dBFS",
+                profile.gain, profile.vad_threshold_dbfs
+            );
+            gain = profile.gain;
+            thresholds.speech_threshold_dbfs = Some(profile.vad_threshold_dbfs);
+        }
+        None => debug!("No calibration profile for this device; run --calibrate to create one"),
+    }
+
+    let (mut producer, consumer) = audio_pipeline::capture_ring(sample_rate, channels);
+    let mut monitor = HealthMonitor::with_thresholds(sample_rate, thresholds);
+    let health_beep = capture.health_beep;
+    let worker_config = WorkerConfig {
+        channels,
+        sample_rate,
+        gain,
+    };
+    let (mut sink, connection_ready) = BufferedSender::new(StartupQueue::for_startup());
+    audio_pipeline::spawn_audio_worker(
+        consumer,
+        worker_config,
+        move |mono| {
+            for event in monitor.process(mono) {
+                report_health_event(event, health_beep);
+            }
+        },
+        move |chunk| {
+            if muted.load(Ordering::Relaxed) {
+                return true;
+            }
+            debug!("Sending audio chunk: {} bytes", chunk.len());
+            sink.send(chunk)
+        },
+    );
+
+    audio_input.start_recording(move |data| {
+        producer.push_slice(data);
     })?;
 
-    // Just wait for the STT client to finish (will be interrupted by Ctrl+C)
-    handle.await??;
+    Ok((audio_input, connection_ready))
+}
 
-    Ok(())
+/// Surface a microphone health change in the log and on the GUI status channel
+fn report_health_event(event: HealthEvent, beep: bool) {
+    match event {
+        HealthEvent::Raised(warning) => {
+            warn!("{}", warning);
+            emit_status(StatusLevel::Warning, warning.code(), &warning.to_string());
+            if beep {
+                audio_health::play_warning_beep();
+            }
+        }
+        HealthEvent::Cleared(warning) => {
+            info!("Microphone warning cleared: {}", warning.code());
+            emit_status(StatusLevel::Info, "mic-ok", "Microphone signal looks healthy");
+        }
+    }
+}
+
+/// Write a status line to stderr for the GUI
+fn emit_status(level: StatusLevel, code: &str, message: &str) {
+    let line = StatusLine {
+        level,
+        code: code.to_string(),
+        message: message.to_string(),
+    };
+    eprintln!("{}", line);
 }
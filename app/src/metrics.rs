@@ -0,0 +1,52 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::info;
+
+/// Process-wide counters for the audio pipeline.
+///
+/// All fields are plain atomics so they can be updated from any thread
+/// without locking.
+pub struct Metrics {
+    /// Capture callbacks whose samples were dropped because the ring buffer was full
+    pub audio_overruns: AtomicU64,
+    /// Samples lost to ring buffer overruns
+    pub audio_samples_dropped: AtomicU64,
+    /// PCM chunks produced for the STT connection, including queued startup chunks
+    pub audio_chunks_sent: AtomicU64,
+    /// Time between capture starting and the STT connection accepting audio
+    pub startup_gap_ms: AtomicU64,
+    /// Chunks captured while the STT connection was being set up
+    pub startup_chunks_queued: AtomicU64,
+    /// Startup chunks discarded because the startup queue was full
+    pub startup_chunks_dropped: AtomicU64,
+}
+
+pub static METRICS: Metrics = Metrics::new();
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            audio_overruns: AtomicU64::new(0),
+            audio_samples_dropped: AtomicU64::new(0),
+            audio_chunks_sent: AtomicU64::new(0),
+            startup_gap_ms: AtomicU64::new(0),
+            startup_chunks_queued: AtomicU64::new(0),
+            startup_chunks_dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Log the current value of every counter
+    pub fn log_summary(&self) {
+        info!(
+            "Audio metrics: chunks_sent={}, overruns={}, samples_dropped={}",
+            self.audio_chunks_sent.load(Ordering::Relaxed),
+            self.audio_overruns.load(Ordering::Relaxed),
+            self.audio_samples_dropped.load(Ordering::Relaxed),
+        );
+        info!(
+            "Startup metrics: gap_ms={}, chunks_queued={}, chunks_dropped={}",
+            self.startup_gap_ms.load(Ordering::Relaxed),
+            self.startup_chunks_queued.load(Ordering::Relaxed),
+            self.startup_chunks_dropped.load(Ordering::Relaxed),
+        );
+    }
+}
@@ -0,0 +1,613 @@
+//! Keyboard backends that do not need write access to `/dev/uinput`.
+//!
+//! The uinput device needs root, which is not available on every machine.
+//! These backends use the XTEST extension on X11 or the virtual keyboard
+//! protocol on wlroots-based Wayland compositors, drive an existing tool
+//! (`wtype` on Wayland compositors with the virtual keyboard protocol,
+//! `xdotool` on X11, `ydotool` through its daemon) or print the text to
+//! stdout, so the rest of the pipeline stays the same.
+
+use anyhow::{bail, Context, Result};
+use nix::unistd::{access, AccessFlags};
+use std::env;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+use tracing::{debug, warn};
+use xkeysym::Keysym;
+
+use crate::cadence::Cadence;
+use crate::input_event::*;
+use crate::virtual_keyboard::{KeyboardHardware, TypedChar};
+use crate::wayland_keyboard::{self, WaylandKeyboardHardware};
+use crate::xtest::XTestKeyboardHardware;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputBackend {
+    /// Virtual keyboard created through `/dev/uinput` (needs root)
+    Uinput,
+    /// X11 XTEST extension, no external tools needed
+    Xtest,
+    /// `zwp_virtual_keyboard_v1` on wlroots-based compositors
+    Wayland,
+    Wtype,
+    Ydotool,
+    Xdotool,
+    /// Print the text instead of typing it
+    Stdout,
+}
+
+impl OutputBackend {
+    /// Parse a backend name: `uinput`, `xtest`, `wayland`, `wtype`,
+    /// `ydotool`, `xdotool` or `stdout`
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "uinput" => Ok(OutputBackend::Uinput),
+            "xtest" => Ok(OutputBackend::Xtest),
+            "wayland" => Ok(OutputBackend::Wayland),
+            "wtype" => Ok(OutputBackend::Wtype),
+            "ydotool" => Ok(OutputBackend::Ydotool),
+            "xdotool" => Ok(OutputBackend::Xdotool),
+            "stdout" => Ok(OutputBackend::Stdout),
+            other => bail!(
+                "Unknown output backend '{}': expected uinput, xtest, wayland, wtype, ydotool, xdotool or stdout",
+                other
+            ),
+        }
+    }
+
+    /// Pick a backend for the current session
+    pub fn detect() -> Self {
+        let uinput = access("/dev/uinput", AccessFlags::W_OK).is_ok();
+        Self::detect_from(
+            |name| env::var(name).ok(),
+            find_in_path,
+            uinput,
+            wayland_keyboard::is_supported,
+        )
+    }
+
+    /// Prefer uinput when it can be opened, then the protocol of the
+    /// session: XTEST on X11 or the virtual keyboard protocol on Wayland
+    /// compositors that offer it. Otherwise use ydotool, which works
+    /// everywhere its daemon runs, or print the text. `virtual_keyboard` is
+    /// only asked on Wayland.
+    pub fn detect_from<V, F, K>(var: V, installed: F, uinput: bool, virtual_keyboard: K) -> Self
+    where
+        V: Fn(&str) -> Option<String>,
+        F: Fn(&str) -> bool,
+        K: FnOnce() -> bool,
+    {
+        if uinput {
+            return OutputBackend::Uinput;
+        }
+
+        let session = var("XDG_SESSION_TYPE").unwrap_or_default();
+        let set = |name: &str| var(name).is_some_and(|value| !value.is_empty());
+        let wayland = session == "wayland" || set("WAYLAND_DISPLAY");
+        let x11 = session == "x11" || (!wayland && set("DISPLAY"));
+
+        // wtype needs the same protocol, so it is no help without it
+        if wayland && virtual_keyboard() {
+            OutputBackend::Wayland
+        } else if x11 {
+            OutputBackend::Xtest
+        } else if installed("ydotool") {
+            OutputBackend::Ydotool
+        } else {
+            OutputBackend::Stdout
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputBackend::Uinput => "uinput",
+            OutputBackend::Xtest => "xtest",
+            OutputBackend::Wayland => "wayland",
+            OutputBackend::Wtype => "wtype",
+            OutputBackend::Ydotool => "ydotool",
+            OutputBackend::Xdotool => "xdotool",
+            OutputBackend::Stdout => "stdout",
+        }
+    }
+
+    /// Create the hardware for a backend other than uinput, which has to be
+    /// set up as root and is created separately
+    pub fn create(&self) -> Result<Box<dyn KeyboardHardware + Send>> {
+        match self {
+            OutputBackend::Uinput => bail!("The uinput backend is created separately"),
+            OutputBackend::Xtest => Ok(Box::new(XTestKeyboardHardware::connect(None)?)),
+            OutputBackend::Wayland => Ok(Box::new(WaylandKeyboardHardware::connect()?)),
+            OutputBackend::Wtype => Ok(Box::new(CommandKeyboardHardware::new(CommandTool::Wtype)?)),
+            OutputBackend::Ydotool => Ok(Box::new(CommandKeyboardHardware::new(
+                CommandTool::Ydotool,
+            )?)),
+            OutputBackend::Xdotool => Ok(Box::new(CommandKeyboardHardware::new(
+                CommandTool::Xdotool,
+            )?)),
+            OutputBackend::Stdout => Ok(Box::new(StdoutKeyboardHardware::new())),
+        }
+    }
+}
+
+/// Whether `program` is an executable file somewhere on `PATH`
+pub fn find_in_path(program: &str) -> bool {
+    env::var_os("PATH").is_some_and(|path| {
+        env::split_paths(&path).any(|dir| Path::new(&dir).join(program).is_file())
+    })
+}
+
+/// Keysym an evdev keycode produces on a US layout, for backends that
+/// send keysyms rather than keycodes
+pub fn evdev_keysym(keycode: u16) -> Option<Keysym> {
+    let keysym = match keycode {
+        KEY_LEFTCTRL => Keysym::Control_L,
+        KEY_LEFTSHIFT => Keysym::Shift_L,
+        KEY_LEFTALT => Keysym::Alt_L,
+        KEY_LEFTMETA => Keysym::Super_L,
+        KEY_INSERT => Keysym::Insert,
+        KEY_ESC => Keysym::Escape,
+        KEY_ENTER => Keysym::Return,
+        KEY_BACKSPACE => Keysym::BackSpace,
+        KEY_TAB => Keysym::Tab,
+        KEY_SPACE => Keysym::space,
+        _ => {
+            // Letters and digits
+            let c = ('a'..='z')
+                .chain('0'..='9')
+                .find(|&c| char_to_keycode(c) == Some((keycode, false)))?;
+            Keysym::from_char(c)
+        }
+    };
+    Some(keysym)
+}
+
+/// Keysym name and, for modifiers, the `wtype -M` name of a keycode
+fn key_names(keycode: u16) -> Option<(String, Option<&'static str>)> {
+    let name = evdev_keysym(keycode)?.name()?.trim_start_matches("XK_");
+    let modifier = match keycode {
+        KEY_LEFTCTRL => Some("ctrl"),
+        KEY_LEFTSHIFT => Some("shift"),
+        KEY_LEFTALT => Some("alt"),
+        KEY_LEFTMETA => Some("logo"),
+        _ => None,
+    };
+    Some((name.to_string(), modifier))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandTool {
+    Wtype,
+    Ydotool,
+    Xdotool,
+}
+
+impl CommandTool {
+    fn program(&self) -> &'static str {
+        match self {
+            CommandTool::Wtype => "wtype",
+            CommandTool::Ydotool => "ydotool",
+            CommandTool::Xdotool => "xdotool",
+        }
+    }
+
+    /// ydotool types through its own US table, so it only handles ASCII;
+    /// wtype and xdotool type any character through a temporary keymap
+    fn can_type(&self, c: char) -> bool {
+        match self {
+            CommandTool::Ydotool => c.is_ascii() && (!c.is_ascii_control() || c == '\n'),
+            CommandTool::Wtype | CommandTool::Xdotool => true,
+        }
+    }
+
+    /// Option that waits `delay` between keys, for the tool's command
+    fn delay_args(&self, delay: Option<Duration>) -> Vec<String> {
+        let Some(delay) = delay else {
+            return Vec::new();
+        };
+        let option = match self {
+            CommandTool::Wtype => "-d",
+            CommandTool::Ydotool => "--key-delay",
+            CommandTool::Xdotool => "--delay",
+        };
+        vec![option.to_string(), delay.as_millis().to_string()]
+    }
+
+    fn type_args(&self, text: &str, delay: Option<Duration>) -> Vec<String> {
+        let mut args = match self {
+            CommandTool::Wtype => vec![],
+            CommandTool::Ydotool | CommandTool::Xdotool => vec!["type".to_string()],
+        };
+        args.extend(self.delay_args(delay));
+        // Text starting with '-' must not be taken for an option
+        args.push("--".to_string());
+        args.push(text.to_string());
+        args
+    }
+
+    /// Arguments that press backspace `count` times in one run
+    fn backspace_args(&self, count: usize, delay: Option<Duration>) -> Vec<String> {
+        let mut args = match self {
+            CommandTool::Wtype => vec![],
+            CommandTool::Ydotool | CommandTool::Xdotool => vec!["key".to_string()],
+        };
+        args.extend(self.delay_args(delay));
+        match self {
+            CommandTool::Ydotool => {
+                for _ in 0..count {
+                    args.extend([
+                        format!("{}:1", KEY_BACKSPACE),
+                        format!("{}:0", KEY_BACKSPACE),
+                    ]);
+                }
+            }
+            CommandTool::Xdotool => {
+                args.extend([
+                    "--repeat".to_string(),
+                    count.to_string(),
+                    "BackSpace".to_string(),
+                ]);
+            }
+            CommandTool::Wtype => {
+                for _ in 0..count {
+                    args.extend(["-k".to_string(), "BackSpace".to_string()]);
+                }
+            }
+        }
+        args
+    }
+
+    /// Arguments that press `keycodes` together and release them
+    fn combo_args(&self, keycodes: &[u16]) -> Result<Vec<String>> {
+        match self {
+            // ydotool takes evdev keycodes with 1 for press and 0 for release
+            CommandTool::Ydotool => {
+                let mut args = vec!["key".to_string()];
+                args.extend(keycodes.iter().map(|k| format!("{}:1", k)));
+                args.extend(keycodes.iter().rev().map(|k| format!("{}:0", k)));
+                Ok(args)
+            }
+            CommandTool::Xdotool => {
+                let names = keycodes
+                    .iter()
+                    .map(|&k| key_names(k).map(|(name, _)| name))
+                    .collect::<Option<Vec<_>>>()
+                    .with_context(|| format!("xdotool cannot press keycodes {:?}", keycodes))?;
+                Ok(vec!["key".to_string(), names.join("+")])
+            }
+            // wtype holds modifiers with -M/-m and taps other keys with -k
+            CommandTool::Wtype => {
+                let mut args = Vec::new();
+                let mut held = Vec::new();
+                for &keycode in keycodes {
+                    let (keysym, modifier) = key_names(keycode)
+                        .with_context(|| format!("wtype cannot press keycode {}", keycode))?;
+                    match modifier {
+                        Some(modifier) => {
+                            args.extend(["-M".to_string(), modifier.to_string()]);
+                            held.push(modifier);
+                        }
+                        None => args.extend(["-k".to_string(), keysym]),
+                    }
+                }
+                for modifier in held.into_iter().rev() {
+                    args.extend(["-m".to_string(), modifier.to_string()]);
+                }
+                Ok(args)
+            }
+        }
+    }
+}
+
+/// [`KeyboardHardware`] that runs `wtype`, `ydotool` or `xdotool`
+pub struct CommandKeyboardHardware {
+    tool: CommandTool,
+    /// Pacing from `set_cadence`; the tool's own default until then
+    cadence: Option<Cadence>,
+}
+
+impl CommandKeyboardHardware {
+    pub fn new(tool: CommandTool) -> Result<Self> {
+        if !find_in_path(tool.program()) {
+            bail!("{} is not installed or not on PATH", tool.program());
+        }
+        Ok(Self {
+            tool,
+            cadence: None,
+        })
+    }
+
+    /// The cadence's pause per batch spread over the keys in a batch
+    fn key_delay(&self, delay: impl Fn(&Cadence) -> Duration) -> Option<Duration> {
+        self.cadence
+            .map(|cadence| delay(&cadence) / cadence.batch_size.max(1) as u32)
+    }
+
+    fn run(&self, args: &[String]) -> Result<()> {
+        debug!("Running {} {:?}", self.tool.program(), args);
+        let output = Command::new(self.tool.program())
+            .args(args)
+            .output()
+            .with_context(|| format!("Failed to run {}", self.tool.program()))?;
+        if !output.status.success() {
+            bail!(
+                "{} failed: {}",
+                self.tool.program(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+impl KeyboardHardware for CommandKeyboardHardware {
+    fn type_text(&mut self, text: &str) -> Result<Vec<TypedChar>> {
+        let typed: Vec<TypedChar> = text
+            .chars()
+            .map(|c| TypedChar {
+                intended: c,
+                emitted: self.tool.can_type(c),
+            })
+            .collect();
+        let supported: String = typed
+            .iter()
+            .filter(|t| t.emitted)
+            .map(|t| t.intended)
+            .collect();
+        let skipped: String = typed
+            .iter()
+            .filter(|t| !t.emitted)
+            .map(|t| t.intended)
+            .collect();
+        if !skipped.is_empty() {
+            warn!(
+                "{} cannot type '{}' in '{}'",
+                self.tool.program(),
+                skipped,
+                text
+            );
+        }
+
+        if !supported.is_empty() {
+            let delay = self.key_delay(|cadence| cadence.char_delay);
+            self.run(&self.tool.type_args(&supported, delay))?;
+        }
+        Ok(typed)
+    }
+
+    fn press_backspace(&mut self) -> Result<()> {
+        self.press_key(KEY_BACKSPACE)
+    }
+
+    fn press_enter(&mut self) -> Result<()> {
+        self.press_key(KEY_ENTER)
+    }
+
+    fn press_key(&mut self, keycode: u16) -> Result<()> {
+        self.press_combo(&[keycode])
+    }
+
+    fn press_combo(&mut self, keycodes: &[u16]) -> Result<()> {
+        let args = self.tool.combo_args(keycodes)?;
+        self.run(&args)
+    }
+
+    fn can_type(&self, c: char) -> bool {
+        self.tool.can_type(c)
+    }
+
+    fn press_backspaces(&mut self, count: usize) -> Result<()> {
+        if count == 0 {
+            return Ok(());
+        }
+        let delay = self.key_delay(|cadence| cadence.backspace_delay);
+        self.run(&self.tool.backspace_args(count, delay))
+    }
+
+    fn set_cadence(&mut self, cadence: Cadence) {
+        debug!("Typing cadence: {:?}", cadence);
+        self.cadence = Some(cadence);
+    }
+}
+
+/// [`KeyboardHardware`] that writes the text to stdout. Backspaces are
+/// written as backspace-space-backspace so a terminal shows the same text
+/// the focused window would.
+pub struct StdoutKeyboardHardware<W: Write = io::Stdout> {
+    out: W,
+}
+
+impl StdoutKeyboardHardware {
+    pub fn new() -> Self {
+        Self::with_writer(io::stdout())
+    }
+}
+
+impl<W: Write> StdoutKeyboardHardware<W> {
+    pub fn with_writer(out: W) -> Self {
+        Self { out }
+    }
+
+    fn write(&mut self, text: &str) -> Result<()> {
+        self.out.write_all(text.as_bytes())?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> KeyboardHardware for StdoutKeyboardHardware<W> {
+    fn type_text(&mut self, text: &str) -> Result<Vec<TypedChar>> {
+        self.write(text)?;
+        Ok(text
+            .chars()
+            .map(|c| TypedChar {
+                intended: c,
+                emitted: true,
+            })
+            .collect())
+    }
+
+    fn press_backspace(&mut self) -> Result<()> {
+        self.write("\u{8} \u{8}")
+    }
+
+    fn press_enter(&mut self) -> Result<()> {
+        self.write("\n")
+    }
+
+    fn press_key(&mut self, keycode: u16) -> Result<()> {
+        match keycode {
+            KEY_BACKSPACE => self.press_backspace(),
+            KEY_ENTER => self.press_enter(),
+            _ => {
+                debug!("Ignoring key {} on stdout", keycode);
+                Ok(())
+            }
+        }
+    }
+
+    /// Only reached through triggers; paste mode is refused with stdout
+    /// since nothing would be printed
+    fn press_combo(&mut self, keycodes: &[u16]) -> Result<()> {
+        debug!("Ignoring key combination {:?} on stdout", keycodes);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(OutputBackend::parse("wtype").unwrap(), OutputBackend::Wtype);
+        assert_eq!(
+            OutputBackend::parse("STDOUT").unwrap(),
+            OutputBackend::Stdout
+        );
+        assert!(OutputBackend::parse("telnet").is_err());
+    }
+
+    #[test]
+    fn test_detect() {
+        let installed = |_: &str| true;
+        let nothing = |_: &str| false;
+        let ydotool = |p: &str| p == "ydotool";
+        let wayland = |name: &str| (name == "XDG_SESSION_TYPE").then(|| "wayland".to_string());
+        let x11 = |name: &str| (name == "DISPLAY").then(|| ":0".to_string());
+        let supported = || true;
+        let unsupported = || false;
+
+        assert_eq!(
+            OutputBackend::detect_from(wayland, installed, true, supported),
+            OutputBackend::Uinput
+        );
+        // The native protocols need no extra tools, so they win over wtype
+        // and xdotool
+        assert_eq!(
+            OutputBackend::detect_from(wayland, nothing, false, supported),
+            OutputBackend::Wayland
+        );
+        assert_eq!(
+            OutputBackend::detect_from(x11, nothing, false, unsupported),
+            OutputBackend::Xtest
+        );
+        // GNOME and KDE have no virtual keyboard protocol
+        assert_eq!(
+            OutputBackend::detect_from(wayland, ydotool, false, unsupported),
+            OutputBackend::Ydotool
+        );
+        assert_eq!(
+            OutputBackend::detect_from(wayland, nothing, false, unsupported),
+            OutputBackend::Stdout
+        );
+        assert_eq!(
+            OutputBackend::detect_from(|_| None, ydotool, false, supported),
+            OutputBackend::Ydotool
+        );
+        assert_eq!(
+            OutputBackend::detect_from(|_| None, nothing, false, supported),
+            OutputBackend::Stdout
+        );
+    }
+
+    #[test]
+    fn test_type_args() {
+        assert_eq!(
+            CommandTool::Wtype.type_args("-hi", None),
+            args(&["--", "-hi"])
+        );
+        assert_eq!(
+            CommandTool::Xdotool.type_args("hi", None),
+            args(&["type", "--", "hi"])
+        );
+        assert_eq!(
+            CommandTool::Ydotool.type_args("hi", Some(Duration::from_millis(10))),
+            args(&["type", "--key-delay", "10", "--", "hi"])
+        );
+    }
+
+    #[test]
+    fn test_backspace_args() {
+        assert_eq!(
+            CommandTool::Xdotool.backspace_args(3, None),
+            args(&["key", "--repeat", "3", "BackSpace"])
+        );
+        assert_eq!(
+            CommandTool::Ydotool.backspace_args(2, None),
+            args(&["key", "14:1", "14:0", "14:1", "14:0"])
+        );
+        assert_eq!(
+            CommandTool::Wtype.backspace_args(2, Some(Duration::from_millis(5))),
+            args(&["-d", "5", "-k", "BackSpace", "-k", "BackSpace"])
+        );
+    }
+
+    #[test]
+    fn test_combo_args() {
+        let paste = [KEY_LEFTCTRL, KEY_V];
+        assert_eq!(
+            CommandTool::Ydotool.combo_args(&paste).unwrap(),
+            args(&["key", "29:1", "47:1", "47:0", "29:0"])
+        );
+        assert_eq!(
+            CommandTool::Xdotool.combo_args(&paste).unwrap(),
+            args(&["key", "Control_L+v"])
+        );
+        assert_eq!(
+            CommandTool::Wtype.combo_args(&paste).unwrap(),
+            args(&["-M", "ctrl", "-k", "v", "-m", "ctrl"])
+        );
+        assert_eq!(
+            CommandTool::Wtype.combo_args(&[KEY_BACKSPACE]).unwrap(),
+            args(&["-k", "BackSpace"])
+        );
+        assert!(CommandTool::Xdotool.combo_args(&[KEY_F1]).is_err());
+    }
+
+    #[test]
+    fn test_ydotool_reports_skipped_characters() {
+        assert!(CommandTool::Ydotool.can_type('a'));
+        assert!(!CommandTool::Ydotool.can_type('é'));
+        assert!(CommandTool::Wtype.can_type('é'));
+    }
+
+    #[test]
+    fn test_stdout_output() {
+        let mut hardware = StdoutKeyboardHardware::with_writer(Vec::new());
+        let typed = hardware.type_text("héllo").unwrap();
+        assert!(typed.iter().all(|t| t.emitted));
+        hardware.press_backspace().unwrap();
+        hardware.press_enter().unwrap();
+        hardware.press_combo(&[KEY_LEFTCTRL, KEY_V]).unwrap();
+        assert_eq!(
+            String::from_utf8(hardware.out).unwrap(),
+            "héllo\u{8} \u{8}\n"
+        );
+    }
+}
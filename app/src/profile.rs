@@ -0,0 +1,94 @@
+//! Named profiles from the config.
+//!
+//! A profile overrides some of the top-level text settings, e.g. numbers as
+//! digits for notes but not for prose. It is chosen with `--profile` or the
+//! `profile` config field and applied for one run only, so the config can
+//! still be saved as written.
+
+use anyhow::{bail, Result};
+
+use crate::config::Config;
+
+/// Text settings in effect after applying the chosen profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextSettings {
+    pub spoken_punctuation: bool,
+    pub format_turns: bool,
+    pub voice_undo: bool,
+    pub inverse_text_normalization: bool,
+    pub code_mode: bool,
+}
+
+impl TextSettings {
+    /// The top-level settings of `config`, with those set in the profile
+    /// `name` overriding them
+    pub fn for_profile(config: &Config, profile: Option<&str>) -> Result<Self> {
+        let mut settings = Self {
+            spoken_punctuation: config.spoken_punctuation,
+            format_turns: config.format_turns,
+            voice_undo: config.voice_undo,
+            inverse_text_normalization: config.inverse_text_normalization,
+            code_mode: config.code_mode,
+        };
+        let Some(name) = profile else {
+            return Ok(settings);
+        };
+        let Some(profile) = config.profiles.get(name) else {
+            let known = config.profiles.keys().cloned().collect::<Vec<_>>();
+            bail!(
+                "Unknown profile '{}' (configured: {})",
+                name,
+                if known.is_empty() {
+                    "none".to_string()
+                } else {
+                    known.join(", ")
+                }
+            );
+        };
+        let overrides = [
+            (&mut settings.spoken_punctuation, profile.spoken_punctuation),
+            (&mut settings.format_turns, profile.format_turns),
+            (&mut settings.voice_undo, profile.voice_undo),
+            (
+                &mut settings.inverse_text_normalization,
+                profile.inverse_text_normalization,
+            ),
+            (&mut settings.code_mode, profile.code_mode),
+        ];
+        for (setting, value) in overrides {
+            if let Some(value) = value {
+                *setting = value;
+            }
+        }
+        Ok(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_overrides() {
+        let json = r#"{"api_key":"k","hotkey_code":"F13","project_id":"p",
+            "spoken_punctuation":true,
+            "profiles":{"notes":{"inverse_text_normalization":true,"format_turns":false}}}"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        let settings = TextSettings::for_profile(&config, Some("notes")).unwrap();
+        assert!(settings.inverse_text_normalization);
+        assert!(!settings.format_turns);
+        // Settings the profile leaves out keep their values
+        assert!(settings.spoken_punctuation);
+        // The saved settings are not touched
+        assert!(!config.inverse_text_normalization);
+        assert!(config.format_turns);
+
+        assert_eq!(
+            TextSettings::for_profile(&config, None)
+                .unwrap()
+                .format_turns,
+            config.format_turns
+        );
+        assert!(TextSettings::for_profile(&config, Some("missing")).is_err());
+    }
+}
@@ -0,0 +1,333 @@
+//! User-defined fixes for words the STT service keeps getting wrong.
+//!
+//! Each rule from the `replacements` list in the config rewrites matching
+//! text before anything else looks at a transcript, so "cube control" can
+//! be typed as "kubectl". Rules run in order, and edits to `config.json`
+//! are picked up while running.
+
+use anyhow::{bail, Context, Result};
+use regex::{Captures, Regex, RegexBuilder};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+use crate::config::{Config, ReplacementRule};
+
+/// How often the config file is checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct CompiledRule {
+    rule: ReplacementRule,
+    pattern: Regex,
+}
+
+impl CompiledRule {
+    pub fn new(rule: &ReplacementRule) -> Result<Self> {
+        if rule.pattern.trim().is_empty() {
+            bail!("Replacement rule has an empty pattern");
+        }
+        let mut source = if rule.regex {
+            rule.pattern.clone()
+        } else {
+            // Any run of whitespace in the transcript matches a space
+            rule.pattern
+                .split_whitespace()
+                .map(regex::escape)
+                .collect::<Vec<_>>()
+                .join(r"\s+")
+        };
+        if rule.whole_word {
+            source = whole_word(&source, rule.regex, &rule.pattern);
+        }
+        let pattern = RegexBuilder::new(&source)
+            .case_insensitive(!rule.case_sensitive)
+            .build()
+            .with_context(|| format!("Invalid replacement rule '{}'", rule.pattern))?;
+        Ok(Self {
+            rule: rule.clone(),
+            pattern,
+        })
+    }
+
+    pub fn rule(&self) -> &ReplacementRule {
+        &self.rule
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        self.pattern
+            .replace_all(text, |captures: &Captures| {
+                let mut replacement = String::new();
+                if self.rule.regex {
+                    captures.expand(&self.rule.replacement, &mut replacement);
+                } else {
+                    replacement.push_str(&self.rule.replacement);
+                }
+                if self.rule.preserve_case {
+                    match_case(&captures[0], &replacement)
+                } else {
+                    replacement
+                }
+            })
+            .into_owned()
+    }
+}
+
+/// Require word boundaries around `source`. A literal pattern only gets a
+/// boundary next to word characters, so "C++" still matches before a space.
+fn whole_word(source: &str, regex: bool, pattern: &str) -> String {
+    if regex {
+        return format!(r"\b(?:{})\b", source);
+    }
+    let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let pattern = pattern.trim();
+    let start = if is_word(pattern.chars().next()) {
+        r"\b"
+    } else {
+        ""
+    };
+    let end = if is_word(pattern.chars().last()) {
+        r"\b"
+    } else {
+        ""
+    };
+    format!("{}{}{}", start, source, end)
+}
+
+/// Give `replacement` the case of `matched`: all caps if the match was
+/// shouted, a leading capital if it started a sentence, otherwise as written
+fn match_case(matched: &str, replacement: &str) -> String {
+    let letters: Vec<char> = matched.chars().filter(|c| c.is_alphabetic()).collect();
+    if letters.len() > 1 && letters.iter().all(|c| c.is_uppercase()) {
+        return replacement.to_uppercase();
+    }
+    if letters.first().is_some_and(|c| c.is_uppercase()) {
+        let mut chars = replacement.chars();
+        if let Some(first) = chars.next() {
+            return first.to_uppercase().chain(chars).collect();
+        }
+    }
+    replacement.to_string()
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReplacementRules {
+    rules: Vec<CompiledRule>,
+}
+
+impl ReplacementRules {
+    pub fn new(rules: &[ReplacementRule]) -> Result<Self> {
+        let rules = rules.iter().map(CompiledRule::new).collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn rules(&self) -> &[CompiledRule] {
+        &self.rules
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Run every rule over `text`, in order
+    pub fn apply(&self, text: &str) -> String {
+        self.rules
+            .iter()
+            .fold(text.to_string(), |text, rule| rule.apply(&text))
+    }
+}
+
+/// Rules that follow changes to the config file
+#[derive(Debug)]
+pub struct Replacements {
+    rules: ReplacementRules,
+    source: Option<ConfigWatch>,
+}
+
+#[derive(Debug)]
+struct ConfigWatch {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+impl Replacements {
+    /// Rules that never change
+    pub fn fixed(rules: ReplacementRules) -> Self {
+        Self {
+            rules,
+            source: None,
+        }
+    }
+
+    /// Start with `rules`, loaded from `path`, and reload them whenever the
+    /// file changes
+    pub fn watch(rules: ReplacementRules, path: PathBuf) -> Self {
+        let modified = modified_time(&path);
+        Self {
+            rules,
+            source: Some(ConfigWatch {
+                path,
+                modified,
+                last_check: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn apply(&mut self, text: &str) -> String {
+        self.reload_if_changed();
+        self.rules.apply(text)
+    }
+
+    /// Reload the rules if the config file changed; a broken file is
+    /// reported and the current rules are kept
+    fn reload_if_changed(&mut self) {
+        let Some(watch) = &mut self.source else {
+            return;
+        };
+        if watch.last_check.elapsed() < RELOAD_INTERVAL {
+            return;
+        }
+        watch.last_check = Instant::now();
+
+        let modified = modified_time(&watch.path);
+        if modified == watch.modified {
+            return;
+        }
+        watch.modified = modified;
+
+        match Config::load_from(&watch.path)
+            .and_then(|config| ReplacementRules::new(&config.replacements))
+        {
+            Ok(rules) => {
+                info!("Reloaded {} replacement rules", rules.len());
+                self.rules = rules;
+            }
+            Err(e) => warn!("Keeping the previous replacement rules: {:#}", e),
+        }
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn literal(pattern: &str, replacement: &str) -> ReplacementRule {
+        ReplacementRule {
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            regex: false,
+            case_sensitive: false,
+            whole_word: true,
+            preserve_case: false,
+        }
+    }
+
+    fn apply(rule: ReplacementRule, text: &str) -> String {
+        CompiledRule::new(&rule).unwrap().apply(text)
+    }
+
+    #[test]
+    fn test_literal_rules() {
+        let rules = ReplacementRules::new(&[
+            literal("cube control", "kubectl"),
+            literal("post gress", "Postgres"),
+        ])
+        .unwrap();
+        assert_eq!(
+            rules.apply("Run Cube  control against post gress."),
+            "Run kubectl against Postgres."
+        );
+        // Characters special to regexes are matched as written
+        assert_eq!(
+            apply(literal("c++", "C++"), "I like c++ a lot"),
+            "I like C++ a lot"
+        );
+        assert_eq!(apply(literal("a.b", "x"), "axb a.b"), "axb x");
+    }
+
+    #[test]
+    fn test_word_boundaries() {
+        assert_eq!(apply(literal("post", "mail"), "poster post"), "poster mail");
+
+        let mut rule = literal("post", "mail");
+        rule.whole_word = false;
+        assert_eq!(apply(rule, "poster post"), "mailer mail");
+    }
+
+    #[test]
+    fn test_case_handling() {
+        let mut rule = literal("cube control", "kubectl");
+        rule.case_sensitive = true;
+        assert_eq!(
+            apply(rule.clone(), "Cube control, cube control"),
+            "Cube control, kubectl"
+        );
+
+        rule.case_sensitive = false;
+        rule.preserve_case = true;
+        assert_eq!(
+            apply(rule, "Cube control, cube control, CUBE CONTROL"),
+            "Kubectl, kubectl, KUBECTL"
+        );
+    }
+
+    #[test]
+    fn test_regex_rules() {
+        let mut rule = literal(r"version (\d+) point (\d+)", "v$1.$2");
+        rule.regex = true;
+        assert_eq!(
+            apply(rule, "Upgrade to version 3 point 12"),
+            "Upgrade to v3.12"
+        );
+
+        // Literal replacements never expand groups
+        assert_eq!(apply(literal("price", "$1"), "the price"), "the $1");
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let mut rule = literal("(unclosed", "x");
+        rule.regex = true;
+        let error = ReplacementRules::new(&[rule]).unwrap_err();
+        assert!(format!("{:#}", error).contains("(unclosed"));
+        assert!(ReplacementRules::new(&[literal("  ", "x")]).is_err());
+    }
+
+    #[test]
+    fn test_reloads_changed_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let write_rules = |rules: &[ReplacementRule]| {
+            let config = Config {
+                replacements: rules.to_vec(),
+                ..Config::default()
+            };
+            fs::write(&path, serde_json::to_string(&config).unwrap()).unwrap();
+        };
+
+        let first = [literal("cube control", "kubectl")];
+        write_rules(&first);
+        let mut replacements =
+            Replacements::watch(ReplacementRules::new(&first).unwrap(), path.clone());
+        assert_eq!(replacements.apply("cube control"), "kubectl");
+
+        write_rules(&[literal("cube control", "kube control")]);
+        let watch = replacements.source.as_mut().unwrap();
+        watch.modified = None;
+        watch.last_check -= RELOAD_INTERVAL;
+        assert_eq!(replacements.apply("cube control"), "kube control");
+
+        // A broken file keeps the rules that were working
+        fs::write(&path, "{ not json").unwrap();
+        let watch = replacements.source.as_mut().unwrap();
+        watch.modified = None;
+        watch.last_check -= RELOAD_INTERVAL;
+        assert_eq!(replacements.apply("cube control"), "kube control");
+    }
+}
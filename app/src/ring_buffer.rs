@@ -0,0 +1,250 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Shared storage for a single-producer/single-consumer ring buffer.
+///
+/// `head` and `tail` are monotonically increasing counters; the slot index is
+/// the counter modulo the capacity. Only the producer advances `tail` and only
+/// the consumer advances `head`, so no locks are needed on either side.
+struct Shared<T> {
+    slots: Box<[UnsafeCell<T>]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    overruns: AtomicU64,
+    dropped: AtomicU64,
+}
+
+// Safety: each slot is only ever accessed by one side at a time, as enforced by
+// the head/tail protocol with acquire/release ordering.
+unsafe impl<T: Send> Send for Shared<T> {}
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        let head = self.head.load(Ordering::Acquire);
+        tail.wrapping_sub(head)
+    }
+}
+
+/// Write half of the ring buffer. Safe to use from a real-time thread: it never
+/// allocates, locks or blocks.
+pub struct Producer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Read half of the ring buffer.
+pub struct Consumer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Create a ring buffer holding up to `capacity` items, split into its two halves
+pub fn ring_buffer<T: Copy + Default>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0, "ring buffer capacity must be non-zero");
+
+    let slots = (0..capacity)
+        .map(|_| UnsafeCell::new(T::default()))
+        .collect::<Vec<_>>()
+        .into_boxed_slice();
+
+    let shared = Arc::new(Shared {
+        slots,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+        overruns: AtomicU64::new(0),
+        dropped: AtomicU64::new(0),
+    });
+
+    (
+        Producer {
+            shared: shared.clone(),
+        },
+        Consumer { shared },
+    )
+}
+
+impl<T: Copy> Producer<T> {
+    /// Push the whole slice, or nothing at all if there is not enough room.
+    ///
+    /// Writing all-or-nothing keeps interleaved multi-channel frames aligned.
+    /// When the slice is dropped the overrun counters are bumped and `false`
+    /// is returned.
+    pub fn push_slice(&mut self, data: &[T]) -> bool {
+        let shared = &*self.shared;
+        let tail = shared.tail.load(Ordering::Relaxed);
+        let head = shared.head.load(Ordering::Acquire);
+        let free = shared.capacity() - tail.wrapping_sub(head);
+
+        if data.len() > free {
+            shared.overruns.fetch_add(1, Ordering::Relaxed);
+            shared
+                .dropped
+                .fetch_add(data.len() as u64, Ordering::Relaxed);
+            return false;
+        }
+
+        let capacity = shared.capacity();
+        for (i, &item) in data.iter().enumerate() {
+            let index = tail.wrapping_add(i) % capacity;
+            // Safety: slots in [tail, tail + free) are owned by the producer
+            unsafe {
+                *shared.slots[index].get() = item;
+            }
+        }
+
+        shared
+            .tail
+            .store(tail.wrapping_add(data.len()), Ordering::Release);
+        true
+    }
+}
+
+impl<T: Copy> Consumer<T> {
+    /// Pop up to `out.len()` items, returning how many were written to `out`
+    pub fn pop_slice(&mut self, out: &mut [T]) -> usize {
+        let shared = &*self.shared;
+        let head = shared.head.load(Ordering::Relaxed);
+        let tail = shared.tail.load(Ordering::Acquire);
+        let available = tail.wrapping_sub(head);
+        let count = available.min(out.len());
+
+        let capacity = shared.capacity();
+        for (i, slot) in out.iter_mut().take(count).enumerate() {
+            let index = head.wrapping_add(i) % capacity;
+            // Safety: slots in [head, tail) are owned by the consumer
+            *slot = unsafe { *shared.slots[index].get() };
+        }
+
+        shared
+            .head
+            .store(head.wrapping_add(count), Ordering::Release);
+        count
+    }
+}
+
+impl<T> Consumer<T> {
+    /// Number of items currently waiting to be read
+    pub fn len(&self) -> usize {
+        self.shared.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of pushes that were dropped because the buffer was full
+    pub fn overruns(&self) -> u64 {
+        self.shared.overruns.load(Ordering::Relaxed)
+    }
+
+    /// Total number of items lost to overruns
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    /// True once the producer has been dropped (e.g. the audio stream stopped)
+    pub fn is_abandoned(&self) -> bool {
+        Arc::strong_count(&self.shared) == 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_and_pop_roundtrip() {
+        let (mut producer, mut consumer) = ring_buffer::<f32>(8);
+
+        assert!(producer.push_slice(&[1.0, 2.0, 3.0]));
+        assert_eq!(consumer.len(), 3);
+
+        let mut out = [0.0; 8];
+        let n = consumer.pop_slice(&mut out);
+        assert_eq!(n, 3);
+        assert_eq!(&out[..n], &[1.0, 2.0, 3.0]);
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn test_wraps_around_capacity() {
+        let (mut producer, mut consumer) = ring_buffer::<i32>(4);
+        let mut out = [0; 4];
+
+        for round in 0..10 {
+            let base = round * 3;
+            assert!(producer.push_slice(&[base, base + 1, base + 2]));
+            let n = consumer.pop_slice(&mut out);
+            assert_eq!(&out[..n], &[base, base + 1, base + 2]);
+        }
+        assert_eq!(consumer.overruns(), 0);
+    }
+
+    #[test]
+    fn test_overrun_drops_whole_slice() {
+        let (mut producer, mut consumer) = ring_buffer::<i32>(4);
+
+        assert!(producer.push_slice(&[1, 2, 3]));
+        // Only one slot left: the two-item push must be dropped entirely
+        assert!(!producer.push_slice(&[4, 5]));
+        assert_eq!(consumer.overruns(), 1);
+        assert_eq!(consumer.dropped(), 2);
+
+        let mut out = [0; 4];
+        let n = consumer.pop_slice(&mut out);
+        assert_eq!(&out[..n], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn test_partial_pop() {
+        let (mut producer, mut consumer) = ring_buffer::<i32>(8);
+        assert!(producer.push_slice(&[1, 2, 3, 4, 5]));
+
+        let mut out = [0; 2];
+        assert_eq!(consumer.pop_slice(&mut out), 2);
+        assert_eq!(out, [1, 2]);
+        assert_eq!(consumer.len(), 3);
+    }
+
+    #[test]
+    fn test_abandoned_when_producer_dropped() {
+        let (producer, consumer) = ring_buffer::<f32>(4);
+        assert!(!consumer.is_abandoned());
+        drop(producer);
+        assert!(consumer.is_abandoned());
+    }
+
+    #[test]
+    fn test_concurrent_producer_consumer() {
+        let (mut producer, mut consumer) = ring_buffer::<u32>(64);
+        let total: u32 = 10_000;
+
+        let writer = std::thread::spawn(move || {
+            let mut next = 0;
+            while next < total {
+                if producer.push_slice(&[next]) {
+                    next += 1;
+                } else {
+                    std::thread::yield_now();
+                }
+            }
+        });
+
+        let mut expected = 0;
+        let mut out = [0u32; 16];
+        while expected < total {
+            let n = consumer.pop_slice(&mut out);
+            for &value in &out[..n] {
+                assert_eq!(value, expected);
+                expected += 1;
+            }
+        }
+
+        writer.join().unwrap();
+    }
+}
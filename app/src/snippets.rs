@@ -0,0 +1,307 @@
+//! Stored text inserted by voice.
+//!
+//! A turn consisting of just "insert <name>" is replaced by the snippet of
+//! that name, which may span several lines. Snippets come from `snippets`
+//! in the config and from `*.txt` files in the `snippets` directory next to
+//! it, where `standup-template.txt` is inserted by "insert standup template".
+//! Placeholders in the text are filled in when it is inserted:
+//! `{date}`, `{time}`, `{datetime}`, `{date:FORMAT}` (strftime) and
+//! `{clipboard}`.
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+use crate::clipboard::Clipboard;
+use crate::spoken_punctuation::normalize_phrase;
+
+/// Word that starts a snippet command
+const COMMAND_WORD: &str = "insert";
+
+pub struct Snippets {
+    /// Snippet text keyed by lowercase name, words separated by one space
+    snippets: BTreeMap<String, String>,
+    /// Matches a whole turn of "insert <name>"; `None` without snippets
+    pattern: Option<Regex>,
+    /// Source for `{clipboard}`
+    clipboard: Option<Box<dyn Clipboard>>,
+}
+
+impl Snippets {
+    pub fn new(snippets: &BTreeMap<String, String>) -> Self {
+        let snippets: BTreeMap<String, String> = snippets
+            .iter()
+            .map(|(name, text)| (normalize_phrase(name), text.clone()))
+            .filter(|(name, _)| !name.is_empty())
+            .collect();
+
+        // Longest names first, so "standup template" wins over "standup"
+        let mut names: Vec<&String> = snippets.keys().collect();
+        names.sort_by_key(|name| std::cmp::Reverse(name.len()));
+        let alternatives = names
+            .iter()
+            .map(|name| {
+                name.split(' ')
+                    .map(regex::escape)
+                    .collect::<Vec<_>>()
+                    .join(r"\s+")
+            })
+            .collect::<Vec<_>>()
+            .join("|");
+        // (?i) = case insensitive
+        // ^\s* = the command is the whole turn, after any leading whitespace
+        // [[:punct:]\s]*$ = optional trailing punctuation or whitespace
+        let pattern = (!snippets.is_empty()).then(|| {
+            Regex::new(&format!(
+                r"(?i)^\s*({}\s+({}))[[:punct:]\s]*$",
+                COMMAND_WORD, alternatives
+            ))
+            .expect("snippet pattern is valid")
+        });
+
+        Self {
+            snippets,
+            pattern,
+            clipboard: None,
+        }
+    }
+
+    /// Combine the `*.txt` files in `dir` with `configured` snippets, which
+    /// win when both define the same name. A missing directory is fine.
+    pub fn load(dir: &Path, configured: &BTreeMap<String, String>) -> Result<Self> {
+        let mut snippets = BTreeMap::new();
+        if dir.is_dir() {
+            let entries = fs::read_dir(dir)
+                .with_context(|| format!("Failed to read snippets from {}", dir.display()))?;
+            for entry in entries {
+                let path = entry?.path();
+                if path.extension().and_then(|e| e.to_str()) != Some("txt") {
+                    continue;
+                }
+                let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+                let text = fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read snippet {}", path.display()))?;
+                // Editors end files with a newline that is not part of the snippet
+                let text = text.strip_suffix('\n').unwrap_or(&text);
+                snippets.insert(
+                    normalize_phrase(&stem.replace(['-', '_'], " ")),
+                    text.to_string(),
+                );
+            }
+        }
+        // Normalize before merging so "Signature" in the config replaces
+        // signature.txt instead of sitting next to it
+        snippets.extend(
+            configured
+                .iter()
+                .map(|(name, text)| (normalize_phrase(name), text.clone())),
+        );
+        Ok(Self::new(&snippets))
+    }
+
+    /// Read `{clipboard}` placeholders from `clipboard`
+    pub fn set_clipboard(&mut self, clipboard: Box<dyn Clipboard>) {
+        self.clipboard = Some(clipboard);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snippets.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.snippets.len()
+    }
+
+    /// Find a snippet command making up the whole of `text`, returning the
+    /// byte offset where the command starts and the snippet's name
+    pub fn find(&self, text: &str) -> Option<(usize, String)> {
+        let captures = self.pattern.as_ref()?.captures(text)?;
+        let command = captures.get(1).unwrap();
+        Some((command.start(), normalize_phrase(&captures[2])))
+    }
+
+    /// Text of the snippet `name` with its placeholders filled in
+    pub fn expand(&mut self, name: &str) -> Option<String> {
+        let template = self.snippets.get(name)?;
+        let clipboard = &mut self.clipboard;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as libc::time_t);
+        Some(fill_placeholders(template, now, || {
+            let Some(clipboard) = clipboard.as_mut() else {
+                warn!("No clipboard tool available for {{clipboard}}");
+                return String::new();
+            };
+            clipboard
+                .get()
+                .unwrap_or_else(|e| {
+                    warn!("Failed to read the clipboard: {:#}", e);
+                    None
+                })
+                .unwrap_or_default()
+        }))
+    }
+}
+
+/// Replace the placeholders in `template`, formatting times as local time
+/// at `now` and reading the clipboard at most once
+fn fill_placeholders<F>(template: &str, now: libc::time_t, clipboard: F) -> String
+where
+    F: FnOnce() -> String,
+{
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN
+        .get_or_init(|| Regex::new(r"\{(date|time|datetime|clipboard)(?::([^}]*))?\}").unwrap());
+
+    let mut clipboard = Some(clipboard);
+    let mut clipboard_text: Option<String> = None;
+    pattern
+        .replace_all(template, |captures: &regex::Captures| {
+            let custom = captures.get(2).map(|m| m.as_str());
+            match &captures[1] {
+                "date" => strftime(custom.unwrap_or("%Y-%m-%d"), now),
+                "time" => strftime(custom.unwrap_or("%H:%M"), now),
+                "datetime" => strftime(custom.unwrap_or("%Y-%m-%d %H:%M"), now),
+                _ => clipboard_text
+                    .get_or_insert_with(|| clipboard.take().map(|read| read()).unwrap_or_default())
+                    .clone(),
+            }
+        })
+        .into_owned()
+}
+
+/// Format `time` in the local timezone with a strftime `format`
+fn strftime(format: &str, time: libc::time_t) -> String {
+    let Ok(c_format) = CString::new(format) else {
+        return String::new();
+    };
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let mut buf = [0u8; 256];
+    // Safety: `tm` and `buf` outlive the calls, and strftime is told the
+    // size of `buf`, returning 0 rather than overflowing it
+    let len = unsafe {
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return String::new();
+        }
+        libc::strftime(
+            buf.as_mut_ptr() as *mut libc::c_char,
+            buf.len(),
+            c_format.as_ptr(),
+            &tm,
+        )
+    };
+    if len == 0 && !format.is_empty() {
+        debug!("strftime format '{}' produced no output", format);
+    }
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snippets() -> Snippets {
+        let mut configured = BTreeMap::new();
+        configured.insert("Signature".to_string(), "Best,\nDaniel".to_string());
+        configured.insert("standup".to_string(), "short".to_string());
+        configured.insert(
+            "standup template".to_string(),
+            "Yesterday:\nToday:\nBlockers:".to_string(),
+        );
+        Snippets::new(&configured)
+    }
+
+    #[test]
+    fn test_find_whole_turn_commands() {
+        let snippets = snippets();
+        assert_eq!(
+            snippets.find(" Insert signature."),
+            Some((1, "signature".to_string()))
+        );
+        assert_eq!(
+            snippets.find("insert standup  template"),
+            Some((0, "standup template".to_string()))
+        );
+        assert_eq!(snippets.find("please insert signature"), None);
+        assert_eq!(snippets.find("insert signature here"), None);
+        assert_eq!(snippets.find("insert coin"), None);
+        assert_eq!(Snippets::new(&BTreeMap::new()).find("insert x"), None);
+    }
+
+    #[test]
+    fn test_expand() {
+        let mut snippets = snippets();
+        assert_eq!(
+            snippets.expand("standup template").as_deref(),
+            Some("Yesterday:\nToday:\nBlockers:")
+        );
+        assert_eq!(snippets.expand("missing"), None);
+    }
+
+    #[test]
+    fn test_placeholders() {
+        // 2024-06-10 12:00 UTC, which is still June 10 or 11 locally
+        let now = 1_718_020_800;
+        let date = strftime("%Y-%m-%d", now);
+        assert!(date == "2024-06-10" || date == "2024-06-11", "{}", date);
+        let mut reads = 0;
+        let filled = fill_placeholders(
+            "{date} {date:%Y} {clipboard}/{clipboard} {unknown}",
+            now,
+            || {
+                reads += 1;
+                "copied".to_string()
+            },
+        );
+        assert_eq!(filled, format!("{} 2024 copied/copied {{unknown}}", date));
+        assert_eq!(reads, 1);
+
+        assert_eq!(strftime("%H:%M", now).len(), 5);
+    }
+
+    #[test]
+    fn test_load_from_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("standup-template.txt"), "Yesterday:\n").unwrap();
+        fs::write(dir.path().join("signature.txt"), "from file").unwrap();
+        fs::write(dir.path().join("notes.md"), "ignored").unwrap();
+
+        let mut configured = BTreeMap::new();
+        configured.insert("signature".to_string(), "from config".to_string());
+        let mut snippets = Snippets::load(dir.path(), &configured).unwrap();
+
+        assert_eq!(snippets.len(), 2);
+        assert_eq!(
+            snippets.expand("standup template").as_deref(),
+            Some("Yesterday:")
+        );
+        assert_eq!(snippets.expand("signature").as_deref(), Some("from config"));
+    }
+
+    #[test]
+    fn test_configured_names_win_regardless_of_case() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("signature.txt"), "from file").unwrap();
+        fs::write(dir.path().join("Standup_Notes.txt"), "from file").unwrap();
+
+        let mut configured = BTreeMap::new();
+        configured.insert("Signature".to_string(), "from config".to_string());
+        configured.insert("standup notes".to_string(), "from config".to_string());
+        let mut snippets = Snippets::load(dir.path(), &configured).unwrap();
+
+        assert_eq!(snippets.len(), 2);
+        assert_eq!(snippets.expand("signature").as_deref(), Some("from config"));
+        assert_eq!(
+            snippets.expand("standup notes").as_deref(),
+            Some("from config")
+        );
+    }
+}
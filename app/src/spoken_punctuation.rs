@@ -0,0 +1,270 @@
+//! Spoken punctuation and formatting commands.
+//!
+//! Saying "comma", "question mark" or "new paragraph" types the symbol
+//! instead of the word. Commands are matched as whole words anywhere in the
+//! transcript, so every Update is converted the same way and the smart
+//! backspacing in [`VirtualKeyboard`](crate::virtual_keyboard::VirtualKeyboard)
+//! keeps working. Prefixing a command with "literal" types the word itself.
+
+use regex::{Regex, RegexBuilder};
+use std::collections::BTreeMap;
+use tracing::warn;
+
+/// Word that makes the following command type as plain text
+pub(crate) const ESCAPE_WORD: &str = "literal";
+
+/// Built-in English commands
+const DEFAULT_COMMANDS: &[(&str, &str)] = &[
+    ("comma", ","),
+    ("period", "."),
+    ("full stop", "."),
+    ("question mark", "?"),
+    ("exclamation mark", "!"),
+    ("exclamation point", "!"),
+    ("colon", ":"),
+    ("semicolon", ";"),
+    ("ellipsis", "..."),
+    ("dash", "-"),
+    ("hyphen", "-"),
+    ("open paren", "("),
+    ("close paren", ")"),
+    ("open bracket", "["),
+    ("close bracket", "]"),
+    ("open brace", "{"),
+    ("close brace", "}"),
+    ("open quote", "\""),
+    ("close quote", "\""),
+    ("new line", "\n"),
+    ("new paragraph", "\n\n"),
+];
+
+/// Sentence punctuation, which replaces any the STT service typed itself
+const PUNCTUATION: &[char] = &[',', '.', '?', '!', ':', ';'];
+
+/// Symbols that attach to the preceding word
+const CLOSING: &[char] = &[',', '.', '?', '!', ':', ';', ')', ']', '}'];
+
+/// Symbols that attach to the following word
+const OPENING: &[char] = &['(', '[', '{'];
+
+#[derive(Debug, Clone)]
+struct Command {
+    replacement: String,
+    /// Drop the whitespace before the command
+    join_before: bool,
+    /// Drop the whitespace after the command
+    join_after: bool,
+}
+
+impl Command {
+    /// Work out the spacing from the replacement: closing punctuation sticks
+    /// to the word before it, opening brackets to the word after, and line
+    /// breaks to neither
+    fn new(replacement: &str) -> Self {
+        let line_break = !replacement.is_empty() && replacement.chars().all(|c| c == '\n');
+        Self {
+            replacement: replacement.to_string(),
+            join_before: line_break || replacement.starts_with(CLOSING),
+            join_after: line_break || replacement.ends_with(OPENING),
+        }
+    }
+
+    /// Whether the command types sentence punctuation
+    fn is_punctuation(&self) -> bool {
+        self.replacement.starts_with(PUNCTUATION)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpokenPunctuation {
+    /// Commands keyed by their lowercase phrase, words separated by one space
+    commands: BTreeMap<String, Command>,
+    /// Matches an optional escape word, then any command phrase
+    pattern: Regex,
+}
+
+impl SpokenPunctuation {
+    /// Build from the built-in table plus `custom` mappings from the config,
+    /// which override built-in phrases
+    pub fn new(custom: &BTreeMap<String, String>) -> Self {
+        let mut commands: BTreeMap<String, Command> = DEFAULT_COMMANDS
+            .iter()
+            .map(|(phrase, replacement)| (phrase.to_string(), Command::new(replacement)))
+            .collect();
+        // Quotes open towards the following word and close onto the previous
+        commands.get_mut("open quote").unwrap().join_after = true;
+        commands.get_mut("close quote").unwrap().join_before = true;
+        // "well hyphen known" is one word, unlike "this dash that"
+        let hyphen = commands.get_mut("hyphen").unwrap();
+        hyphen.join_before = true;
+        hyphen.join_after = true;
+
+        for (phrase, replacement) in custom {
+            let phrase = normalize_phrase(phrase);
+            if phrase.is_empty() || phrase.split(' ').next() == Some(ESCAPE_WORD) {
+                warn!("Ignoring spoken punctuation command '{}'", phrase);
+                continue;
+            }
+            commands.insert(phrase, Command::new(replacement));
+        }
+
+        // Longest phrases first, so "new paragraph" wins over a custom "new"
+        let mut phrases: Vec<&String> = commands.keys().collect();
+        phrases.sort_by_key(|phrase| std::cmp::Reverse(phrase.len()));
+        let alternatives = phrases
+            .iter()
+            .map(|phrase| {
+                phrase
+                    .split(' ')
+                    .map(regex::escape)
+                    .collect::<Vec<_>>()
+                    .join(r"\s+")
+            })
+            .collect::<Vec<_>>()
+            .join("|");
+        // The STT service often punctuates around command words ("Good.
+        // Comma,"), so punctuation right after a command is swallowed
+        let pattern = RegexBuilder::new(&format!(
+            r"\b(?:({}\s+))?({})\b[.,!?;:]*",
+            ESCAPE_WORD, alternatives
+        ))
+        .case_insensitive(true)
+        .build()
+        .expect("spoken punctuation pattern is valid");
+
+        Self { commands, pattern }
+    }
+
+    /// Replace spoken commands in `text` with the symbols they stand for
+    pub fn apply(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut last = 0;
+        let mut skip_whitespace = false;
+        for captures in self.pattern.captures_iter(text) {
+            let whole = captures.get(0).unwrap();
+            let phrase = captures.get(2).unwrap();
+            push_text(&mut out, &text[last..whole.start()], skip_whitespace);
+            last = whole.end();
+            skip_whitespace = false;
+
+            if captures.get(1).is_some() {
+                // Escaped: type the words as spoken, minus the escape word
+                out.push_str(&text[phrase.start()..whole.end()]);
+                continue;
+            }
+
+            let command = &self.commands[&normalize_phrase(phrase.as_str())];
+            if command.is_punctuation() {
+                let kept = out.trim_end_matches(|c| c == ' ' || PUNCTUATION.contains(&c));
+                out.truncate(kept.len());
+            } else if command.join_before {
+                out.truncate(out.trim_end_matches(' ').len());
+            }
+            out.push_str(&command.replacement);
+            skip_whitespace = command.join_after;
+        }
+        push_text(&mut out, &text[last..], skip_whitespace);
+        out
+    }
+}
+
+impl Default for SpokenPunctuation {
+    fn default() -> Self {
+        Self::new(&BTreeMap::new())
+    }
+}
+
+/// Append `text` to `out`, without its leading whitespace if `skip_whitespace`
+fn push_text(out: &mut String, text: &str, skip_whitespace: bool) {
+    if skip_whitespace {
+        out.push_str(text.trim_start());
+    } else {
+        out.push_str(text);
+    }
+}
+
+/// Lowercase `phrase` and separate its words with single spaces
+pub(crate) fn normalize_phrase(phrase: &str) -> String {
+    phrase
+        .split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_punctuation_attaches_to_words() {
+        let spoken = SpokenPunctuation::default();
+        assert_eq!(
+            spoken.apply("looks good comma but why question mark"),
+            "looks good, but why?"
+        );
+        assert_eq!(
+            spoken.apply("call foo open paren bar close paren period"),
+            "call foo (bar)."
+        );
+        assert_eq!(
+            spoken.apply("he said open quote hi close quote"),
+            "he said \"hi\""
+        );
+    }
+
+    #[test]
+    fn test_line_breaks() {
+        let spoken = SpokenPunctuation::default();
+        assert_eq!(
+            spoken.apply("first point new line second new paragraph done"),
+            "first point\nsecond\n\ndone"
+        );
+        assert_eq!(
+            spoken.apply("well hyphen known dash mostly"),
+            "well-known - mostly"
+        );
+    }
+
+    #[test]
+    fn test_case_and_stt_punctuation() {
+        let spoken = SpokenPunctuation::default();
+        // Flux capitalizes and punctuates around the command words
+        assert_eq!(
+            spoken.apply("Looks good. Comma, nice work. Period."),
+            "Looks good, nice work."
+        );
+        assert_eq!(spoken.apply("Question Mark"), "?");
+    }
+
+    #[test]
+    fn test_whole_words_only() {
+        let spoken = SpokenPunctuation::default();
+        assert_eq!(
+            spoken.apply("the periodic table has commas"),
+            "the periodic table has commas"
+        );
+    }
+
+    #[test]
+    fn test_literal_escape() {
+        let spoken = SpokenPunctuation::default();
+        assert_eq!(
+            spoken.apply("add a literal comma here comma please"),
+            "add a comma here, please"
+        );
+        assert_eq!(spoken.apply("Literal new line"), "new line");
+    }
+
+    #[test]
+    fn test_custom_commands() {
+        let mut custom = BTreeMap::new();
+        custom.insert("Fat  Arrow".to_string(), "=>".to_string());
+        custom.insert("period".to_string(), ";".to_string());
+        custom.insert("literal thing".to_string(), "x".to_string());
+        let spoken = SpokenPunctuation::new(&custom);
+
+        assert_eq!(spoken.apply("a fat arrow b period"), "a => b;");
+        assert_eq!(spoken.apply("literal thing"), "literal thing");
+    }
+}
@@ -0,0 +1,102 @@
+//! Machine-readable status lines the CLI writes to stderr so that the GUI,
+//! which runs it as a child process, can surface them in its status area.
+
+use anyhow::{bail, Context, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// Marks a line on stderr as a status line rather than ordinary output
+pub const STATUS_PREFIX: &str = "@voice-keyboard-status";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusLevel {
+    Info,
+    Warning,
+}
+
+impl StatusLevel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            StatusLevel::Info => "info",
+            StatusLevel::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusLine {
+    pub level: StatusLevel,
+    pub code: String,
+    pub message: String,
+}
+
+impl fmt::Display for StatusLine {
+    /// Render as a single line: `<prefix> <level> <code> <message>`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            STATUS_PREFIX,
+            self.level.as_str(),
+            self.code,
+            self.message.replace('\n', " ")
+        )
+    }
+}
+
+impl FromStr for StatusLine {
+    type Err = anyhow::Error;
+
+    /// Parse a line previously produced by the `Display` impl
+    fn from_str(line: &str) -> Result<Self> {
+        let Some(rest) = line.trim_end().strip_prefix(STATUS_PREFIX) else {
+            bail!("not a status line");
+        };
+        let mut parts = rest.trim_start().splitn(3, ' ');
+        let level = match parts.next() {
+            Some("info") => StatusLevel::Info,
+            Some("warning") => StatusLevel::Warning,
+            _ => bail!("unknown status level"),
+        };
+        let code = parts.next().context("status line has no code")?.to_string();
+        let message = parts.next().unwrap_or("").to_string();
+        Ok(Self {
+            level,
+            code,
+            message,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_line_roundtrip() {
+        let line = StatusLine {
+            level: StatusLevel::Warning,
+            code: "mic-silent".to_string(),
+            message: "Microphone is producing digital silence".to_string(),
+        };
+        assert_eq!(line.to_string().parse::<StatusLine>().unwrap(), line);
+    }
+
+    #[test]
+    fn test_parse_ignores_other_output() {
+        assert!("INFO voice_keyboard: Listening"
+            .parse::<StatusLine>()
+            .is_err());
+        assert!("@voice-keyboard-status bogus code message"
+            .parse::<StatusLine>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_without_message() {
+        let parsed: StatusLine = "@voice-keyboard-status info mic-ok\n".parse().unwrap();
+        assert_eq!(parsed.level, StatusLevel::Info);
+        assert_eq!(parsed.code, "mic-ok");
+        assert_eq!(parsed.message, "");
+    }
+}
@@ -3,7 +3,9 @@ use futures_util::{SinkExt, StreamExt};
 use http::{header::AUTHORIZATION, HeaderValue};
 use serde::{Deserialize, Serialize};
 use std::env;
+use std::time::Duration;
 use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
 use tokio_tungstenite::tungstenite::client::IntoClientRequest;
 use tokio_tungstenite::tungstenite::error::Error as WsError;
 use tokio_tungstenite::{connect_async, tungstenite::Message};
@@ -11,6 +13,10 @@ use tracing::{debug, error, info};
 
 pub const STT_URL: &str = "wss://api.deepgram.com/v2/listen";
 
+/// How long the audio stream may be idle before a KeepAlive is sent; the
+/// server closes connections that receive nothing for about ten seconds
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(4);
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WordInfo {
     pub word: String,
@@ -169,15 +175,34 @@ impl SttClient {
         // Spawn task to handle WebSocket communication
         let handle = tokio::spawn(async move {
             // Task to send audio data (fatal on send error)
+            // While no audio is flowing (e.g. a muted long-running session)
+            // send KeepAlive messages so the idle connection stays open.
             let send_task = tokio::spawn(async move {
-                while let Some(audio_data) = audio_rx.recv().await {
-                    if let Err(e) = ws_sender
-                        .send(Message::Binary(audio_data))
-                        .await
-                        .map_err(enrich_ws_error)
-                    {
-                        error!("Failed to send audio data: {}", e);
-                        return Err(e);
+                let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+                keepalive.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                keepalive.reset();
+
+                loop {
+                    tokio::select! {
+                        audio = audio_rx.recv() => {
+                            let Some(audio_data) = audio else { break };
+                            if let Err(e) = ws_sender
+                                .send(Message::Binary(audio_data))
+                                .await
+                                .map_err(enrich_ws_error)
+                            {
+                                error!("Failed to send audio data: {}", e);
+                                return Err(e);
+                            }
+                            keepalive.reset();
+                        }
+                        _ = keepalive.tick() => {
+                            debug!("Sending KeepAlive control message");
+                            ws_sender
+                                .send(Message::Text(String::from("{\"type\":\"KeepAlive\"}")))
+                                .await
+                                .map_err(enrich_ws_error)?;
+                        }
                     }
                 }
 
@@ -0,0 +1,235 @@
+//! Last-resort ASCII replacements for characters the keyboard cannot type.
+//!
+//! Typing "cafe" and straight quotes beats silently dropping letters, so
+//! characters with neither a key on the layout nor a Unicode input method
+//! are replaced with close ASCII equivalents before they are typed. Custom
+//! mappings from the config take precedence and always apply.
+
+use std::collections::{BTreeMap, HashMap};
+use tracing::warn;
+
+/// Accented letters grouped by the ASCII letter they reduce to
+const ACCENTED: &[(&str, char)] = &[
+    ("àáâãäåāăą", 'a'),
+    ("ÀÁÂÃÄÅĀĂĄ", 'A'),
+    ("çćĉċč", 'c'),
+    ("ÇĆĈĊČ", 'C'),
+    ("ďđð", 'd'),
+    ("ĎĐÐ", 'D'),
+    ("èéêëēĕėęě", 'e'),
+    ("ÈÉÊËĒĔĖĘĚ", 'E'),
+    ("ĝğġģ", 'g'),
+    ("ĜĞĠĢ", 'G'),
+    ("ĥħ", 'h'),
+    ("ĤĦ", 'H'),
+    ("ìíîïĩīĭįı", 'i'),
+    ("ÌÍÎÏĨĪĬĮİ", 'I'),
+    ("ĵ", 'j'),
+    ("Ĵ", 'J'),
+    ("ķ", 'k'),
+    ("Ķ", 'K'),
+    ("ĺļľŀł", 'l'),
+    ("ĹĻĽĿŁ", 'L'),
+    ("ñńņňŉ", 'n'),
+    ("ÑŃŅŇ", 'N'),
+    ("òóôõöøōŏő", 'o'),
+    ("ÒÓÔÕÖØŌŎŐ", 'O'),
+    ("ŕŗř", 'r'),
+    ("ŔŖŘ", 'R'),
+    ("śŝşš", 's'),
+    ("ŚŜŞŠ", 'S'),
+    ("ţťŧ", 't'),
+    ("ŢŤŦ", 'T'),
+    ("ùúûüũūŭůűų", 'u'),
+    ("ÙÚÛÜŨŪŬŮŰŲ", 'U'),
+    ("ŵ", 'w'),
+    ("Ŵ", 'W'),
+    ("ýÿŷ", 'y'),
+    ("ÝŸŶ", 'Y'),
+    ("źżž", 'z'),
+    ("ŹŻŽ", 'Z'),
+];
+
+/// Ligatures, typographic punctuation and symbols
+const SYMBOLS: &[(char, &str)] = &[
+    ('æ', "ae"),
+    ('Æ', "AE"),
+    ('œ', "oe"),
+    ('Œ', "OE"),
+    ('ß', "ss"),
+    ('þ', "th"),
+    ('Þ', "Th"),
+    ('ĳ', "ij"),
+    ('Ĳ', "IJ"),
+    ('ﬀ', "ff"),
+    ('ﬁ', "fi"),
+    ('ﬂ', "fl"),
+    ('ﬃ', "ffi"),
+    ('ﬄ', "ffl"),
+    ('‘', "'"),
+    ('’', "'"),
+    ('‚', "'"),
+    ('‛', "'"),
+    ('′', "'"),
+    ('“', "\""),
+    ('”', "\""),
+    ('„', "\""),
+    ('‟', "\""),
+    ('″', "\""),
+    ('«', "\""),
+    ('»', "\""),
+    ('‹', "'"),
+    ('›', "'"),
+    ('‐', "-"),
+    ('‑', "-"),
+    ('‒', "-"),
+    ('–', "-"),
+    ('—', "--"),
+    ('―', "--"),
+    ('−', "-"),
+    ('…', "..."),
+    ('•', "*"),
+    ('·', "."),
+    ('\u{a0}', " "),
+    ('\u{2009}', " "),
+    ('\u{202f}', " "),
+    ('×', "x"),
+    ('÷', "/"),
+    ('€', "EUR"),
+    ('£', "GBP"),
+    ('©', "(c)"),
+    ('®', "(R)"),
+    ('™', "(TM)"),
+];
+
+/// Built-in ASCII replacement for `c`
+pub fn ascii_equivalent(c: char) -> Option<String> {
+    if let Some((_, base)) = ACCENTED.iter().find(|(accented, _)| accented.contains(c)) {
+        return Some(base.to_string());
+    }
+    SYMBOLS
+        .iter()
+        .find(|(symbol, _)| *symbol == c)
+        .map(|(_, replacement)| replacement.to_string())
+}
+
+#[derive(Debug, Clone)]
+pub struct Transliterator {
+    enabled: bool,
+    custom: HashMap<char, String>,
+}
+
+impl Default for Transliterator {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            custom: HashMap::new(),
+        }
+    }
+}
+
+impl Transliterator {
+    /// Build from the config's custom table, whose keys must be single
+    /// characters. `enabled` only controls the built-in table.
+    pub fn new(enabled: bool, custom: &BTreeMap<String, String>) -> Self {
+        let custom = custom
+            .iter()
+            .filter_map(|(from, to)| {
+                let mut chars = from.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some((c, to.clone())),
+                    _ => {
+                        warn!(
+                            "Ignoring transliteration for '{}': keys must be a single character",
+                            from
+                        );
+                        None
+                    }
+                }
+            })
+            .collect();
+        Self { enabled, custom }
+    }
+
+    /// Replace characters in `text` that `can_type` rejects with ASCII
+    /// equivalents, and apply custom mappings everywhere
+    pub fn normalize<F>(&self, text: &str, can_type: F) -> String
+    where
+        F: Fn(char) -> bool,
+    {
+        let mut out = String::with_capacity(text.len());
+        for c in text.chars() {
+            if let Some(replacement) = self.custom.get(&c) {
+                out.push_str(replacement);
+            } else if !self.enabled || can_type(c) {
+                out.push(c);
+            } else {
+                match ascii_equivalent(c) {
+                    Some(replacement) => out.push_str(&replacement),
+                    None => out.push(c),
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ascii_only(c: char) -> bool {
+        c.is_ascii()
+    }
+
+    #[test]
+    fn test_accents_and_punctuation() {
+        let t = Transliterator::default();
+        assert_eq!(
+            t.normalize("“Café” – naïve façade…", ascii_only),
+            "\"Cafe\" - naive facade..."
+        );
+        assert_eq!(
+            t.normalize("Œuvre, ﬁne, straße", ascii_only),
+            "OEuvre, fine, strasse"
+        );
+    }
+
+    #[test]
+    fn test_keeps_typeable_characters() {
+        let t = Transliterator::default();
+        // A German layout can type umlauts directly
+        let german = |c: char| c.is_ascii() || "äöüß".contains(c);
+        assert_eq!(t.normalize("Grüße – ok", german), "Grüße - ok");
+    }
+
+    #[test]
+    fn test_unknown_characters_pass_through() {
+        let t = Transliterator::default();
+        assert_eq!(t.normalize("hi 😀 ж", ascii_only), "hi 😀 ж");
+    }
+
+    #[test]
+    fn test_custom_mappings() {
+        let mut custom = BTreeMap::new();
+        custom.insert("€".to_string(), " euros".to_string());
+        custom.insert("’".to_string(), "'".to_string());
+        custom.insert("ab".to_string(), "ignored".to_string());
+        let t = Transliterator::new(false, &custom);
+
+        // Custom entries apply even to typeable characters and when the
+        // built-in table is disabled
+        assert_eq!(t.normalize("5€ it’s é", |_| true), "5 euros it's é");
+        assert_eq!(t.normalize("é", ascii_only), "é");
+    }
+
+    #[test]
+    fn test_accent_table_has_no_duplicates() {
+        let mut seen = std::collections::HashSet::new();
+        for (accented, _) in ACCENTED {
+            for c in accented.chars() {
+                assert!(seen.insert(c), "duplicate entry for '{}'", c);
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! End-of-turn trigger phrases.
+//!
+//! A turn that ends with a trigger phrase ("submit", "send it") has the
+//! phrase removed from the typed text and a key or shortcut pressed in its
+//! place. Without any configuration, "enter" presses Enter.
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::BTreeMap;
+
+use crate::clipboard::parse_key_combo;
+use crate::input_event::KEY_ENTER;
+use crate::keymap::Keymap;
+
+#[derive(Debug, Clone)]
+pub struct Trigger {
+    /// The phrase as configured
+    pub phrase: String,
+    /// Keycodes pressed together when the phrase ends a turn
+    pub keys: Vec<u16>,
+    /// Matches the phrase at the end of the text, with the whitespace
+    /// before it and any punctuation or whitespace after it
+    pattern: Regex,
+}
+
+impl Trigger {
+    pub fn new(phrase: &str, keys: Vec<u16>) -> Self {
+        let words = phrase
+            .split_whitespace()
+            .map(regex::escape)
+            .collect::<Vec<_>>()
+            .join(r"\s+");
+        // (?i) = case insensitive
+        // \s* = optional leading whitespace
+        // \b...\b = the phrase as whole words
+        // [[:punct:]\s]* = optional trailing punctuation or whitespace
+        let pattern = Regex::new(&format!(r"(?i)\s*\b{}\b[[:punct:]\s]*$", words))
+            .expect("trigger pattern is valid");
+        Self {
+            phrase: phrase.to_string(),
+            keys,
+            pattern,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Triggers {
+    /// Longest phrases first, so "please submit" wins over "submit"
+    triggers: Vec<Trigger>,
+}
+
+impl Default for Triggers {
+    fn default() -> Self {
+        Self {
+            triggers: vec![Trigger::new("enter", vec![KEY_ENTER])],
+        }
+    }
+}
+
+impl Triggers {
+    /// Build from the config's phrase -> shortcut table, e.g.
+    /// `"send it": "ctrl+enter"`. Shortcut keys are looked up on `keymap`.
+    pub fn new(phrases: &BTreeMap<String, String>, keymap: &Keymap) -> Result<Self> {
+        let mut triggers = phrases
+            .iter()
+            .filter(|(phrase, _)| !phrase.trim().is_empty())
+            .map(|(phrase, shortcut)| {
+                let keys = parse_key_combo(shortcut, keymap)
+                    .with_context(|| format!("Invalid action for trigger phrase '{}'", phrase))?;
+                Ok(Trigger::new(phrase, keys))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        triggers.sort_by_key(|trigger| std::cmp::Reverse(trigger.phrase.len()));
+        Ok(Self { triggers })
+    }
+
+    /// Find the trigger that ends `text`, returning the byte offset where
+    /// the phrase (including the whitespace before it) starts
+    pub fn find(&self, text: &str) -> Option<(usize, &Trigger)> {
+        self.triggers
+            .iter()
+            .find_map(|trigger| trigger.pattern.find(text).map(|m| (m.start(), trigger)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_event::*;
+
+    fn triggers() -> Triggers {
+        let phrases = [
+            ("enter", "enter"),
+            ("send it", "ctrl+enter"),
+            ("submit", "Enter"),
+            ("tab", "tab"),
+            ("escape", "esc"),
+        ]
+        .iter()
+        .map(|(phrase, shortcut)| (phrase.to_string(), shortcut.to_string()))
+        .collect();
+        Triggers::new(&phrases, &Keymap::us()).unwrap()
+    }
+
+    #[test]
+    fn test_default_is_enter() {
+        let triggers = Triggers::default();
+        let (start, trigger) = triggers.find("hello world enter.").unwrap();
+        assert_eq!(start, "hello world".len());
+        assert_eq!(trigger.keys, vec![KEY_ENTER]);
+        assert!(triggers.find("enter the room").is_none());
+    }
+
+    #[test]
+    fn test_configured_triggers() {
+        let triggers = triggers();
+
+        let (start, trigger) = triggers.find("Looks good, Send  it!").unwrap();
+        assert_eq!(start, "Looks good,".len());
+        assert_eq!(trigger.keys, vec![KEY_LEFTCTRL, KEY_ENTER]);
+
+        assert_eq!(triggers.find("submit").unwrap().1.keys, vec![KEY_ENTER]);
+        assert_eq!(triggers.find("next tab").unwrap().1.keys, vec![KEY_TAB]);
+        assert_eq!(triggers.find("escape.").unwrap().1.keys, vec![KEY_ESC]);
+
+        // Phrases only count as whole words at the very end
+        assert!(triggers.find("resubmit").is_none());
+        assert!(triggers.find("send it now").is_none());
+    }
+
+    #[test]
+    fn test_invalid_shortcut() {
+        let mut phrases = BTreeMap::new();
+        phrases.insert("go".to_string(), "ctrl+launch".to_string());
+        assert!(Triggers::new(&phrases, &Keymap::us()).is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! Joining consecutive turns into running text.
+//!
+//! Every turn arrives as a separate sentence-cased transcript, so without
+//! help they run together ("Hello there.How are you"). The formatter
+//! remembers how the previous turn ended and adjusts the start of the next
+//! one: a separating space, a capital letter after a sentence ends, and a
+//! lowercase first word when the previous turn stopped mid-sentence.
+
+/// Characters after which a new sentence starts
+const SENTENCE_END: &[char] = &['.', '?', '!', '\n'];
+
+/// Closing quotes and brackets that may follow a sentence terminator
+const TRAILING_CLOSERS: &[char] = &['"', '\'', ')', ']', '}', '”', '’'];
+
+/// Characters that stick to the following word
+const OPENERS: &[char] = &['(', '[', '{', '“', '‘', '/', '-'];
+
+/// Punctuation that sticks to the preceding word
+const CLOSERS: &[char] = &[',', '.', ';', ':', '!', '?', ')', ']', '}', '”', '’'];
+
+/// Code symbols that attach to the end of the previous turn
+const CODE_CLOSERS: &[char] = &[',', '.', ';', ':', ')', ']', '}', '(', '[', '_'];
+
+/// How a finished turn ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TurnEnd {
+    last: char,
+    /// Whether the turn ended a sentence, looking through closing quotes
+    /// and brackets
+    sentence_ended: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TurnFormatter {
+    /// `None` at the start of a field, e.g. after Enter was pressed
+    previous: Option<TurnEnd>,
+}
+
+impl TurnFormatter {
+    /// Adjust the start of `text` to follow the previous turn
+    pub fn format(&self, text: &str) -> String {
+        let Some(TurnEnd {
+            last,
+            sentence_ended,
+        }) = self.previous
+        else {
+            return text.to_string();
+        };
+        let body = text.trim_start();
+        let Some(first) = body.chars().next() else {
+            return text.to_string();
+        };
+
+        let mut out = String::with_capacity(text.len() + 1);
+        if !last.is_whitespace() && !OPENERS.contains(&last) && !CLOSERS.contains(&first) {
+            out.push(' ');
+        }
+
+        if sentence_ended {
+            out.push_str(&capitalize(body));
+        } else {
+            out.push_str(&lowercase_continuation(body));
+        }
+        out
+    }
+
+    /// Separate `text` from the previous turn for code, leaving its case
+    /// alone
+    pub fn format_code(&self, text: &str) -> String {
+        let Some(TurnEnd { last, .. }) = self.previous else {
+            return text.to_string();
+        };
+        let body = text.trim_start();
+        let Some(first) = body.chars().next() else {
+            return text.to_string();
+        };
+        if last.is_whitespace() || OPENERS.contains(&last) || CODE_CLOSERS.contains(&first) {
+            body.to_string()
+        } else {
+            format!(" {}", body)
+        }
+    }
+
+    /// Remember how the finished turn `text` ended; empty turns change nothing
+    pub fn finish_turn(&mut self, text: &str) {
+        if let Some(last) = text.chars().last() {
+            let sentence_ended = text
+                .trim_end_matches(TRAILING_CLOSERS)
+                .ends_with(SENTENCE_END);
+            self.previous = Some(TurnEnd {
+                last,
+                sentence_ended,
+            });
+        }
+    }
+
+    /// Start over as if at the beginning of an empty field
+    pub fn reset(&mut self) {
+        self.previous = None;
+    }
+
+    /// State to hand back to [`restore`](Self::restore) if the next turn is undone
+    pub fn state(&self) -> Option<TurnEnd> {
+        self.previous
+    }
+
+    pub fn restore(&mut self, state: Option<TurnEnd>) {
+        self.previous = state;
+    }
+}
+
+/// Uppercase the first character of `text`
+pub(crate) fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Lowercase the first word, unless it looks like "I", an acronym or a name
+/// with inner capitals
+fn lowercase_continuation(text: &str) -> String {
+    let word: String = text
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '\'' || *c == '’')
+        .collect();
+    let is_pronoun = word == "I" || word.starts_with("I'") || word.starts_with("I’");
+    let capitals = word.chars().filter(|c| c.is_uppercase()).count();
+    if word.is_empty() || is_pronoun || capitals > 1 {
+        return text.to_string();
+    }
+
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn after(previous: &str) -> TurnFormatter {
+        let mut formatter = TurnFormatter::default();
+        formatter.finish_turn(previous);
+        formatter
+    }
+
+    #[test]
+    fn test_first_turn_is_unchanged() {
+        let formatter = TurnFormatter::default();
+        assert_eq!(formatter.format("hello there."), "hello there.");
+    }
+
+    #[test]
+    fn test_new_sentence() {
+        let formatter = after("Hello there.");
+        assert_eq!(formatter.format("How are you?"), " How are you?");
+        assert_eq!(formatter.format("how are you?"), " How are you?");
+        assert_eq!(after("Really?").format("yes"), " Yes");
+        assert_eq!(after("line\n").format("next"), "Next");
+        assert_eq!(after("He said \"go.\"").format("so"), " So");
+    }
+
+    #[test]
+    fn test_continuation() {
+        let formatter = after("I think that");
+        assert_eq!(formatter.format("We should go."), " we should go.");
+        assert_eq!(formatter.format("I agree"), " I agree");
+        assert_eq!(formatter.format("I'm sure"), " I'm sure");
+        assert_eq!(formatter.format("NASA agrees"), " NASA agrees");
+        assert_eq!(after("first,").format("Second"), " second");
+    }
+
+    #[test]
+    fn test_spacing() {
+        assert_eq!(after("Hello").format(", world"), ", world");
+        assert_eq!(after("call (").format("Foo"), "foo");
+        assert_eq!(after("ends with space ").format("Next"), "next");
+        assert_eq!(after("Hello.").format("   "), "   ");
+    }
+
+    #[test]
+    fn test_code_spacing() {
+        assert_eq!(after("Hello.").format_code("return x"), " return x");
+        assert_eq!(after("user").format_code(".name"), ".name");
+        assert_eq!(after("foo(").format_code("bar"), "bar");
+        assert_eq!(after("x").format_code("= y"), " = y");
+    }
+
+    #[test]
+    fn test_reset_and_restore() {
+        let mut formatter = after("Hello.");
+        let state = formatter.state();
+        formatter.finish_turn(" More");
+        formatter.restore(state);
+        assert_eq!(formatter.format("Next"), " Next");
+
+        formatter.reset();
+        assert_eq!(formatter.format("Next"), "Next");
+    }
+}
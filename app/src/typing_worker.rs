@@ -0,0 +1,198 @@
+//! Typing runs on its own thread, fed by a queue, so that slow keystrokes
+//! (and the pauses between them) never hold up the WebSocket reader.
+//!
+//! Each Update carries the whole transcript of the turn so far, so an
+//! Update that has not been typed yet is worthless once a newer one for
+//! the same turn arrives. The queue replaces it instead of typing both.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use tracing::{debug, error};
+
+use crate::virtual_keyboard::{KeyboardHardware, VirtualKeyboard};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypingCommand {
+    /// Latest transcript of the turn in progress
+    Update { turn_index: u32, transcript: String },
+    /// The turn is finished: type its final form and handle "enter"
+    EndOfTurn,
+}
+
+#[derive(Default)]
+struct Queue {
+    commands: VecDeque<TypingCommand>,
+    closed: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    ready: Condvar,
+}
+
+/// Sending half of the typing queue; cheap to clone
+#[derive(Clone)]
+pub struct TypingSender {
+    shared: Arc<Shared>,
+}
+
+impl TypingSender {
+    pub fn update(&self, turn_index: u32, transcript: &str) {
+        self.push(TypingCommand::Update {
+            turn_index,
+            transcript: transcript.to_string(),
+        });
+    }
+
+    pub fn end_of_turn(&self) {
+        self.push(TypingCommand::EndOfTurn);
+    }
+
+    /// Let the worker finish what is queued and exit
+    pub fn close(&self) {
+        self.shared.queue.lock().unwrap().closed = true;
+        self.shared.ready.notify_one();
+    }
+
+    fn push(&self, command: TypingCommand) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        if enqueue(&mut queue.commands, command) {
+            debug!("Dropped a superseded transcript update");
+        }
+        drop(queue);
+        self.shared.ready.notify_one();
+    }
+}
+
+/// Append `command`, replacing a pending Update of the same turn that it
+/// supersedes. Returns true if an Update was replaced.
+fn enqueue(commands: &mut VecDeque<TypingCommand>, command: TypingCommand) -> bool {
+    if let (
+        Some(TypingCommand::Update {
+            turn_index: pending,
+            transcript,
+        }),
+        TypingCommand::Update {
+            turn_index,
+            transcript: latest,
+        },
+    ) = (commands.back_mut(), &command)
+    {
+        if pending == turn_index {
+            *transcript = latest.clone();
+            return true;
+        }
+    }
+    commands.push_back(command);
+    false
+}
+
+/// Spawn the thread that types queued transcripts with `keyboard`. The
+/// thread exits once the sender is closed and the queue is drained, and
+/// hands the keyboard back.
+///
+/// Failing to type is fatal, as the text on screen can no longer be tracked.
+pub fn spawn_typing_worker<H>(
+    mut keyboard: VirtualKeyboard<H>,
+) -> (TypingSender, thread::JoinHandle<VirtualKeyboard<H>>)
+where
+    H: KeyboardHardware + Send + 'static,
+{
+    let shared = Arc::new(Shared::default());
+    let sender = TypingSender {
+        shared: shared.clone(),
+    };
+
+    let handle = thread::spawn(move || loop {
+        let command = {
+            let mut queue = shared.queue.lock().unwrap();
+            loop {
+                if let Some(command) = queue.commands.pop_front() {
+                    break Some(command);
+                }
+                if queue.closed {
+                    break None;
+                }
+                queue = shared.ready.wait(queue).unwrap();
+            }
+        };
+
+        match command {
+            Some(TypingCommand::Update { transcript, .. }) => {
+                if let Err(e) = keyboard.update_transcript(&transcript) {
+                    error!("Failed to update transcript: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            Some(TypingCommand::EndOfTurn) => {
+                if let Err(e) = keyboard.finalize_transcript() {
+                    error!("Failed to finalize transcript: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            None => break keyboard,
+        }
+    });
+
+    (sender, handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_keyboard::MockKeyboardHardware;
+
+    fn update(turn_index: u32, transcript: &str) -> TypingCommand {
+        TypingCommand::Update {
+            turn_index,
+            transcript: transcript.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_enqueue_coalesces_updates_of_a_turn() {
+        let mut commands = VecDeque::new();
+        assert!(!enqueue(&mut commands, update(0, "hel")));
+        assert!(enqueue(&mut commands, update(0, "hello")));
+        assert!(enqueue(&mut commands, update(0, "hello world")));
+        assert!(!enqueue(&mut commands, TypingCommand::EndOfTurn));
+        assert!(!enqueue(&mut commands, update(1, "next")));
+        // A new turn never replaces the previous turn's update
+        assert!(!enqueue(&mut commands, update(2, "other")));
+
+        assert_eq!(
+            Vec::from(commands),
+            vec![
+                update(0, "hello world"),
+                TypingCommand::EndOfTurn,
+                update(1, "next"),
+                update(2, "other"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_worker_types_queued_turns() {
+        let mut keyboard = VirtualKeyboard::new(MockKeyboardHardware::new());
+        keyboard.set_voice_enter_enabled(true);
+        let (sender, handle) = spawn_typing_worker(keyboard);
+
+        sender.update(0, "hello");
+        sender.update(0, "hello wor");
+        sender.update(0, "hello world enter");
+        sender.end_of_turn();
+        sender.update(1, "again");
+        sender.close();
+
+        let mut keyboard = handle.join().unwrap();
+        let hardware = keyboard.hardware_mut();
+        assert_eq!(
+            hardware.typed_chars.iter().collect::<String>(),
+            "hello worldagain"
+        );
+        assert!(hardware.enter_pressed);
+        assert_eq!(keyboard.get_current_text(), "again");
+    }
+}
@@ -0,0 +1,124 @@
+//! Retracting dictated text by voice.
+//!
+//! A turn ending in "never mind" or "cancel" is erased instead of committed.
+//! "Scratch that" erases what was said before it in the same turn, or the
+//! previous turn if it was said on its own. Typed turns are only remembered
+//! until a key such as Enter is pressed, since that may have sent them.
+
+use regex::Regex;
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+
+pub use crate::config::DEFAULT_MAX_UNDO_CHARS;
+use crate::turn_format::TurnEnd;
+
+/// How many finished turns can be scratched, most recent first
+pub const HISTORY_TURNS: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UndoCommand {
+    /// "never mind" / "cancel": drop the current turn
+    Cancel,
+    /// "scratch that": drop the current turn, or the previous one if the
+    /// command was all that was said
+    ScratchThat,
+}
+
+/// Find an undo command at the end of `text`, returning the byte offset
+/// where it (including the whitespace before it) starts
+pub fn find_command(text: &str) -> Option<(usize, UndoCommand)> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| {
+        Regex::new(r"(?i)\s*\b(?:(scratch\s+that)|never\s*mind|cancel)\b[[:punct:]\s]*$").unwrap()
+    });
+    let captures = pattern.captures(text)?;
+    let command = if captures.get(1).is_some() {
+        UndoCommand::ScratchThat
+    } else {
+        UndoCommand::Cancel
+    };
+    Some((captures.get(0).unwrap().start(), command))
+}
+
+/// A finished turn that can be scratched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UndoEntry {
+    /// Characters the turn put on screen
+    pub chars: usize,
+    /// How the text before the turn ended, to format the next turn once
+    /// this one is gone
+    pub previous_end: Option<TurnEnd>,
+}
+
+/// Recently finished turns, oldest first
+#[derive(Debug, Default)]
+pub struct UndoHistory {
+    turns: VecDeque<UndoEntry>,
+}
+
+impl UndoHistory {
+    /// Remember a finished turn; turns that typed nothing are skipped
+    pub fn push(&mut self, entry: UndoEntry) {
+        if entry.chars == 0 {
+            return;
+        }
+        if self.turns.len() == HISTORY_TURNS {
+            self.turns.pop_front();
+        }
+        self.turns.push_back(entry);
+    }
+
+    /// Take the most recent turn
+    pub fn pop(&mut self) -> Option<UndoEntry> {
+        self.turns.pop_back()
+    }
+
+    /// Forget every turn, e.g. once Enter may have sent them
+    pub fn clear(&mut self) {
+        self.turns.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.turns.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_command() {
+        assert_eq!(
+            find_command("hello there, never mind."),
+            Some(("hello there,".len(), UndoCommand::Cancel))
+        );
+        assert_eq!(find_command("Nevermind"), Some((0, UndoCommand::Cancel)));
+        assert_eq!(find_command("cancel"), Some((0, UndoCommand::Cancel)));
+        assert_eq!(
+            find_command("Scratch that!"),
+            Some((0, UndoCommand::ScratchThat))
+        );
+        assert_eq!(find_command("please cancel the meeting"), None);
+        assert_eq!(find_command("scratch this"), None);
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let entry = |chars| UndoEntry {
+            chars,
+            previous_end: None,
+        };
+        let mut history = UndoHistory::default();
+        history.push(entry(0));
+        assert_eq!(history.len(), 0);
+
+        for chars in 1..=HISTORY_TURNS + 5 {
+            history.push(entry(chars));
+        }
+        assert_eq!(history.len(), HISTORY_TURNS);
+        assert_eq!(history.pop(), Some(entry(HISTORY_TURNS + 5)));
+        history.clear();
+        assert_eq!(history.pop(), None);
+    }
+}
@@ -0,0 +1,305 @@
+//! Fallback strategies for typing characters that have no key on the active
+//! keyboard layout (accented letters, typographic punctuation, emoji,
+//! non-Latin scripts).
+//!
+//! Which strategy works depends on the desktop: GTK applications and IBus
+//! accept Ctrl+Shift+U followed by the hex code point, while X11/XKB setups
+//! with a Compose key accept the standard compose sequences.
+
+use anyhow::{bail, Result};
+
+use crate::input_event::*;
+use crate::keymap::{KeyMapping, Keymap};
+
+/// A single key action emitted by a Unicode input strategy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyStroke {
+    /// Press and release a key with the modifiers for its shift level
+    Tap(KeyMapping),
+    /// Press the keys in order, then release them in reverse order
+    Chord(Vec<u16>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnicodeInput {
+    /// Skip characters that are not on the keymap
+    Disabled,
+    /// GTK/IBus hex entry: Ctrl+Shift+U, the code point in hex, then Space
+    CtrlShiftU,
+    /// Compose key followed by a standard XKB compose sequence
+    Compose { compose_key: u16 },
+}
+
+impl UnicodeInput {
+    /// Parse a strategy name: `none`, `ctrl-shift-u` or `compose`
+    pub fn parse(value: &str, compose_key: u16) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "none" | "off" => Ok(UnicodeInput::Disabled),
+            "ctrl-shift-u" | "ibus" | "gtk" => Ok(UnicodeInput::CtrlShiftU),
+            "compose" => Ok(UnicodeInput::Compose { compose_key }),
+            other => bail!(
+                "Unknown Unicode input method '{}': expected none, ctrl-shift-u or compose",
+                other
+            ),
+        }
+    }
+
+    /// Pick a strategy from the current environment
+    pub fn detect() -> Self {
+        Self::detect_from(|name| std::env::var(name).ok())
+    }
+
+    /// Use Ctrl+Shift+U when an IBus input method or a GNOME session is in
+    /// use, since both handle it; otherwise keep the old skip behaviour, as
+    /// the sequence would be typed literally into applications that do not.
+    pub fn detect_from<F>(var: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let contains = |name: &str, needle: &str| {
+            var(name).is_some_and(|value| value.to_ascii_lowercase().contains(needle))
+        };
+
+        if contains("GTK_IM_MODULE", "ibus")
+            || contains("QT_IM_MODULE", "ibus")
+            || contains("XMODIFIERS", "ibus")
+            || contains("XDG_CURRENT_DESKTOP", "gnome")
+        {
+            UnicodeInput::CtrlShiftU
+        } else {
+            UnicodeInput::Disabled
+        }
+    }
+
+    /// Key strokes that produce `c`, or `None` if this strategy cannot type
+    /// it. The hex digits and compose sequences are typed through `keymap`,
+    /// since both are interpreted by the active layout.
+    pub fn strokes(&self, c: char, keymap: &Keymap) -> Option<Vec<KeyStroke>> {
+        match self {
+            UnicodeInput::Disabled => None,
+            UnicodeInput::CtrlShiftU => {
+                let u = keymap.lookup('u')?;
+                let mut strokes = vec![KeyStroke::Chord(vec![
+                    KEY_LEFTCTRL,
+                    KEY_LEFTSHIFT,
+                    u.keycode,
+                ])];
+                for digit in format!("{:x}", c as u32).chars() {
+                    strokes.push(KeyStroke::Tap(keymap.lookup(digit)?));
+                }
+                strokes.push(KeyStroke::Tap(keymap.lookup(' ')?));
+                Some(strokes)
+            }
+            UnicodeInput::Compose { compose_key } => {
+                let sequence = compose_sequence(c)?;
+                let mut strokes = vec![KeyStroke::Tap(KeyMapping {
+                    keycode: *compose_key,
+                    shift: false,
+                    level3: None,
+                })];
+                for key in sequence.chars() {
+                    strokes.push(KeyStroke::Tap(keymap.lookup(key)?));
+                }
+                Some(strokes)
+            }
+        }
+    }
+}
+
+/// Parse the key used as Compose: `menu`, `rightalt`, `rightctrl`,
+/// `rightmeta`, `leftmeta`, `capslock`, `scrolllock` or `pause`
+pub fn parse_compose_key(value: &str) -> Result<u16> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "menu" | "compose" => Ok(KEY_COMPOSE),
+        "rightalt" | "ralt" | "altgr" => Ok(KEY_RIGHTALT),
+        "rightctrl" | "rctrl" => Ok(KEY_RIGHTCTRL),
+        "rightmeta" | "rwin" => Ok(KEY_RIGHTMETA),
+        "leftmeta" | "lwin" => Ok(KEY_LEFTMETA),
+        "capslock" => Ok(KEY_CAPSLOCK),
+        "scrolllock" => Ok(KEY_SCROLLLOCK),
+        "pause" => Ok(KEY_PAUSE),
+        other => bail!("Unknown compose key '{}'", other),
+    }
+}
+
+/// Accented letters and the compose prefix that produces them
+const COMPOSE_ACCENTS: &[(char, &str)] = &[
+    ('\'', "aeiouyAEIOUY"),
+    ('`', "aeiouAEIOU"),
+    ('^', "aeiouAEIOU"),
+    ('"', "aeiouyAEIOU"),
+    ('~', "anoANO"),
+];
+
+/// Characters produced by each accent prefix, in the same order as
+/// [`COMPOSE_ACCENTS`]
+const COMPOSE_ACCENTED: &[&str] = &[
+    "áéíóúýÁÉÍÓÚÝ",
+    "àèìòùÀÈÌÒÙ",
+    "âêîôûÂÊÎÔÛ",
+    "äëïöüÿÄËÏÖÜ",
+    "ãñõÃÑÕ",
+];
+
+/// Other commonly dictated characters from the default XKB Compose table
+const COMPOSE_SYMBOLS: &[(char, &str)] = &[
+    ('ç', ",c"),
+    ('Ç', ",C"),
+    ('ß', "ss"),
+    ('æ', "ae"),
+    ('Æ', "AE"),
+    ('œ', "oe"),
+    ('Œ', "OE"),
+    ('ø', "/o"),
+    ('Ø', "/O"),
+    ('å', "oa"),
+    ('Å', "OA"),
+    ('—', "---"),
+    ('–', "--."),
+    ('…', ".."),
+    ('‘', "<'"),
+    ('’', ">'"),
+    ('“', "<\""),
+    ('”', ">\""),
+    ('«', "<<"),
+    ('»', ">>"),
+    ('¡', "!!"),
+    ('¿', "??"),
+    ('€', "=e"),
+    ('£', "L-"),
+    ('°', "oo"),
+    ('©', "oc"),
+    ('®', "or"),
+    ('™', "tm"),
+    ('×', "xx"),
+    ('÷', ":-"),
+    ('±', "+-"),
+];
+
+/// Compose sequence (after the Compose key) for `c`
+pub fn compose_sequence(c: char) -> Option<String> {
+    for ((accent, bases), accented) in COMPOSE_ACCENTS.iter().zip(COMPOSE_ACCENTED) {
+        if let Some(index) = accented.chars().position(|a| a == c) {
+            let base = bases.chars().nth(index)?;
+            return Some(format!("{}{}", accent, base));
+        }
+    }
+
+    COMPOSE_SYMBOLS
+        .iter()
+        .find(|(symbol, _)| *symbol == c)
+        .map(|(_, sequence)| sequence.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tap(keycode: u16, shift: bool) -> KeyStroke {
+        KeyStroke::Tap(KeyMapping {
+            keycode,
+            shift,
+            level3: None,
+        })
+    }
+
+    #[test]
+    fn test_ctrl_shift_u_strokes() {
+        let strokes = UnicodeInput::CtrlShiftU
+            .strokes('é', &Keymap::us())
+            .unwrap();
+        assert_eq!(
+            strokes,
+            vec![
+                KeyStroke::Chord(vec![KEY_LEFTCTRL, KEY_LEFTSHIFT, KEY_U]),
+                tap(KEY_E, false),
+                tap(KEY_9, false),
+                tap(KEY_SPACE, false),
+            ]
+        );
+
+        // Emoji are outside the BMP but still plain hex
+        let strokes = UnicodeInput::CtrlShiftU
+            .strokes('😀', &Keymap::us())
+            .unwrap();
+        assert_eq!(strokes.len(), 1 + 5 + 1);
+    }
+
+    #[test]
+    fn test_compose_strokes() {
+        let keymap = Keymap::us();
+        let input = UnicodeInput::Compose {
+            compose_key: KEY_RIGHTALT,
+        };
+        assert_eq!(
+            input.strokes('é', &keymap).unwrap(),
+            vec![
+                tap(KEY_RIGHTALT, false),
+                tap(KEY_APOSTROPHE, false),
+                tap(KEY_E, false)
+            ]
+        );
+        assert_eq!(
+            input.strokes('—', &keymap).unwrap(),
+            vec![
+                tap(KEY_RIGHTALT, false),
+                tap(KEY_MINUS, false),
+                tap(KEY_MINUS, false),
+                tap(KEY_MINUS, false)
+            ]
+        );
+        // No compose sequence exists for emoji or non-Latin scripts
+        assert_eq!(input.strokes('😀', &keymap), None);
+        assert_eq!(input.strokes('ж', &keymap), None);
+    }
+
+    #[test]
+    fn test_compose_sequences() {
+        assert_eq!(compose_sequence('Ü').as_deref(), Some("\"U"));
+        assert_eq!(compose_sequence('ñ').as_deref(), Some("~n"));
+        assert_eq!(compose_sequence('”').as_deref(), Some(">\""));
+        assert_eq!(compose_sequence('a'), None);
+    }
+
+    #[test]
+    fn test_accent_tables_line_up() {
+        for ((_, bases), accented) in COMPOSE_ACCENTS.iter().zip(COMPOSE_ACCENTED) {
+            assert_eq!(bases.chars().count(), accented.chars().count());
+        }
+    }
+
+    #[test]
+    fn test_disabled_types_nothing() {
+        assert_eq!(UnicodeInput::Disabled.strokes('é', &Keymap::us()), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            UnicodeInput::parse("ctrl-shift-u", KEY_COMPOSE).unwrap(),
+            UnicodeInput::CtrlShiftU
+        );
+        assert_eq!(
+            UnicodeInput::parse("compose", KEY_RIGHTALT).unwrap(),
+            UnicodeInput::Compose {
+                compose_key: KEY_RIGHTALT
+            }
+        );
+        assert!(UnicodeInput::parse("telepathy", KEY_COMPOSE).is_err());
+        assert_eq!(parse_compose_key("AltGr").unwrap(), KEY_RIGHTALT);
+        assert!(parse_compose_key("spacebar").is_err());
+    }
+
+    #[test]
+    fn test_detect() {
+        let ibus = |name: &str| (name == "GTK_IM_MODULE").then(|| "ibus".to_string());
+        assert_eq!(UnicodeInput::detect_from(ibus), UnicodeInput::CtrlShiftU);
+
+        let gnome =
+            |name: &str| (name == "XDG_CURRENT_DESKTOP").then(|| "ubuntu:GNOME".to_string());
+        assert_eq!(UnicodeInput::detect_from(gnome), UnicodeInput::CtrlShiftU);
+
+        let kde = |name: &str| (name == "XDG_CURRENT_DESKTOP").then(|| "KDE".to_string());
+        assert_eq!(UnicodeInput::detect_from(kde), UnicodeInput::Disabled);
+    }
+}
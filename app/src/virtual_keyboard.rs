@@ -1,15 +1,29 @@
 #![allow(dead_code)]
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use nix::fcntl::{open, OFlag};
 use nix::sys::stat::Mode;
 use nix::unistd::close;
-use regex::Regex;
 use std::io::Write;
 use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tracing::{debug, error, info, warn};
 
+use crate::cadence::Cadence;
+use crate::clipboard::Clipboard;
+use crate::code_mode::{self, CodeFormatter};
 use crate::input_event::*;
+use crate::itn;
+use crate::keymap::Keymap;
+use crate::replacements::Replacements;
+use crate::snippets::Snippets;
+use crate::spoken_punctuation::SpokenPunctuation;
+use crate::transliterate::Transliterator;
+use crate::triggers::Triggers;
+use crate::turn_format::TurnFormatter;
+use crate::undo::{self, UndoCommand, UndoEntry, UndoHistory};
+use crate::unicode_input::{KeyStroke, UnicodeInput};
 
 // Define ioctl macros for uinput
 // The nix ioctl_write_int! macro requires the ioctl type and number
@@ -17,24 +31,195 @@ nix::ioctl_write_int!(ui_set_evbit, b'U', 100);
 nix::ioctl_write_int!(ui_set_keybit, b'U', 101);
 nix::ioctl_none!(ui_dev_create, b'U', 1);
 nix::ioctl_none!(ui_dev_destroy, b'U', 2);
+nix::ioctl_write_ptr!(ui_dev_setup, b'U', 3, UInputSetup);
+nix::ioctl_read_buf!(ui_get_sysname, b'U', 44, u8);
+nix::ioctl_read!(ui_get_version, b'U', 45, libc::c_uint);
+
+/// Where the kernel lists uinput-created devices
+const SYSFS_VIRTUAL_INPUT: &str = "/sys/devices/virtual/input";
+
+/// Identity the virtual keyboard presents to the system.
+///
+/// udev and hwdb rules match on these values, so users can, for example,
+/// give the device its own keymap or exclude it from other tools.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceIdentity {
+    pub name: String,
+    pub bustype: u16,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
+}
+
+impl Default for DeviceIdentity {
+    fn default() -> Self {
+        Self {
+            name: "Voice Keyboard".to_string(),
+            bustype: BUS_USB,
+            vendor: 0x1234,
+            product: 0x5678,
+            version: 1,
+        }
+    }
+}
+
+impl DeviceIdentity {
+    fn input_id(&self) -> InputId {
+        InputId {
+            bustype: self.bustype,
+            vendor: self.vendor,
+            product: self.product,
+            version: self.version,
+        }
+    }
+}
+
+/// Parse a vendor or product id. Ids are hexadecimal as in `lsusb` and udev
+/// rules, with or without a `0x` prefix.
+pub fn parse_device_id(value: &str) -> Result<u16> {
+    let digits = value
+        .trim()
+        .trim_start_matches("0x")
+        .trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).map_err(|_| {
+        anyhow!(
+            "Invalid device id '{}': expected a hex value such as 0x1234",
+            value
+        )
+    })
+}
+
+/// Parse a bus type given by name (usb, bluetooth, virtual, pci, i8042, i2c)
+/// or as a hex number
+pub fn parse_bus_type(value: &str) -> Result<u16> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "usb" => Ok(BUS_USB),
+        "bluetooth" => Ok(BUS_BLUETOOTH),
+        "virtual" => Ok(BUS_VIRTUAL),
+        "pci" => Ok(BUS_PCI),
+        "i8042" => Ok(BUS_I8042),
+        "i2c" => Ok(BUS_I2C),
+        other => parse_device_id(other).map_err(|_| {
+            anyhow!(
+                "Unknown bus type '{}': expected usb, bluetooth, virtual, pci, i8042, i2c or a hex value",
+                value
+            )
+        }),
+    }
+}
+
+/// Decode the NUL-terminated name returned by UI_GET_SYSNAME
+fn sysname_from_buf(buf: &[u8]) -> Option<String> {
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    let name = std::str::from_utf8(&buf[..len]).ok()?;
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+/// Find the `/dev/input/eventN` node for the input device whose sysfs
+/// directory is `sysfs_dir`
+fn find_event_node(sysfs_dir: &Path) -> Option<PathBuf> {
+    std::fs::read_dir(sysfs_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .find(|name| {
+            name.strip_prefix("event")
+                .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        })
+        .map(|name| Path::new("/dev/input").join(name))
+}
+
+/// One character passed to [`KeyboardHardware::type_text`] and whether it
+/// actually produced a character on screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TypedChar {
+    pub intended: char,
+    pub emitted: bool,
+}
 
 /// Hardware abstraction trait for keyboard operations
 pub trait KeyboardHardware {
-    fn type_text(&mut self, text: &str) -> Result<()>;
+    /// Type `text`, reporting for every character whether it was emitted or
+    /// skipped, so callers know how many backspaces remove it again
+    fn type_text(&mut self, text: &str) -> Result<Vec<TypedChar>>;
     fn press_backspace(&mut self) -> Result<()>;
     fn press_enter(&mut self) -> Result<()>;
     fn press_key(&mut self, keycode: u16) -> Result<()>;
+    /// Press `keycodes` in order, then release them in reverse order
+    fn press_combo(&mut self, keycodes: &[u16]) -> Result<()>;
+
+    /// Whether `type_text` can produce `c`
+    fn can_type(&self, _c: char) -> bool {
+        true
+    }
+
+    /// Press backspace `count` times
+    fn press_backspaces(&mut self, count: usize) -> Result<()> {
+        for _ in 0..count {
+            self.press_backspace()?;
+        }
+        Ok(())
+    }
+
+    /// Set how fast keys are sent; hardware without pacing ignores it
+    fn set_cadence(&mut self, _cadence: Cadence) {}
+}
+
+impl<T: KeyboardHardware + ?Sized> KeyboardHardware for Box<T> {
+    fn type_text(&mut self, text: &str) -> Result<Vec<TypedChar>> {
+        (**self).type_text(text)
+    }
+
+    fn press_backspace(&mut self) -> Result<()> {
+        (**self).press_backspace()
+    }
+
+    fn press_enter(&mut self) -> Result<()> {
+        (**self).press_enter()
+    }
+
+    fn press_key(&mut self, keycode: u16) -> Result<()> {
+        (**self).press_key(keycode)
+    }
+
+    fn press_combo(&mut self, keycodes: &[u16]) -> Result<()> {
+        (**self).press_combo(keycodes)
+    }
+
+    fn can_type(&self, c: char) -> bool {
+        (**self).can_type(c)
+    }
+
+    fn press_backspaces(&mut self, count: usize) -> Result<()> {
+        (**self).press_backspaces(count)
+    }
+
+    fn set_cadence(&mut self, cadence: Cadence) {
+        (**self).set_cadence(cadence)
+    }
 }
 
 /// Real hardware implementation using Linux uinput
 pub struct RealKeyboardHardware {
     fd: i32,
     name: String,
+    sysname: Option<String>,
+    event_node: Option<PathBuf>,
+    keymap: Keymap,
+    unicode_input: UnicodeInput,
+    cadence: Cadence,
 }
 
 impl RealKeyboardHardware {
-    pub fn new(device_name: &str) -> Result<Self> {
-        info!("Creating virtual keyboard device: {}", device_name);
+    pub fn new(identity: &DeviceIdentity) -> Result<Self> {
+        info!(
+            "Creating virtual keyboard device: {} ({:04x}:{:04x}, bus 0x{:02x})",
+            identity.name, identity.vendor, identity.product, identity.bustype
+        );
 
         // Open uinput device
         let fd = open(
@@ -62,17 +247,97 @@ impl RealKeyboardHardware {
             }
         }
 
-        // Set up device using the legacy uinput_user_dev interface
+        // Prefer UI_DEV_SETUP (uinput 5+, Linux 4.5) and fall back to
+        // writing the legacy uinput_user_dev struct on older kernels
+        if !Self::try_dev_setup(fd, identity) {
+            Self::write_legacy_setup(fd, identity)?;
+        }
+
+        // Create the device
+        unsafe {
+            ui_dev_create(fd).context("Failed to create uinput device")?;
+        }
+
+        let sysname = Self::query_sysname(fd);
+        let event_node = sysname
+            .as_deref()
+            .and_then(|sysname| find_event_node(&Path::new(SYSFS_VIRTUAL_INPUT).join(sysname)));
+
+        info!("Virtual keyboard '{}' created successfully", identity.name);
+        match (&sysname, &event_node) {
+            (_, Some(node)) => info!("Virtual keyboard event device: {}", node.display()),
+            (Some(sysname), None) => info!(
+                "Virtual keyboard registered as {}/{} (event node not found)",
+                SYSFS_VIRTUAL_INPUT, sysname
+            ),
+            (None, None) => debug!("Kernel did not report the virtual keyboard's sysfs name"),
+        }
+
+        Ok(Self {
+            fd,
+            name: identity.name.clone(),
+            sysname,
+            event_node,
+            keymap: Keymap::us(),
+            unicode_input: UnicodeInput::Disabled,
+            cadence: Cadence::default(),
+        })
+    }
+
+    /// Wrap an already open file descriptor, e.g. a pipe in tests
+    #[cfg(test)]
+    fn from_fd(fd: i32) -> Self {
+        Self {
+            fd,
+            name: "Test Keyboard".to_string(),
+            sysname: None,
+            event_node: None,
+            keymap: Keymap::us(),
+            unicode_input: UnicodeInput::Disabled,
+            cadence: Cadence::default(),
+        }
+    }
+
+    /// Configure the device with UI_DEV_SETUP; returns false if the kernel
+    /// does not support it
+    fn try_dev_setup(fd: i32, identity: &DeviceIdentity) -> bool {
+        let mut version: libc::c_uint = 0;
+        match unsafe { ui_get_version(fd, &mut version) } {
+            Ok(_) if version >= UINPUT_VERSION_DEV_SETUP => {
+                debug!("uinput version {}; using UI_DEV_SETUP", version);
+            }
+            Ok(_) => {
+                debug!(
+                    "uinput version {} predates UI_DEV_SETUP; using legacy setup",
+                    version
+                );
+                return false;
+            }
+            Err(e) => {
+                debug!("UI_GET_VERSION not supported ({}); using legacy setup", e);
+                return false;
+            }
+        }
+
+        let setup = UInputSetup::new(&identity.name, identity.input_id());
+        match unsafe { ui_dev_setup(fd, &setup) } {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("UI_DEV_SETUP failed ({}); falling back to legacy setup", e);
+                false
+            }
+        }
+    }
+
+    /// Configure the device through the legacy uinput_user_dev interface
+    fn write_legacy_setup(fd: i32, identity: &DeviceIdentity) -> Result<()> {
         let mut uidev = crate::input_event::UInputUserDev::default();
         // Set device name
-        let name_bytes = device_name.as_bytes();
+        let name_bytes = identity.name.as_bytes();
         let copy_len = std::cmp::min(name_bytes.len(), uidev.name.len() - 1);
         uidev.name[..copy_len].copy_from_slice(&name_bytes[..copy_len]);
         // Set device id
-        uidev.id.bustype = 0x03; // USB
-        uidev.id.vendor = 0x1234;
-        uidev.id.product = 0x5678;
-        uidev.id.version = 1;
+        uidev.id = identity.input_id();
         uidev.ff_effects_max = 0;
 
         // Write device info
@@ -84,31 +349,63 @@ impl RealKeyboardHardware {
             )
         };
 
-        file.write_all(uidev_bytes)
-            .context("Failed to write device info")?;
+        let result = file
+            .write_all(uidev_bytes)
+            .context("Failed to write device info");
 
         // Prevent the file from being closed when it goes out of scope
         std::mem::forget(file);
 
-        // Create the device
-        unsafe {
-            ui_dev_create(fd).context("Failed to create uinput device")?;
+        result
+    }
+
+    /// Ask the kernel for the sysfs name (e.g. `input23`) of the created device
+    fn query_sysname(fd: i32) -> Option<String> {
+        let mut buf = [0u8; 64];
+        match unsafe { ui_get_sysname(fd, &mut buf) } {
+            Ok(_) => sysname_from_buf(&buf),
+            Err(e) => {
+                debug!("UI_GET_SYSNAME not supported: {}", e);
+                None
+            }
         }
+    }
 
-        info!("Virtual keyboard '{}' created successfully", device_name);
+    /// Use `keymap` to find the key for each character
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        debug!("Keyboard layout: {}", keymap.name());
+        self.keymap = keymap;
+    }
 
-        Ok(Self {
-            fd,
-            name: device_name.to_string(),
-        })
+    /// Layout used to find the key for each character
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
     }
 
-    fn send_event(&self, event: InputEvent) -> Result<()> {
+    /// Choose how characters missing from the keymap are typed
+    pub fn set_unicode_input(&mut self, unicode_input: UnicodeInput) {
+        debug!("Unicode input strategy: {:?}", unicode_input);
+        self.unicode_input = unicode_input;
+    }
+
+    /// sysfs name of the device, e.g. `input23`
+    pub fn sysname(&self) -> Option<&str> {
+        self.sysname.as_deref()
+    }
+
+    /// `/dev/input/eventN` node of the device, if it could be determined
+    pub fn event_node(&self) -> Option<&Path> {
+        self.event_node.as_deref()
+    }
+
+    /// Write `events` to the device with a single syscall
+    fn write_events(&self, events: &[InputEvent]) -> Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+
         let event_bytes = unsafe {
-            std::slice::from_raw_parts(
-                &event as *const _ as *const u8,
-                std::mem::size_of::<InputEvent>(),
-            )
+            std::slice::from_raw_parts(events.as_ptr() as *const u8, std::mem::size_of_val(events))
         };
 
         let bytes_written = unsafe {
@@ -119,6 +416,9 @@ impl RealKeyboardHardware {
             )
         };
 
+        if bytes_written < 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to write input events");
+        }
         if bytes_written != event_bytes.len() as isize {
             return Err(anyhow::anyhow!(
                 "Partial write: expected {} bytes, wrote {}",
@@ -130,50 +430,97 @@ impl RealKeyboardHardware {
         Ok(())
     }
 
-    fn send_key(&self, keycode: u16, pressed: bool) -> Result<()> {
+    /// Queue a key event followed by a synchronization event
+    fn push_key(events: &mut Vec<InputEvent>, keycode: u16, pressed: bool) {
         debug!("Sending key: {} (pressed: {})", keycode, pressed);
+        events.push(InputEvent::key_event(keycode, pressed));
+        events.push(InputEvent::syn_event());
+    }
 
-        // Send key event
-        let key_event = InputEvent::key_event(keycode, pressed);
-        self.send_event(key_event)?;
-
-        // Send synchronization event
-        let syn_event = InputEvent::syn_event();
-        self.send_event(syn_event)?;
+    /// Queue the key events for `strokes`
+    fn push_strokes(events: &mut Vec<InputEvent>, strokes: &[KeyStroke]) {
+        for stroke in strokes {
+            match stroke {
+                KeyStroke::Tap(mapping) => {
+                    // Hold the level three shift (usually AltGr) and Shift
+                    // as the layout's key type requires
+                    if let Some(level3) = mapping.level3 {
+                        Self::push_key(events, level3, true);
+                    }
+                    if mapping.shift {
+                        Self::push_key(events, KEY_LEFTSHIFT, true);
+                    }
+                    Self::push_key(events, mapping.keycode, true);
+                    Self::push_key(events, mapping.keycode, false);
+                    if mapping.shift {
+                        Self::push_key(events, KEY_LEFTSHIFT, false);
+                    }
+                    if let Some(level3) = mapping.level3 {
+                        Self::push_key(events, level3, false);
+                    }
+                }
+                KeyStroke::Chord(keycodes) => {
+                    for keycode in keycodes {
+                        Self::push_key(events, *keycode, true);
+                    }
+                    for keycode in keycodes.iter().rev() {
+                        Self::push_key(events, *keycode, false);
+                    }
+                }
+            }
+        }
+    }
 
-        Ok(())
+    fn send_strokes(&self, strokes: &[KeyStroke]) -> Result<()> {
+        let mut events = Vec::new();
+        Self::push_strokes(&mut events, strokes);
+        self.write_events(&events)
     }
 }
 
 impl KeyboardHardware for RealKeyboardHardware {
-    fn type_text(&mut self, text: &str) -> Result<()> {
+    fn type_text(&mut self, text: &str) -> Result<Vec<TypedChar>> {
         debug!("Typing text: '{}'", text);
 
+        // Characters are written `batch_size` at a time, pausing after
+        // each batch so the receiving application keeps up
+        let mut typed = Vec::with_capacity(text.len());
+        let mut events = Vec::new();
+        let mut batched = 0;
         for c in text.chars() {
-            if let Some((keycode, needs_shift)) = char_to_keycode(c) {
-                if needs_shift {
-                    // Press shift
-                    self.send_key(KEY_LEFTSHIFT, true)?;
-
-                    // Press the key
-                    self.send_key(keycode, true)?;
-                    self.send_key(keycode, false)?;
-
-                    // Release shift
-                    self.send_key(KEY_LEFTSHIFT, false)?;
-                } else {
-                    // Just press the key
-                    self.press_key(keycode)?;
-                }
-
-                // Small delay between characters for more natural typing
-                std::thread::sleep(std::time::Duration::from_millis(10));
+            let emitted = if let Some(mapping) = self.keymap.lookup(c) {
+                Self::push_strokes(&mut events, &[KeyStroke::Tap(mapping)]);
+                true
+            } else if let Some(strokes) = self.unicode_input.strokes(c, &self.keymap) {
+                debug!("Typing '{}' via {:?}", c, self.unicode_input);
+                Self::push_strokes(&mut events, &strokes);
+                true
             } else {
                 warn!("Unsupported character: '{}'", c);
+                false
+            };
+            typed.push(TypedChar {
+                intended: c,
+                emitted,
+            });
+
+            if emitted {
+                batched += 1;
+                if batched == self.cadence.batch_size {
+                    self.write_events(&events)?;
+                    events.clear();
+                    batched = 0;
+                    std::thread::sleep(self.cadence.char_delay);
+                }
             }
         }
 
-        Ok(())
+        if batched > 0 {
+            self.write_events(&events)?;
+            std::thread::sleep(self.cadence.char_delay);
+        }
+
+        Ok(typed)
     }
 
     fn press_backspace(&mut self) -> Result<()> {
@@ -185,10 +532,37 @@ impl KeyboardHardware for RealKeyboardHardware {
     }
 
     fn press_key(&mut self, keycode: u16) -> Result<()> {
-        self.send_key(keycode, true)?;
-        self.send_key(keycode, false)?;
+        self.press_combo(&[keycode])
+    }
+
+    fn press_combo(&mut self, keycodes: &[u16]) -> Result<()> {
+        self.send_strokes(&[KeyStroke::Chord(keycodes.to_vec())])
+    }
+
+    fn can_type(&self, c: char) -> bool {
+        self.keymap.lookup(c).is_some() || self.unicode_input.strokes(c, &self.keymap).is_some()
+    }
+
+    fn press_backspaces(&mut self, count: usize) -> Result<()> {
+        let mut remaining = count;
+        while remaining > 0 {
+            let batch = remaining.min(self.cadence.batch_size.max(1));
+            let mut events = Vec::with_capacity(batch * 4);
+            for _ in 0..batch {
+                Self::push_key(&mut events, KEY_BACKSPACE, true);
+                Self::push_key(&mut events, KEY_BACKSPACE, false);
+            }
+            self.write_events(&events)?;
+            std::thread::sleep(self.cadence.backspace_delay);
+            remaining -= batch;
+        }
         Ok(())
     }
+
+    fn set_cadence(&mut self, cadence: Cadence) {
+        debug!("Typing cadence: {:?}", cadence);
+        self.cadence = cadence;
+    }
 }
 
 impl Drop for RealKeyboardHardware {
@@ -216,12 +590,87 @@ impl Drop for RealKeyboardHardware {
 unsafe impl Send for RealKeyboardHardware {}
 unsafe impl Sync for RealKeyboardHardware {}
 
+/// Pastes finalized turns through the clipboard instead of typing them
+pub struct PasteOutput {
+    clipboard: Box<dyn Clipboard>,
+    /// Keycodes of the paste shortcut, e.g. Ctrl+V
+    combo: Vec<u16>,
+    /// How long to wait after pasting before restoring the old clipboard,
+    /// since the target application reads the clipboard asynchronously
+    restore_delay: Duration,
+}
+
+impl PasteOutput {
+    pub fn new(clipboard: Box<dyn Clipboard>, combo: Vec<u16>, restore_delay: Duration) -> Self {
+        Self {
+            clipboard,
+            combo,
+            restore_delay,
+        }
+    }
+
+    /// Put `text` on the clipboard, send the paste shortcut and restore
+    /// the previous clipboard contents
+    fn paste<H: KeyboardHardware>(&mut self, hardware: &mut H, text: &str) -> Result<()> {
+        let previous = match self.clipboard.get() {
+            Ok(previous) => Some(previous),
+            Err(e) => {
+                warn!(
+                    "Failed to read the clipboard, it will not be restored: {:#}",
+                    e
+                );
+                None
+            }
+        };
+
+        self.clipboard
+            .set(text)
+            .context("Failed to set the clipboard")?;
+        hardware.press_combo(&self.combo)?;
+        std::thread::sleep(self.restore_delay);
+
+        match previous {
+            Some(Some(previous)) => self.clipboard.set(&previous),
+            Some(None) => self.clipboard.clear(),
+            None => Ok(()),
+        }
+        .context("Failed to restore the clipboard")
+    }
+}
+
 /// Business logic layer that handles transcript processing and enter command detection
 pub struct VirtualKeyboard<H: KeyboardHardware> {
     hardware: H,
+    /// The transcript as it was meant to be typed
     current_text: String,
+    /// Outcome of typing each character of `current_text`; the on-screen
+    /// text only contains the emitted ones
+    typed: Vec<TypedChar>,
     interpret_enter_word: bool,
+    /// Phrases that end a turn with a key press, "enter" by default
+    triggers: Triggers,
     uppercase_enabled: bool,
+    /// Type spoken numbers, dates and amounts in written form
+    inverse_text_normalization: bool,
+    transliterator: Transliterator,
+    /// User rules fixing words the STT service gets wrong
+    replacements: Option<Replacements>,
+    /// Converts spoken commands such as "comma" into symbols when set
+    spoken_punctuation: Option<SpokenPunctuation>,
+    /// Interprets code commands instead of prose when set
+    code_formatter: Option<CodeFormatter>,
+    /// Stored text that a turn of "insert <name>" is replaced with
+    snippets: Option<Snippets>,
+    /// When set, turns are collected silently and pasted at end of turn
+    paste: Option<PasteOutput>,
+    /// Joins each turn onto the previous one when set
+    turn_formatter: Option<TurnFormatter>,
+    /// Whether "scratch that", "never mind" and "cancel" retract text
+    voice_undo_enabled: bool,
+    /// Most characters a single undo may erase
+    max_undo_chars: usize,
+    /// Finished turns that "scratch that" can still erase
+    undo_history: UndoHistory,
 }
 
 impl<H: KeyboardHardware> VirtualKeyboard<H> {
@@ -229,31 +678,158 @@ impl<H: KeyboardHardware> VirtualKeyboard<H> {
         Self {
             hardware,
             current_text: String::new(),
+            typed: Vec::new(),
             interpret_enter_word: true,
+            triggers: Triggers::default(),
             uppercase_enabled: false,
+            inverse_text_normalization: false,
+            transliterator: Transliterator::default(),
+            replacements: None,
+            spoken_punctuation: None,
+            code_formatter: None,
+            snippets: None,
+            paste: None,
+            turn_formatter: None,
+            voice_undo_enabled: false,
+            max_undo_chars: undo::DEFAULT_MAX_UNDO_CHARS,
+            undo_history: UndoHistory::default(),
         }
     }
 
-    /// Enable or disable interpreting the word 'enter' at the end of a turn
+    /// Enable or disable interpreting trigger phrases such as 'enter' at the
+    /// end of a turn
     pub fn set_voice_enter_enabled(&mut self, enabled: bool) {
         self.interpret_enter_word = enabled;
     }
 
+    /// Enable or disable spacing and capitalizing each turn to follow on
+    /// from the previous one
+    pub fn set_turn_formatting_enabled(&mut self, enabled: bool) {
+        self.turn_formatter = enabled.then(TurnFormatter::default);
+    }
+
+    /// Enable or disable retracting text with "scratch that", "never mind"
+    /// and "cancel"
+    pub fn set_voice_undo_enabled(&mut self, enabled: bool) {
+        self.voice_undo_enabled = enabled;
+    }
+
+    /// Refuse to erase more than `max_chars` characters with one undo
+    pub fn set_max_undo_chars(&mut self, max_chars: usize) {
+        self.max_undo_chars = max_chars;
+    }
+
+    /// Set the phrases that end a turn with a key press
+    pub fn set_triggers(&mut self, triggers: Triggers) {
+        self.triggers = triggers;
+    }
+
     /// Enable or disable uppercase conversion of all typed text
     pub fn set_uppercase_enabled(&mut self, enabled: bool) {
         self.uppercase_enabled = enabled;
     }
 
+    /// Enable or disable writing spoken numbers, dates and amounts as
+    /// digits and symbols
+    pub fn set_inverse_text_normalization_enabled(&mut self, enabled: bool) {
+        self.inverse_text_normalization = enabled;
+    }
+
+    /// Set how characters the hardware cannot type are replaced
+    pub fn set_transliterator(&mut self, transliterator: Transliterator) {
+        self.transliterator = transliterator;
+    }
+
+    /// Rewrite each transcript with user replacement rules; `None` types
+    /// transcripts as recognized
+    pub fn set_replacements(&mut self, replacements: Option<Replacements>) {
+        self.replacements = replacements;
+    }
+
+    /// Convert spoken punctuation and formatting commands; `None` types
+    /// every word as spoken
+    pub fn set_spoken_punctuation(&mut self, spoken_punctuation: Option<SpokenPunctuation>) {
+        self.spoken_punctuation = spoken_punctuation;
+    }
+
+    /// Switch between code mode, where spoken casing and symbol commands
+    /// are interpreted, and prose
+    pub fn set_code_mode_enabled(&mut self, enabled: bool) {
+        self.code_formatter = enabled.then(CodeFormatter::default);
+    }
+
+    pub fn code_mode_enabled(&self) -> bool {
+        self.code_formatter.is_some()
+    }
+
+    /// Replace turns of "insert <name>" with stored snippets; `None` types
+    /// such turns as spoken
+    pub fn set_snippets(&mut self, snippets: Option<Snippets>) {
+        self.snippets = snippets;
+    }
+
+    /// Paste each finished turn through the clipboard instead of typing it
+    /// as it is recognized; `None` switches back to typing
+    pub fn set_paste_output(&mut self, paste: Option<PasteOutput>) {
+        self.paste = paste;
+    }
+
     /// Update the transcript incrementally, handling smart backspacing
     /// 1. Type new characters if the new transcript extends the current one
     /// 2. Only backspace the characters that actually changed, then type the new ending
     pub fn update_transcript(&mut self, new_transcript: &str) -> Result<()> {
+        // Fix misrecognized words first, so later steps see the corrected text
+        let processed_transcript = match &mut self.replacements {
+            Some(replacements) => replacements.apply(new_transcript),
+            None => new_transcript.to_string(),
+        };
+
+        // Turn spoken commands into symbols, with code spacing in code mode
+        let processed_transcript = match (&self.code_formatter, &self.spoken_punctuation) {
+            (Some(code), _) => code.apply(&processed_transcript),
+            (None, Some(spoken)) => spoken.apply(&processed_transcript),
+            (None, None) => processed_transcript,
+        };
+
+        // Write numbers as digits once punctuation commands are symbols, so
+        // "one comma two" stays two separate numbers
+        let processed_transcript = if self.inverse_text_normalization {
+            itn::normalize(&processed_transcript)
+        } else {
+            processed_transcript
+        };
+
+        // Space and capitalize the turn to follow on from the previous one
+        // (code is only spaced, never capitalized)
+        let processed_transcript = match (&self.turn_formatter, &self.code_formatter) {
+            (Some(formatter), Some(_)) => formatter.format_code(&processed_transcript),
+            (Some(formatter), None) => formatter.format(&processed_transcript),
+            (None, _) => processed_transcript,
+        };
+
         // Conditionally convert the new transcript to uppercase
         let processed_transcript = if self.uppercase_enabled {
-            new_transcript.to_uppercase()
+            processed_transcript.to_uppercase()
         } else {
-            new_transcript.to_string()
+            processed_transcript
         };
+
+        // The clipboard takes any character, so only custom replacements
+        // apply when pasting; nothing is typed until the turn ends
+        if self.paste.is_some() {
+            self.current_text = self
+                .transliterator
+                .normalize(&processed_transcript, |_| true);
+            debug!("Collecting transcript for paste: '{}'", self.current_text);
+            return Ok(());
+        }
+
+        // Replace untypeable characters up front so that current_text
+        // matches what ends up on screen
+        let hardware = &self.hardware;
+        let processed_transcript = self
+            .transliterator
+            .normalize(&processed_transcript, |c| hardware.can_type(c));
         
         debug!(
             "Updating transcript from '{}' to '{}' (original: '{}', uppercase: {})",
@@ -272,8 +848,7 @@ impl<H: KeyboardHardware> VirtualKeyboard<H> {
             let new_chars = &processed_transcript[self.current_text.len()..];
             if !new_chars.is_empty() {
                 debug!("Typing new characters: '{}'", new_chars);
-                self.hardware.type_text(new_chars)?;
-                self.current_text = processed_transcript.to_string();
+                self.type_and_track(new_chars)?;
             }
         } else {
             // Find the common prefix between current and new transcript
@@ -284,88 +859,248 @@ impl<H: KeyboardHardware> VirtualKeyboard<H> {
                 .take_while(|(a, b)| a == b)
                 .count();
 
-            let current_chars: Vec<char> = self.current_text.chars().collect();
-            let chars_to_backspace = current_chars.len() - common_prefix_len;
-
             debug!(
                 "Common prefix length: {}, need to backspace {} characters",
-                common_prefix_len, chars_to_backspace
+                common_prefix_len,
+                self.on_screen_len_from(common_prefix_len)
             );
 
             // Only backspace the characters that differ
-            if chars_to_backspace > 0 {
-                debug!("Backspacing {} characters", chars_to_backspace);
-                for _ in 0..chars_to_backspace {
-                    self.hardware.press_backspace()?;
-                }
-            }
+            self.erase_from(common_prefix_len)?;
 
             // Type the new ending (everything after the common prefix)
             let new_chars: Vec<char> = processed_transcript.chars().collect();
             if common_prefix_len < new_chars.len() {
                 let new_ending: String = new_chars[common_prefix_len..].iter().collect();
                 debug!("Typing new ending: '{}'", new_ending);
-                self.hardware.type_text(&new_ending)?;
+                self.type_and_track(&new_ending)?;
             }
-
-            self.current_text = processed_transcript.to_string();
         }
 
         Ok(())
     }
 
     /// Finalize the current transcript
-    /// If the transcript ends with "enter" (with optional punctuation/whitespace),
-    /// backspace that portion and press the ENTER key
-    /// Otherwise, just finalize without pressing enter
+    /// If the transcript ends with a trigger phrase such as "enter" (with optional
+    /// punctuation/whitespace), backspace that portion and press the trigger's keys
+    /// Otherwise, just finalize without pressing anything
+    /// In paste mode the collected turn is pasted first
     pub fn finalize_transcript(&mut self) -> Result<()> {
         debug!("Finalizing transcript: '{}'", self.current_text);
+
+        if let Some(code) = code_mode::find_mode_command(&self.current_text) {
+            info!("Switching to {} mode", if code { "code" } else { "prose" });
+            // The command was only typed while it was being recognized
+            if self.paste.is_none() {
+                self.erase_from(0)?;
+            }
+            self.current_text.clear();
+            self.typed.clear();
+            self.set_code_mode_enabled(code);
+            return Ok(());
+        }
+
+        if self.voice_undo_enabled {
+            if let Some((start_pos, command)) = undo::find_command(&self.current_text) {
+                let said_before = !self.current_text[..start_pos].trim().is_empty();
+                let start_char = self.current_text[..start_pos].chars().count();
+                debug!("Found {:?} at end of transcript", command);
+                self.discard_turn(start_char)?;
+                if command == UndoCommand::ScratchThat && !said_before {
+                    self.undo_previous_turn()?;
+                }
+                return Ok(());
+            }
+        }
         
-        if self.interpret_enter_word {
-            // Regex to match "enter" (case-insensitive) at the end, optionally followed by 
-            // punctuation and/or whitespace: (?i)\s*\benter\b[[:punct:]\s]*$
-            // (?i) = case insensitive
-            // \s* = optional leading whitespace  
-            // \benter\b = the word "enter" with word boundaries
-            // [[:punct:]\s]* = optional trailing punctuation or whitespace
-            // $ = end of string
-            let enter_regex = Regex::new(r"(?i)\s*\benter\b[[:punct:]\s]*$").unwrap();
-            
-            // Find the match and extract the information we need before mutating self
-            let match_info = enter_regex.find(&self.current_text).map(|m| {
-                (m.start(), m.as_str().chars().count(), m.as_str().to_string())
-            });
-            
-            if let Some((start_pos, chars_to_backspace, matched_str)) = match_info {
-                debug!("Found 'enter' command at end of transcript: '{}'", matched_str);
-                debug!("Backspacing {} characters for 'enter' command", chars_to_backspace);
-                
-                // Backspace the matched portion
-                for _ in 0..chars_to_backspace {
-                    self.hardware.press_backspace()?;
-                    // Small delay between backspaces for reliability
-                    std::thread::sleep(std::time::Duration::from_millis(5));
+        // A snippet replaces the whole turn, so there is no trigger phrase
+        // left to look for
+        let inserted_snippet = self.insert_snippet()?;
+
+        // Keys pressed once the turn's text is in place
+        let mut action: Option<Vec<u16>> = None;
+        if inserted_snippet {
+            // Voice-enter disabled still presses ENTER below
+            if !self.interpret_enter_word {
+                action = Some(vec![KEY_ENTER]);
+            }
+        } else if self.interpret_enter_word {
+            // Find the trigger phrase and extract the information we need
+            // before mutating self
+            let match_info = self
+                .triggers
+                .find(&self.current_text)
+                .map(|(start, trigger)| {
+                    (
+                        start,
+                        self.current_text[..start].chars().count(),
+                        trigger.clone(),
+                    )
+                });
+
+            if let Some((start_pos, start_char, trigger)) = match_info {
+                debug!(
+                    "Found trigger phrase '{}' at end of transcript: '{}'",
+                    trigger.phrase,
+                    &self.current_text[start_pos..]
+                );
+
+                if self.paste.is_some() {
+                    // Nothing was typed yet, so just drop the phrase
+                    self.current_text.truncate(start_pos);
+                } else {
+                    debug!(
+                        "Backspacing {} characters for trigger phrase '{}'",
+                        self.on_screen_len_from(start_char),
+                        trigger.phrase
+                    );
+
+                    // Backspace the matched portion, paced by the hardware's
+                    // cadence for reliability
+                    self.erase_from(start_char)?;
                 }
-                
-                // Update our internal tracking to remove the backspaced characters
-                self.current_text = self.current_text[..start_pos].to_string();
-                
-                // Press the actual ENTER key
-                debug!("Pressing ENTER key");
-                self.hardware.press_enter()?;
+                action = Some(trigger.keys);
             }
         } else {
             // Voice-enter disabled: always press ENTER at end-of-turn
             debug!("Voice-enter disabled; pressing ENTER at end-of-turn");
-            self.hardware.press_enter()?;
+            action = Some(vec![KEY_ENTER]);
+        }
+
+        // A key press may have sent the text, so it can no longer be undone,
+        // and the next turn starts afresh
+        let previous_end = self.turn_formatter.as_ref().and_then(|f| f.state());
+        if let Some(formatter) = self.turn_formatter.as_mut() {
+            if action.is_some() {
+                formatter.reset();
+            } else {
+                formatter.finish_turn(&self.current_text);
+            }
+        }
+        if action.is_some() {
+            self.undo_history.clear();
+        } else {
+            let chars = if self.paste.is_some() {
+                self.current_text.chars().count()
+            } else {
+                self.on_screen_len_from(0)
+            };
+            self.undo_history.push(UndoEntry {
+                chars,
+                previous_end,
+            });
+        }
+
+        if let Some(paste) = self.paste.as_mut() {
+            if !self.current_text.is_empty() {
+                debug!("Pasting transcript: '{}'", self.current_text);
+                paste.paste(&mut self.hardware, &self.current_text)?;
+            }
+        }
+
+        match action.as_deref() {
+            Some([KEY_ENTER]) => {
+                // Press the actual ENTER key
+                debug!("Pressing ENTER key");
+                self.hardware.press_enter()?;
+            }
+            Some(keys) => {
+                debug!("Pressing keys {:?}", keys);
+                self.hardware.press_combo(keys)?;
+            }
+            None => {}
         }
         
         // Clear the current text tracking
         self.current_text.clear();
+        self.typed.clear();
         
         Ok(())
     }
 
+    /// Erase the turn in progress instead of committing it. The undo
+    /// command starting at `command_char` is always erased.
+    fn discard_turn(&mut self, command_char: usize) -> Result<()> {
+        let said_before = self.on_screen_len_from(0) - self.on_screen_len_from(command_char);
+        if said_before > self.max_undo_chars {
+            warn!(
+                "Not erasing the current turn: {} characters is more than the undo limit of {}",
+                said_before, self.max_undo_chars
+            );
+            self.erase_from(command_char)?;
+            // What is left stays on screen, so older turns are out of reach
+            self.undo_history.clear();
+            if let Some(formatter) = self.turn_formatter.as_mut() {
+                formatter.finish_turn(&self.current_text);
+            }
+        } else {
+            info!("Discarding the current turn");
+            self.erase_from(0)?;
+        }
+
+        self.current_text.clear();
+        self.typed.clear();
+        Ok(())
+    }
+
+    /// Replace a turn of "insert <name>" with the snippet's text, returning
+    /// whether one was inserted
+    fn insert_snippet(&mut self) -> Result<bool> {
+        let Some(snippets) = self.snippets.as_mut() else {
+            return Ok(false);
+        };
+        let Some((start_pos, name)) = snippets.find(&self.current_text) else {
+            return Ok(false);
+        };
+        let Some(text) = snippets.expand(&name) else {
+            return Ok(false);
+        };
+        info!("Inserting snippet '{}'", name);
+
+        if self.paste.is_some() {
+            // Nothing was typed yet, so the snippet is pasted in its place
+            let text = self.transliterator.normalize(&text, |_| true);
+            self.current_text.truncate(start_pos);
+            self.current_text.push_str(&text);
+        } else {
+            let hardware = &self.hardware;
+            let text = self
+                .transliterator
+                .normalize(&text, |c| hardware.can_type(c));
+            // Keep the space the turn started with
+            self.erase_from(self.current_text[..start_pos].chars().count())?;
+            self.type_and_track(&text)?;
+        }
+        Ok(true)
+    }
+
+    /// Erase the most recent finished turn
+    fn undo_previous_turn(&mut self) -> Result<()> {
+        match self.undo_history.pop() {
+            Some(entry) if entry.chars > self.max_undo_chars => {
+                warn!(
+                    "Not scratching the previous turn: {} characters is more than the undo limit of {}",
+                    entry.chars, self.max_undo_chars
+                );
+                // Older turns are no longer at the end of the text
+                self.undo_history.clear();
+            }
+            Some(entry) => {
+                info!(
+                    "Scratching the previous turn ({} characters, {} more can be scratched)",
+                    entry.chars,
+                    self.undo_history.len()
+                );
+                self.hardware.press_backspaces(entry.chars)?;
+                if let Some(formatter) = self.turn_formatter.as_mut() {
+                    formatter.restore(entry.previous_end);
+                }
+            }
+            None => info!("Nothing to scratch"),
+        }
+        Ok(())
+    }
+
     /// Clear the current text by backspacing
     fn clear_current_text(&mut self) -> Result<()> {
         if !self.current_text.is_empty() {
@@ -376,23 +1111,65 @@ impl<H: KeyboardHardware> VirtualKeyboard<H> {
 
     /// Backspace all characters in the current text
     fn backspace_current_text(&mut self) -> Result<()> {
-        let char_count = self.current_text.chars().count();
-        debug!("Backspacing {} characters", char_count);
+        debug!("Backspacing {} characters", self.on_screen_len_from(0));
+        self.erase_from(0)
+    }
 
-        for _ in 0..char_count {
-            self.hardware.press_backspace()?;
-            // Small delay between backspaces for reliability
-            std::thread::sleep(std::time::Duration::from_millis(5));
-        }
+    /// Number of on-screen characters produced from `current_text[char_index..]`
+    fn on_screen_len_from(&self, char_index: usize) -> usize {
+        self.typed
+            .get(char_index..)
+            .unwrap_or_default()
+            .iter()
+            .filter(|t| t.emitted)
+            .count()
+    }
 
-        self.current_text.clear();
+    /// Remove everything typed from `char_index` onwards, pressing backspace
+    /// once per emitted character
+    fn erase_from(&mut self, char_index: usize) -> Result<()> {
+        self.hardware
+            .press_backspaces(self.on_screen_len_from(char_index))?;
+
+        self.typed.truncate(char_index);
+        self.sync_current_text();
         Ok(())
     }
 
+    /// Type `text` and record what the hardware actually emitted
+    fn type_and_track(&mut self, text: &str) -> Result<()> {
+        let typed = self.hardware.type_text(text)?;
+        let skipped = typed.iter().filter(|t| !t.emitted).count();
+        if skipped > 0 {
+            debug!("{} characters could not be typed and were skipped", skipped);
+        }
+        self.typed.extend(typed);
+        self.sync_current_text();
+        Ok(())
+    }
+
+    fn sync_current_text(&mut self) {
+        self.current_text = self.typed.iter().map(|t| t.intended).collect();
+    }
+
+    /// Access the underlying hardware, e.g. to reconfigure it after creation
+    pub fn hardware_mut(&mut self) -> &mut H {
+        &mut self.hardware
+    }
+
     /// Get the current text that has been typed
     pub fn get_current_text(&self) -> &str {
         &self.current_text
     }
+
+    /// The part of the current text that actually appeared on screen
+    pub fn get_on_screen_text(&self) -> String {
+        self.typed
+            .iter()
+            .filter(|t| t.emitted)
+            .map(|t| t.intended)
+            .collect()
+    }
 }
 
 /// Mock hardware implementation for testing
@@ -400,6 +1177,9 @@ pub struct MockKeyboardHardware {
     pub typed_chars: Vec<char>,
     pub backspace_count: usize,
     pub enter_pressed: bool,
+    /// Characters the mock skips, like a layout without a key for them
+    pub unsupported: Vec<char>,
+    pub combos: Vec<Vec<u16>>,
 }
 
 impl MockKeyboardHardware {
@@ -408,16 +1188,34 @@ impl MockKeyboardHardware {
             typed_chars: Vec::new(),
             backspace_count: 0,
             enter_pressed: false,
+            unsupported: Vec::new(),
+            combos: Vec::new(),
+        }
+    }
+
+    /// A mock that cannot type any of the characters in `unsupported`
+    pub fn with_unsupported(unsupported: &str) -> Self {
+        Self {
+            unsupported: unsupported.chars().collect(),
+            ..Self::new()
         }
     }
 }
 
 impl KeyboardHardware for MockKeyboardHardware {
-    fn type_text(&mut self, text: &str) -> Result<()> {
+    fn type_text(&mut self, text: &str) -> Result<Vec<TypedChar>> {
+        let mut typed = Vec::new();
         for c in text.chars() {
-            self.typed_chars.push(c);
+            let emitted = !self.unsupported.contains(&c);
+            if emitted {
+                self.typed_chars.push(c);
+            }
+            typed.push(TypedChar {
+                intended: c,
+                emitted,
+            });
         }
-        Ok(())
+        Ok(typed)
     }
 
     fn press_backspace(&mut self) -> Result<()> {
@@ -437,11 +1235,59 @@ impl KeyboardHardware for MockKeyboardHardware {
         // Mock implementation - could log the keycode if needed
         Ok(())
     }
+
+    fn press_combo(&mut self, keycodes: &[u16]) -> Result<()> {
+        self.combos.push(keycodes.to_vec());
+        Ok(())
+    }
+
+    fn can_type(&self, c: char) -> bool {
+        !self.unsupported.contains(&c)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_parse_device_ids() {
+        assert_eq!(parse_device_id("0x1234").unwrap(), 0x1234);
+        assert_eq!(parse_device_id("046d").unwrap(), 0x046d);
+        assert!(parse_device_id("0x12345").is_err());
+        assert!(parse_device_id("keyboard").is_err());
+    }
+
+    #[test]
+    fn test_parse_bus_type() {
+        assert_eq!(parse_bus_type("usb").unwrap(), BUS_USB);
+        assert_eq!(parse_bus_type("Virtual").unwrap(), BUS_VIRTUAL);
+        assert_eq!(parse_bus_type("0x19").unwrap(), 0x19);
+        assert!(parse_bus_type("serial-port").is_err());
+    }
+
+    #[test]
+    fn test_sysname_from_buf() {
+        let mut buf = [0u8; 64];
+        buf[..7].copy_from_slice(b"input42");
+        assert_eq!(sysname_from_buf(&buf).as_deref(), Some("input42"));
+        assert_eq!(sysname_from_buf(&[0u8; 8]), None);
+    }
+
+    #[test]
+    fn test_find_event_node() {
+        let dir = std::env::temp_dir().join(format!("vk-sysfs-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("capabilities")).unwrap();
+        std::fs::create_dir_all(dir.join("event17")).unwrap();
+        std::fs::write(dir.join("name"), "Voice Keyboard").unwrap();
+
+        let node = find_event_node(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(node, Some(PathBuf::from("/dev/input/event17")));
+        assert_eq!(find_event_node(&dir), None);
+    }
 
     #[test]
     fn test_incremental_typing_extension() {
@@ -866,6 +1712,167 @@ mod tests {
         assert_eq!(kb.hardware.backspace_count, 6);
     }
 
+    #[test]
+    fn test_transliteration_keeps_tracking_in_sync() {
+        let mut custom = std::collections::BTreeMap::new();
+        custom.insert("æ".to_string(), "ae".to_string());
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new());
+        kb.set_transliterator(Transliterator::new(true, &custom));
+
+        kb.update_transcript("encyclopædia").unwrap();
+        assert_eq!(kb.current_text, "encyclopaedia");
+
+        // The correction is diffed against the replaced text, so only the
+        // changed ending is backspaced
+        kb.update_transcript("encyclopædias").unwrap();
+        assert_eq!(kb.hardware.backspace_count, 0);
+        assert_eq!(
+            kb.hardware.typed_chars.iter().collect::<String>(),
+            "encyclopaedias"
+        );
+    }
+
+    #[test]
+    fn test_skipped_character_is_not_backspaced() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::with_unsupported("😀"));
+
+        kb.update_transcript("see you 😀").unwrap();
+        assert_eq!(kb.current_text, "see you 😀");
+        assert_eq!(kb.get_on_screen_text(), "see you ");
+
+        // The emoji never appeared, so replacing it must not eat the space
+        kb.update_transcript("see you soon").unwrap();
+        assert_eq!(kb.hardware.backspace_count, 0);
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "see you soon");
+    }
+
+    #[test]
+    fn test_correction_after_skipped_characters() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::with_unsupported("ж"));
+
+        kb.update_transcript("say жжж then go").unwrap();
+        assert_eq!(kb.get_on_screen_text(), "say  then go");
+
+        kb.update_transcript("say жжж and stop").unwrap();
+        // Only "then go" (7 characters) was on screen after the common prefix
+        assert_eq!(kb.hardware.backspace_count, 7);
+        assert_eq!(
+            kb.hardware.typed_chars.iter().collect::<String>(),
+            "say  and stop"
+        );
+    }
+
+    #[test]
+    fn test_clear_counts_only_emitted_characters() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::with_unsupported("😀"));
+
+        kb.update_transcript("a😀b😀").unwrap();
+        kb.update_transcript("").unwrap();
+        assert_eq!(kb.hardware.backspace_count, 2);
+        assert!(kb.hardware.typed_chars.is_empty());
+        assert_eq!(kb.current_text, "");
+    }
+
+    #[test]
+    fn test_enter_command_after_skipped_characters() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::with_unsupported("😀"));
+
+        kb.update_transcript("wow 😀 enter").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert!(kb.hardware.enter_pressed);
+        // Only " enter" is removed; "wow " and the gap stay intact
+        assert_eq!(kb.hardware.backspace_count, 6);
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "wow ");
+    }
+
+    /// Clipboard shared with the test so its contents can be inspected
+    #[derive(Clone, Default)]
+    struct MockClipboard {
+        contents: Arc<Mutex<Option<String>>>,
+        history: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Clipboard for MockClipboard {
+        fn get(&mut self) -> Result<Option<String>> {
+            Ok(self.contents.lock().unwrap().clone())
+        }
+
+        fn set(&mut self, text: &str) -> Result<()> {
+            self.history.lock().unwrap().push(text.to_string());
+            *self.contents.lock().unwrap() = Some(text.to_string());
+            Ok(())
+        }
+
+        fn clear(&mut self) -> Result<()> {
+            *self.contents.lock().unwrap() = None;
+            Ok(())
+        }
+    }
+
+    fn paste_keyboard(clipboard: &MockClipboard) -> VirtualKeyboard<MockKeyboardHardware> {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new());
+        kb.set_paste_output(Some(PasteOutput::new(
+            Box::new(clipboard.clone()),
+            vec![KEY_LEFTCTRL, KEY_V],
+            Duration::ZERO,
+        )));
+        kb
+    }
+
+    #[test]
+    fn test_paste_mode_pastes_turn_and_restores_clipboard() {
+        let clipboard = MockClipboard::default();
+        *clipboard.contents.lock().unwrap() = Some("previous".to_string());
+        let mut kb = paste_keyboard(&clipboard);
+
+        kb.update_transcript("hello").unwrap();
+        kb.update_transcript("hello wörld 😀").unwrap();
+        // Nothing is typed while the turn is in progress
+        assert!(kb.hardware.typed_chars.is_empty());
+        assert_eq!(kb.hardware.backspace_count, 0);
+
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.combos, vec![vec![KEY_LEFTCTRL, KEY_V]]);
+        assert_eq!(
+            *clipboard.history.lock().unwrap(),
+            vec!["hello wörld 😀".to_string(), "previous".to_string()]
+        );
+        assert_eq!(
+            clipboard.contents.lock().unwrap().as_deref(),
+            Some("previous")
+        );
+        assert!(!kb.hardware.enter_pressed);
+        assert_eq!(kb.current_text, "");
+    }
+
+    #[test]
+    fn test_paste_mode_enter_command() {
+        let clipboard = MockClipboard::default();
+        let mut kb = paste_keyboard(&clipboard);
+
+        kb.update_transcript("send it enter.").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(
+            *clipboard.history.lock().unwrap(),
+            vec!["send it".to_string()]
+        );
+        assert!(kb.hardware.enter_pressed);
+        // The clipboard was empty before, so it is cleared again
+        assert_eq!(*clipboard.contents.lock().unwrap(), None);
+    }
+
+    #[test]
+    fn test_paste_mode_empty_turn_pastes_nothing() {
+        let clipboard = MockClipboard::default();
+        let mut kb = paste_keyboard(&clipboard);
+
+        kb.update_transcript("enter").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert!(kb.hardware.combos.is_empty());
+        assert!(clipboard.history.lock().unwrap().is_empty());
+        assert!(kb.hardware.enter_pressed);
+    }
+
     #[test]
     fn test_normal_mode_basic() {
         let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new());
@@ -876,4 +1883,345 @@ mod tests {
         assert_eq!(kb.current_text, "Hello World");
         assert_eq!(kb.hardware.typed_chars, ['H', 'e', 'l', 'l', 'o', ' ', 'W', 'o', 'r', 'l', 'd']);
     }
+
+    #[test]
+    fn test_finalize_with_configured_trigger() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new());
+        let phrases = BTreeMap::from([
+            ("send it".to_string(), "ctrl+enter".to_string()),
+            ("submit".to_string(), "enter".to_string()),
+        ]);
+        kb.set_triggers(Triggers::new(&phrases, &Keymap::us()).unwrap());
+
+        kb.update_transcript("looks good, send it.").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(
+            kb.hardware.typed_chars.iter().collect::<String>(),
+            "looks good,"
+        );
+        assert_eq!(kb.hardware.combos, vec![vec![KEY_LEFTCTRL, KEY_ENTER]]);
+        assert!(!kb.hardware.enter_pressed);
+
+        // "enter" is no longer a trigger once the table replaces it
+        kb.update_transcript("enter").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert!(!kb.hardware.enter_pressed);
+
+        kb.update_transcript("submit").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert!(kb.hardware.enter_pressed);
+    }
+
+    fn undo_keyboard() -> VirtualKeyboard<MockKeyboardHardware> {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new());
+        kb.set_voice_undo_enabled(true);
+        kb
+    }
+
+    fn on_screen(kb: &VirtualKeyboard<MockKeyboardHardware>) -> String {
+        kb.hardware.typed_chars.iter().collect()
+    }
+
+    #[test]
+    fn test_cancel_discards_turn() {
+        let mut kb = undo_keyboard();
+        kb.update_transcript("first. ").unwrap();
+        kb.finalize_transcript().unwrap();
+
+        kb.update_transcript("oops, never mind.").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(on_screen(&kb), "first. ");
+        assert!(!kb.hardware.enter_pressed);
+        assert_eq!(kb.get_current_text(), "");
+    }
+
+    #[test]
+    fn test_scratch_that_removes_previous_turns() {
+        let mut kb = undo_keyboard();
+        for turn in ["one ", "two ", "three "] {
+            kb.update_transcript(turn).unwrap();
+            kb.finalize_transcript().unwrap();
+        }
+
+        kb.update_transcript("Scratch that.").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(on_screen(&kb), "one two ");
+
+        kb.update_transcript("scratch that").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(on_screen(&kb), "one ");
+
+        // Said after other words, it only removes the current turn
+        kb.update_transcript("blah blah scratch that").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(on_screen(&kb), "one ");
+    }
+
+    #[test]
+    fn test_undo_stops_at_key_press_and_limit() {
+        let mut kb = undo_keyboard();
+        kb.update_transcript("sent enter").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert!(kb.hardware.enter_pressed);
+
+        kb.update_transcript("scratch that").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(on_screen(&kb), "sent");
+
+        kb.set_max_undo_chars(5);
+        kb.update_transcript(" too long to erase").unwrap();
+        kb.finalize_transcript().unwrap();
+        kb.update_transcript("scratch that").unwrap();
+        kb.finalize_transcript().unwrap();
+        // Only the command itself was erased
+        assert_eq!(on_screen(&kb), "sent too long to erase");
+
+        kb.update_transcript(" and this too, cancel").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(on_screen(&kb), "sent too long to erase and this too,");
+    }
+
+    #[test]
+    fn test_turn_formatting() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new());
+        kb.set_turn_formatting_enabled(true);
+        kb.set_voice_undo_enabled(true);
+
+        for turn in ["Hello there.", "How are", "You doing", "Fine"] {
+            kb.update_transcript(turn).unwrap();
+            kb.finalize_transcript().unwrap();
+        }
+        assert_eq!(on_screen(&kb), "Hello there. How are you doing fine");
+
+        // Scratching a turn restores how the text before it ended
+        kb.update_transcript("Scratch that.").unwrap();
+        kb.finalize_transcript().unwrap();
+        kb.update_transcript("Scratch that.").unwrap();
+        kb.finalize_transcript().unwrap();
+        kb.update_transcript("Doing well?").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(on_screen(&kb), "Hello there. How are doing well?");
+
+        // Enter starts a fresh field
+        kb.update_transcript("Sent. enter").unwrap();
+        kb.finalize_transcript().unwrap();
+        kb.update_transcript("New message").unwrap();
+        assert_eq!(
+            on_screen(&kb),
+            "Hello there. How are doing well? Sent.New message"
+        );
+    }
+
+    #[test]
+    fn test_spoken_punctuation() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new());
+        kb.set_spoken_punctuation(Some(SpokenPunctuation::default()));
+
+        kb.update_transcript("looks good com").unwrap();
+        assert_eq!(kb.get_current_text(), "looks good com");
+        kb.update_transcript("looks good comma thanks").unwrap();
+        assert_eq!(kb.get_current_text(), "looks good, thanks");
+        assert_eq!(
+            kb.hardware.typed_chars.iter().collect::<String>(),
+            "looks good, thanks"
+        );
+    }
+
+    #[test]
+    fn test_replacements_run_first() {
+        use crate::config::ReplacementRule;
+        use crate::replacements::ReplacementRules;
+
+        let rule = ReplacementRule {
+            pattern: "cube control".to_string(),
+            replacement: "kubectl".to_string(),
+            regex: false,
+            case_sensitive: false,
+            whole_word: true,
+            preserve_case: false,
+        };
+        let rules = ReplacementRules::new(&[rule]).unwrap();
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new());
+        kb.set_replacements(Some(Replacements::fixed(rules)));
+        kb.set_spoken_punctuation(Some(SpokenPunctuation::default()));
+
+        kb.update_transcript("run cube control comma please").unwrap();
+        assert_eq!(kb.get_current_text(), "run kubectl, please");
+    }
+
+    #[test]
+    fn test_inverse_text_normalization() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new());
+        kb.set_inverse_text_normalization_enabled(true);
+
+        kb.update_transcript("twenty three dollars").unwrap();
+        assert_eq!(kb.get_current_text(), "$23");
+        kb.update_transcript("twenty three dollars fifty on march fourth")
+            .unwrap();
+        assert_eq!(kb.get_current_text(), "$23.50 on March 4");
+        assert_eq!(
+            kb.hardware.typed_chars.iter().collect::<String>(),
+            "$23.50 on March 4"
+        );
+    }
+
+    #[test]
+    fn test_code_mode() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new());
+        kb.set_turn_formatting_enabled(true);
+        kb.set_spoken_punctuation(Some(SpokenPunctuation::default()));
+
+        kb.update_transcript("Code mode.").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert!(kb.code_mode_enabled());
+        assert_eq!(on_screen(&kb), "");
+
+        for turn in [
+            "let camel case user id",
+            "Equals request dot user ID.",
+            "dot unwrap",
+        ] {
+            kb.update_transcript(turn).unwrap();
+            kb.finalize_transcript().unwrap();
+        }
+        assert_eq!(on_screen(&kb), "let userId = request.user ID.unwrap");
+
+        kb.update_transcript("prose mode").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert!(!kb.code_mode_enabled());
+        kb.update_transcript("Done comma thanks").unwrap();
+        assert_eq!(
+            on_screen(&kb),
+            "let userId = request.user ID.unwrap done, thanks"
+        );
+    }
+
+    fn signature() -> Snippets {
+        let mut snippets = BTreeMap::new();
+        snippets.insert("signature".to_string(), "Best,\nDaniel".to_string());
+        Snippets::new(&snippets)
+    }
+
+    #[test]
+    fn test_snippet_replaces_whole_turn() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new());
+        kb.set_turn_formatting_enabled(true);
+        kb.set_voice_undo_enabled(true);
+        kb.set_snippets(Some(signature()));
+
+        kb.update_transcript("Thanks.").unwrap();
+        kb.finalize_transcript().unwrap();
+        kb.update_transcript("Insert signature.").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(on_screen(&kb), "Thanks. Best,\nDaniel");
+        assert!(!kb.hardware.enter_pressed);
+
+        // The snippet counts as a turn for undo
+        kb.update_transcript("scratch that").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(on_screen(&kb), "Thanks.");
+
+        // Only a turn of just the command inserts the snippet
+        kb.update_transcript("Insert signature here").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(on_screen(&kb), "Thanks. Insert signature here");
+    }
+
+    #[test]
+    fn test_paste_mode_snippet() {
+        let clipboard = MockClipboard::default();
+        let mut kb = paste_keyboard(&clipboard);
+        kb.set_snippets(Some(signature()));
+
+        kb.update_transcript("insert signature").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(
+            *clipboard.history.lock().unwrap(),
+            vec!["Best,\nDaniel".to_string()]
+        );
+        assert!(kb.hardware.typed_chars.is_empty());
+    }
+
+    /// `(type, code, value)` of every event read back from the pipe
+    type EventCollector = std::thread::JoinHandle<Vec<(u16, u16, i32)>>;
+
+    /// uinput hardware writing into a pipe, plus a thread collecting every
+    /// event written to it until the hardware is dropped
+    fn piped_hardware() -> (RealKeyboardHardware, EventCollector) {
+        use std::io::Read;
+
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let mut reader = unsafe { std::fs::File::from_raw_fd(fds[0]) };
+        let collector = std::thread::spawn(move || {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).unwrap();
+            bytes
+                .chunks_exact(std::mem::size_of::<InputEvent>())
+                .map(|chunk| {
+                    let event =
+                        unsafe { std::ptr::read_unaligned(chunk.as_ptr() as *const InputEvent) };
+                    (event.type_, event.code, event.value)
+                })
+                .collect()
+        });
+        (RealKeyboardHardware::from_fd(fds[1]), collector)
+    }
+
+    #[test]
+    fn test_uinput_events_are_batched() {
+        let (mut hardware, collector) = piped_hardware();
+        hardware.set_cadence(Cadence::FAST);
+        hardware.type_text("Hi").unwrap();
+        hardware.press_backspaces(2).unwrap();
+        drop(hardware);
+
+        let key = |code, value| vec![(EV_KEY, code, value), (EV_SYN, SYN_REPORT, 0)];
+        let expected: Vec<_> = [
+            key(KEY_LEFTSHIFT, 1),
+            key(KEY_H, 1),
+            key(KEY_H, 0),
+            key(KEY_LEFTSHIFT, 0),
+            key(KEY_I, 1),
+            key(KEY_I, 0),
+            key(KEY_BACKSPACE, 1),
+            key(KEY_BACKSPACE, 0),
+            key(KEY_BACKSPACE, 1),
+            key(KEY_BACKSPACE, 0),
+        ]
+        .concat();
+        assert_eq!(collector.join().unwrap(), expected);
+    }
+
+    #[test]
+    #[ignore = "benchmark; run with --ignored --nocapture"]
+    fn bench_typing_throughput() {
+        let text: String = "the quick brown fox jumps over the lazy dog. "
+            .chars()
+            .cycle()
+            .take(500)
+            .collect();
+
+        for (name, cadence) in [
+            ("fast", Cadence::FAST),
+            ("normal", Cadence::NORMAL),
+            ("slow", Cadence::SLOW),
+        ] {
+            let (mut hardware, collector) = piped_hardware();
+            hardware.set_cadence(cadence);
+            let start = std::time::Instant::now();
+            hardware.type_text(&text).unwrap();
+            let elapsed = start.elapsed();
+            drop(hardware);
+            let events = collector.join().unwrap().len();
+
+            println!(
+                "{:>6}: 500 characters ({} events) in {:>7.1?} = {:>7.0} chars/s",
+                name,
+                events,
+                elapsed,
+                500.0 / elapsed.as_secs_f64()
+            );
+        }
+    }
 }
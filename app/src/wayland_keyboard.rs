@@ -0,0 +1,507 @@
+//! Typing through the `zwp_virtual_keyboard_v1` Wayland protocol, which
+//! wlroots-based compositors (Sway, Hyprland) offer to ordinary clients.
+//!
+//! A virtual keyboard brings its own keymap. Instead of mirroring the
+//! user's layout, every keysym that is needed gets its own keycode in a
+//! generated keymap, which is uploaded again whenever a new one is added.
+//! That way any Unicode character can be typed without modifiers.
+
+use anyhow::{bail, Context, Result};
+use std::io::Write;
+use std::os::fd::AsFd;
+use std::time::Instant;
+use tracing::{debug, info};
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::{wl_keyboard, wl_registry, wl_seat};
+use wayland_client::{delegate_noop, Connection, Dispatch, EventQueue, Proxy, QueueHandle};
+use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::{
+    zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1,
+    zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1,
+};
+use xkeysym::Keysym;
+
+use crate::cadence::Cadence;
+use crate::output_backend::evdev_keysym;
+use crate::virtual_keyboard::{KeyboardHardware, TypedChar};
+
+/// Evdev keycodes are XKB keycodes minus this offset
+const XKB_KEYCODE_OFFSET: u32 = 8;
+
+/// Keysyms per keymap; X11 clients such as XWayland cannot see higher
+/// keycodes, so the table starts over when a combination does not fit
+const MAX_KEYSYMS: usize = 255 - XKB_KEYCODE_OFFSET as usize;
+
+/// Real modifier an XKB keymap assigns to a modifier keysym, and its bit in
+/// `wl_keyboard.modifiers`
+fn modifier(keysym: Keysym) -> Option<(&'static str, u32)> {
+    match keysym {
+        Keysym::Shift_L => Some(("Shift", 1 << 0)),
+        Keysym::Control_L => Some(("Control", 1 << 2)),
+        Keysym::Alt_L => Some(("Mod1", 1 << 3)),
+        Keysym::Super_L => Some(("Mod4", 1 << 6)),
+        _ => None,
+    }
+}
+
+/// Keymap with one keycode per keysym, grown as keysyms are needed
+#[derive(Debug, Clone, Default)]
+pub struct DynamicKeymap {
+    keysyms: Vec<Keysym>,
+}
+
+impl DynamicKeymap {
+    /// Evdev keycodes for `keysyms`, pressed together, and whether the
+    /// keymap had to change. Room for all of them is made first, so no
+    /// keycode is reassigned while the combination is held.
+    pub fn keycodes(&mut self, keysyms: &[Keysym]) -> (Vec<u32>, bool) {
+        let mut missing: Vec<Keysym> = Vec::new();
+        for &keysym in keysyms {
+            if !self.keysyms.contains(&keysym) && !missing.contains(&keysym) {
+                missing.push(keysym);
+            }
+        }
+        if self.keysyms.len() + missing.len() > MAX_KEYSYMS {
+            self.keysyms.clear();
+            missing = Vec::new();
+            for &keysym in keysyms {
+                if !missing.contains(&keysym) {
+                    missing.push(keysym);
+                }
+            }
+        }
+        let changed = !missing.is_empty();
+        self.keysyms.extend(missing);
+
+        let keycodes = keysyms
+            .iter()
+            .map(|keysym| self.keysyms.iter().position(|k| k == keysym).unwrap() as u32 + 1)
+            .collect();
+        (keycodes, changed)
+    }
+
+    /// Keymap in the XKB text format the protocol expects. Keysyms are
+    /// written as numbers so no name table is needed.
+    pub fn to_xkb(&self) -> String {
+        let max = self.keysyms.len() as u32 + XKB_KEYCODE_OFFSET;
+        let mut keycodes = String::new();
+        let mut symbols = String::new();
+        for (index, keysym) in self.keysyms.iter().enumerate() {
+            let code = index as u32 + XKB_KEYCODE_OFFSET + 1;
+            keycodes.push_str(&format!("        <K{}> = {};\n", code, code));
+            symbols.push_str(&format!(
+                "        key <K{}> {{ [ 0x{:x} ] }};\n",
+                code,
+                keysym.raw()
+            ));
+            if let Some((name, _)) = modifier(*keysym) {
+                symbols.push_str(&format!(
+                    "        modifier_map {} {{ <K{}> }};\n",
+                    name, code
+                ));
+            }
+        }
+
+        format!(
+            "xkb_keymap {{\n\
+             \x20   xkb_keycodes \"voice-keyboard\" {{\n\
+             \x20       minimum = {min};\n\
+             \x20       maximum = {max};\n\
+             {keycodes}\
+             \x20   }};\n\
+             \x20   xkb_types \"voice-keyboard\" {{ include \"complete\" }};\n\
+             \x20   xkb_compatibility \"voice-keyboard\" {{ include \"complete\" }};\n\
+             \x20   xkb_symbols \"voice-keyboard\" {{\n\
+             {symbols}\
+             \x20   }};\n\
+             }};\n",
+            min = XKB_KEYCODE_OFFSET,
+        )
+    }
+}
+
+/// Whether the compositor named by `$WAYLAND_DISPLAY` offers
+/// `zwp_virtual_keyboard_manager_v1`; GNOME and KDE do not
+pub fn is_supported() -> bool {
+    let Ok(conn) = Connection::connect_to_env() else {
+        return false;
+    };
+    let Ok((globals, _queue)) = registry_queue_init::<State>(&conn) else {
+        return false;
+    };
+    let interface = ZwpVirtualKeyboardManagerV1::interface().name;
+    globals
+        .contents()
+        .with_list(|list| list.iter().any(|global| global.interface == interface))
+}
+
+/// Protocol state; none of the bound objects send events we need
+struct State;
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+delegate_noop!(State: ignore wl_seat::WlSeat);
+delegate_noop!(State: ZwpVirtualKeyboardManagerV1);
+delegate_noop!(State: ZwpVirtualKeyboardV1);
+
+/// [`KeyboardHardware`] backed by a Wayland virtual keyboard
+pub struct WaylandKeyboardHardware {
+    conn: Connection,
+    queue: EventQueue<State>,
+    keyboard: ZwpVirtualKeyboardV1,
+    keymap: DynamicKeymap,
+    /// Keeps the last uploaded keymap file open until it is replaced
+    keymap_file: Option<std::fs::File>,
+    started: Instant,
+    cadence: Cadence,
+}
+
+impl WaylandKeyboardHardware {
+    /// Connect to the compositor named by `$WAYLAND_DISPLAY`
+    pub fn connect() -> Result<Self> {
+        let conn = Connection::connect_to_env().context("Failed to connect to Wayland")?;
+        Self::from_connection(conn)
+    }
+
+    pub fn from_connection(conn: Connection) -> Result<Self> {
+        let (globals, mut queue) =
+            registry_queue_init::<State>(&conn).context("Failed to list Wayland globals")?;
+        let qh = queue.handle();
+        let seat: wl_seat::WlSeat = globals
+            .bind(&qh, 1..=7, ())
+            .context("The compositor has no seat")?;
+        let manager: ZwpVirtualKeyboardManagerV1 = globals
+            .bind(&qh, 1..=1, ())
+            .context("The compositor does not support zwp_virtual_keyboard_manager_v1")?;
+        let keyboard = manager.create_virtual_keyboard(&seat, &qh, ());
+        queue
+            .roundtrip(&mut State)
+            .context("Failed to create the virtual keyboard")?;
+        info!("Typing through the Wayland virtual keyboard protocol");
+
+        let mut hardware = Self {
+            conn,
+            queue,
+            keyboard,
+            keymap: DynamicKeymap::default(),
+            keymap_file: None,
+            started: Instant::now(),
+            cadence: Cadence::default(),
+        };
+        // The protocol requires a keymap before the first key event
+        hardware.keycodes(&[Keysym::BackSpace])?;
+        Ok(hardware)
+    }
+
+    fn upload_keymap(&mut self) -> Result<()> {
+        let mut text = self.keymap.to_xkb().into_bytes();
+        text.push(0);
+        let mut file = tempfile::tempfile().context("Failed to create the keymap file")?;
+        file.write_all(&text)?;
+        self.keyboard.keymap(
+            wl_keyboard::KeymapFormat::XkbV1.into(),
+            file.as_fd(),
+            text.len() as u32,
+        );
+        self.keymap_file = Some(file);
+        debug!("Uploaded keymap with {} keysyms", self.keymap.keysyms.len());
+        Ok(())
+    }
+
+    /// Keycodes for `keysyms`, uploading a new keymap if any was not
+    /// mapped yet
+    fn keycodes(&mut self, keysyms: &[Keysym]) -> Result<Vec<u32>> {
+        let (keycodes, changed) = self.keymap.keycodes(keysyms);
+        if changed {
+            self.upload_keymap()?;
+        }
+        Ok(keycodes)
+    }
+
+    fn send_key(&self, keycode: u32, pressed: bool) {
+        let state = if pressed {
+            wl_keyboard::KeyState::Pressed
+        } else {
+            wl_keyboard::KeyState::Released
+        };
+        let time = self.started.elapsed().as_millis() as u32;
+        self.keyboard.key(time, keycode, state.into());
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.conn
+            .flush()
+            .context("Failed to send Wayland requests")?;
+        // Surface protocol errors, such as a rejected keymap, right away
+        self.queue
+            .dispatch_pending(&mut State)
+            .context("Wayland protocol error")?;
+        Ok(())
+    }
+
+    /// Press `keysyms` together, with modifier state reported as the
+    /// protocol requires
+    fn press_keysyms(&mut self, keysyms: &[Keysym]) -> Result<()> {
+        let keycodes = self.keycodes(keysyms)?;
+        let keys: Vec<(u32, Option<u32>)> = keycodes
+            .into_iter()
+            .zip(keysyms)
+            .map(|(keycode, &keysym)| (keycode, modifier(keysym).map(|(_, bit)| bit)))
+            .collect();
+        for request in combo_requests(&keys) {
+            match request {
+                Request::Press(keycode) => self.send_key(keycode, true),
+                Request::Release(keycode) => self.send_key(keycode, false),
+                Request::Modifiers(mask) => self.keyboard.modifiers(mask, 0, 0, 0),
+            }
+        }
+        self.flush()
+    }
+}
+
+/// Virtual keyboard request that presses keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Request {
+    Press(u32),
+    Release(u32),
+    /// `modifiers` with the depressed mask
+    Modifiers(u32),
+}
+
+/// Requests pressing `keys`, given as keycodes with their modifier bit.
+/// Clients take modifier state only from `modifiers`, so it is reported
+/// after the modifier keys go down and before the other keys are pressed,
+/// and cleared once everything is released.
+fn combo_requests(keys: &[(u32, Option<u32>)]) -> Vec<Request> {
+    let modifiers: Vec<u32> = keys
+        .iter()
+        .filter(|(_, bit)| bit.is_some())
+        .map(|&(keycode, _)| keycode)
+        .collect();
+    let others: Vec<u32> = keys
+        .iter()
+        .filter(|(_, bit)| bit.is_none())
+        .map(|&(keycode, _)| keycode)
+        .collect();
+    let mask = keys
+        .iter()
+        .filter_map(|&(_, bit)| bit)
+        .fold(0, |mask, bit| mask | bit);
+
+    let mut requests: Vec<Request> = modifiers.iter().copied().map(Request::Press).collect();
+    if mask != 0 {
+        requests.push(Request::Modifiers(mask));
+    }
+    requests.extend(others.iter().copied().map(Request::Press));
+    requests.extend(others.iter().rev().copied().map(Request::Release));
+    requests.extend(modifiers.iter().rev().copied().map(Request::Release));
+    if mask != 0 {
+        requests.push(Request::Modifiers(0));
+    }
+    requests
+}
+
+impl KeyboardHardware for WaylandKeyboardHardware {
+    fn type_text(&mut self, text: &str) -> Result<Vec<TypedChar>> {
+        debug!("Typing text via the virtual keyboard: '{}'", text);
+
+        let mut typed = Vec::with_capacity(text.len());
+        let mut sent = 0;
+        for c in text.chars() {
+            let keysym = match c {
+                '\n' => Keysym::Return,
+                '\t' => Keysym::Tab,
+                c => Keysym::from_char(c),
+            };
+            let emitted = keysym != Keysym::NoSymbol;
+            if emitted {
+                self.press_keysyms(&[keysym])?;
+                sent += 1;
+                self.cadence.pace_chars(sent);
+            }
+            typed.push(TypedChar {
+                intended: c,
+                emitted,
+            });
+        }
+        Ok(typed)
+    }
+
+    fn press_backspace(&mut self) -> Result<()> {
+        self.press_keysyms(&[Keysym::BackSpace])
+    }
+
+    fn press_enter(&mut self) -> Result<()> {
+        self.press_keysyms(&[Keysym::Return])
+    }
+
+    fn press_key(&mut self, keycode: u16) -> Result<()> {
+        self.press_combo(&[keycode])
+    }
+
+    fn press_combo(&mut self, keycodes: &[u16]) -> Result<()> {
+        let mut keysyms = Vec::with_capacity(keycodes.len());
+        for &keycode in keycodes {
+            match evdev_keysym(keycode) {
+                Some(keysym) => keysyms.push(keysym),
+                None => bail!("Cannot press keycode {} on the virtual keyboard", keycode),
+            }
+        }
+        self.press_keysyms(&keysyms)
+    }
+
+    fn can_type(&self, c: char) -> bool {
+        Keysym::from_char(c) != Keysym::NoSymbol
+    }
+
+    fn press_backspaces(&mut self, count: usize) -> Result<()> {
+        for pressed in 1..=count {
+            self.press_backspace()?;
+            self.cadence.pace_backspaces(pressed);
+        }
+        Ok(())
+    }
+
+    fn set_cadence(&mut self, cadence: Cadence) {
+        self.cadence = cadence;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+    use std::process::{Child, Command};
+    use std::time::Duration;
+
+    fn keysym(c: char) -> Keysym {
+        Keysym::from_char(c)
+    }
+
+    #[test]
+    fn test_dynamic_keymap_assigns_keycodes() {
+        let mut keymap = DynamicKeymap::default();
+        assert_eq!(keymap.keycodes(&[keysym('a')]), (vec![1], true));
+        assert_eq!(keymap.keycodes(&[keysym('€')]), (vec![2], true));
+        assert_eq!(keymap.keycodes(&[keysym('a')]), (vec![1], false));
+        assert_eq!(
+            keymap.keycodes(&[Keysym::Control_L, keysym('a')]),
+            (vec![3, 1], true)
+        );
+    }
+
+    #[test]
+    fn test_dynamic_keymap_starts_over_when_full() {
+        let mut keymap = DynamicKeymap::default();
+        for c in ('\u{400}'..).take(MAX_KEYSYMS - 1) {
+            keymap.keycodes(&[keysym(c)]);
+        }
+        let first = keysym('\u{400}');
+        assert_eq!(keymap.keycodes(&[first]), (vec![1], false));
+
+        // A combination that does not fit starts over before any keycode is
+        // handed out, so the mapped keysym is not left with a stale keycode
+        assert_eq!(
+            keymap.keycodes(&[first, Keysym::Control_L, keysym('a')]),
+            (vec![1, 2, 3], true)
+        );
+        assert_eq!(keymap.keysyms, vec![first, Keysym::Control_L, keysym('a')]);
+    }
+
+    #[test]
+    fn test_combo_requests_report_modifiers_first() {
+        use Request::*;
+        let control = 1 << 2;
+        let shift = 1 << 0;
+        assert_eq!(
+            combo_requests(&[(1, Some(control)), (2, Some(shift)), (3, None)]),
+            vec![
+                Press(1),
+                Press(2),
+                Modifiers(control | shift),
+                Press(3),
+                Release(3),
+                Release(2),
+                Release(1),
+                Modifiers(0),
+            ]
+        );
+        // Plain keys never touch the modifier state
+        assert_eq!(combo_requests(&[(4, None)]), vec![Press(4), Release(4)]);
+    }
+
+    #[test]
+    fn test_keymap_text() {
+        let mut keymap = DynamicKeymap::default();
+        keymap.keycodes(&[Keysym::Control_L, keysym('ж')]);
+        let text = keymap.to_xkb();
+
+        assert!(text.contains("minimum = 8;"));
+        assert!(text.contains("maximum = 10;"));
+        assert!(text.contains("<K9> = 9;"));
+        assert!(text.contains("key <K9> { [ 0xffe3 ] };"));
+        assert!(text.contains("modifier_map Control { <K9> };"));
+        assert!(text.contains("key <K10> { [ 0x6d6 ] };"));
+    }
+
+    struct Compositor {
+        child: Child,
+        /// Removed once the compositor has exited
+        _runtime_dir: tempfile::TempDir,
+    }
+
+    impl Drop for Compositor {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+
+    /// Types through a headless Sway. Needs `sway` on PATH:
+    /// `cargo test -- --ignored wayland_keyboard`
+    #[test]
+    #[ignore]
+    fn test_types_into_headless_sway() {
+        let runtime_dir = tempfile::tempdir().unwrap();
+        let child = Command::new("sway")
+            .args(["--config", "/dev/null"])
+            .env("WLR_BACKENDS", "headless")
+            .env("WLR_LIBINPUT_NO_DEVICES", "1")
+            .env("XDG_RUNTIME_DIR", runtime_dir.path())
+            .env("WAYLAND_DISPLAY", "wayland-vk-test")
+            .spawn()
+            .unwrap();
+        let socket = runtime_dir.path().join("wayland-vk-test");
+        let _compositor = Compositor {
+            child,
+            _runtime_dir: runtime_dir,
+        };
+        for _ in 0..50 {
+            if socket.exists() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        let conn = Connection::from_socket(UnixStream::connect(&socket).unwrap()).unwrap();
+        let mut hardware = WaylandKeyboardHardware::from_connection(conn).unwrap();
+        let typed = hardware.type_text("héllo € 😀\n").unwrap();
+        assert!(typed.iter().all(|t| t.emitted));
+        hardware.press_backspace().unwrap();
+        hardware
+            .press_combo(&[crate::input_event::KEY_LEFTCTRL, crate::input_event::KEY_V])
+            .unwrap();
+
+        // The compositor accepted every keymap and key event
+        hardware.queue.roundtrip(&mut State).unwrap();
+    }
+}
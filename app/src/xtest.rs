@@ -0,0 +1,515 @@
+//! Typing through the X11 XTEST extension, which any client may use, so no
+//! root or uinput device is needed on X11 desktops.
+//!
+//! XTEST injects keycodes, so characters are looked up in the server's
+//! current keyboard mapping, which already reflects the user's layout.
+//! Characters the layout lacks are typed by briefly binding their keysym to
+//! an unused keycode, the same trick `xdotool` uses. Several unused keycodes
+//! take turns, so a client still reading one key press does not see its
+//! keycode rebound underneath it.
+
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{self, ConnectionExt as _, Window};
+use x11rb::protocol::xtest::ConnectionExt as _;
+use x11rb::rust_connection::RustConnection;
+use xkeysym::Keysym;
+
+use crate::cadence::Cadence;
+use crate::virtual_keyboard::{KeyboardHardware, TypedChar};
+
+/// X server keycodes are evdev keycodes plus this offset
+const X_KEYCODE_OFFSET: u16 = 8;
+
+/// Time for clients to pick up a changed mapping before it is used or reset
+const REMAP_DELAY: Duration = Duration::from_millis(20);
+
+/// Most unused keycodes taken for temporary bindings
+const MAX_SPARE_KEYCODES: usize = 4;
+
+/// Character lookup built from the core keyboard mapping
+#[derive(Debug, Clone)]
+pub struct XKeymap {
+    /// Keycode and whether Shift is needed, from the first two columns
+    chars: HashMap<char, (u8, bool)>,
+    /// Keycode of a Shift key
+    shift: Option<u8>,
+    /// Keycodes without any keysyms, highest first, used for temporary
+    /// bindings
+    spare: Vec<u8>,
+    keysyms_per_keycode: u8,
+}
+
+impl XKeymap {
+    /// Build from a `GetKeyboardMapping` reply covering all keycodes from
+    /// `min_keycode`
+    pub fn from_mapping(min_keycode: u8, keysyms_per_keycode: u8, keysyms: &[u32]) -> Self {
+        let mut chars = HashMap::new();
+        let mut shift = None;
+        let mut spare = Vec::new();
+        let per_keycode = usize::from(keysyms_per_keycode.max(1));
+
+        for (index, syms) in keysyms.chunks(per_keycode).enumerate() {
+            let Ok(keycode) = u8::try_from(usize::from(min_keycode) + index) else {
+                break;
+            };
+            if syms.iter().all(|&sym| sym == 0) {
+                spare.push(keycode);
+                continue;
+            }
+            if syms[0] == u32::from(Keysym::Shift_L) && shift.is_none() {
+                shift = Some(keycode);
+            }
+
+            for (column, &sym) in syms.iter().take(2).enumerate() {
+                let keysym = Keysym::new(sym);
+                if keysym.is_keypad_key() {
+                    continue;
+                }
+                // Return reports '\r', but dictated newlines are '\n'
+                let c = match keysym.key_char() {
+                    Some('\r') => '\n',
+                    Some(c) if !c.is_control() || c == '\t' => c,
+                    _ => continue,
+                };
+                chars.entry(c).or_insert((keycode, column == 1));
+            }
+        }
+
+        // Prefer the highest free keycodes, well away from real keys
+        spare.reverse();
+        spare.truncate(MAX_SPARE_KEYCODES);
+
+        Self {
+            chars,
+            shift,
+            spare,
+            keysyms_per_keycode,
+        }
+    }
+
+    pub fn lookup(&self, c: char) -> Option<(u8, bool)> {
+        self.chars.get(&c).copied()
+    }
+
+    /// Whether `c` can be typed, directly or through a temporary binding
+    pub fn can_type(&self, c: char) -> bool {
+        self.lookup(c).is_some()
+            || (!self.spare.is_empty() && Keysym::from_char(c) != Keysym::NoSymbol)
+    }
+}
+
+/// Which spare keycode each missing keysym is bound to
+#[derive(Debug, Clone)]
+struct SpareSlots {
+    /// Spare keycodes and the keysym currently bound to each
+    slots: Vec<(u8, Option<Keysym>)>,
+    /// Slot to rebind next, the one bound longest ago
+    next: usize,
+}
+
+impl SpareSlots {
+    fn new(spare: &[u8]) -> Self {
+        Self {
+            slots: spare.iter().map(|&keycode| (keycode, None)).collect(),
+            next: 0,
+        }
+    }
+
+    /// Keycode for `keysym` and whether it has to be bound first
+    fn assign(&mut self, keysym: Keysym) -> Option<(u8, bool)> {
+        if let Some(&(keycode, _)) = self.slots.iter().find(|(_, bound)| *bound == Some(keysym)) {
+            return Some((keycode, false));
+        }
+        let index = self.next % self.slots.len().max(1);
+        let slot = self.slots.get_mut(index)?;
+        slot.1 = Some(keysym);
+        self.next = index + 1;
+        Some((slot.0, true))
+    }
+
+    fn bound(&self) -> impl Iterator<Item = u8> + '_ {
+        self.slots
+            .iter()
+            .filter(|(_, bound)| bound.is_some())
+            .map(|&(keycode, _)| keycode)
+    }
+}
+
+/// Temporary bindings for one `type_text` call, undone when dropped so the
+/// spare keycodes are freed even when typing fails halfway
+struct SpareBindings<'a> {
+    conn: &'a RustConnection,
+    keysyms_per_keycode: u8,
+    slots: SpareSlots,
+}
+
+impl<'a> SpareBindings<'a> {
+    fn new(conn: &'a RustConnection, keymap: &XKeymap) -> Self {
+        Self {
+            conn,
+            keysyms_per_keycode: keymap.keysyms_per_keycode,
+            slots: SpareSlots::new(&keymap.spare),
+        }
+    }
+
+    /// Keycode typing `keysym`, binding a spare keycode if needed
+    fn keycode(&mut self, keysym: Keysym) -> Result<Option<u8>> {
+        let Some((keycode, needs_binding)) = self.slots.assign(keysym) else {
+            return Ok(None);
+        };
+        if needs_binding {
+            bind(self.conn, self.keysyms_per_keycode, keycode, keysym)?;
+        }
+        Ok(Some(keycode))
+    }
+}
+
+impl Drop for SpareBindings<'_> {
+    fn drop(&mut self) {
+        let bound: Vec<u8> = self.slots.bound().collect();
+        if bound.is_empty() {
+            return;
+        }
+        std::thread::sleep(REMAP_DELAY);
+        for keycode in bound {
+            if let Err(e) = bind(
+                self.conn,
+                self.keysyms_per_keycode,
+                keycode,
+                Keysym::NoSymbol,
+            ) {
+                warn!("Failed to free spare keycode {}: {:#}", keycode, e);
+            }
+        }
+    }
+}
+
+/// Bind `keysym` to `keycode` (or unbind it with `NoSymbol`) and wait until
+/// the server has applied the change
+fn bind(conn: &RustConnection, keysyms_per_keycode: u8, keycode: u8, keysym: Keysym) -> Result<()> {
+    // Let the server handle the key events already sent first
+    conn.get_input_focus()?.reply()?;
+    let syms = vec![u32::from(keysym); usize::from(keysyms_per_keycode)];
+    conn.change_keyboard_mapping(1, keycode, keysyms_per_keycode, &syms)?;
+    conn.get_input_focus()?.reply()?;
+    std::thread::sleep(REMAP_DELAY);
+    Ok(())
+}
+
+/// [`KeyboardHardware`] that sends key events through XTEST
+pub struct XTestKeyboardHardware {
+    conn: RustConnection,
+    root: Window,
+    keymap: XKeymap,
+    cadence: Cadence,
+}
+
+impl XTestKeyboardHardware {
+    /// Connect to `display`, or `$DISPLAY` if `None`
+    pub fn connect(display: Option<&str>) -> Result<Self> {
+        let (conn, screen) =
+            x11rb::connect(display).context("Failed to connect to the X server")?;
+        let version = conn
+            .xtest_get_version(2, 2)
+            .context("The X server does not support XTEST")?
+            .reply()
+            .context("The X server does not support XTEST")?;
+        debug!(
+            "XTEST {}.{} available",
+            version.major_version, version.minor_version
+        );
+
+        let root = conn.setup().roots[screen].root;
+        let keymap = Self::load_keymap(&conn)?;
+        info!(
+            "Typing through XTEST ({} characters mapped)",
+            keymap.chars.len()
+        );
+        if keymap.spare.is_empty() {
+            warn!("No free keycode; characters missing from the layout will be skipped");
+        }
+
+        Ok(Self {
+            conn,
+            root,
+            keymap,
+            cadence: Cadence::default(),
+        })
+    }
+
+    fn load_keymap(conn: &RustConnection) -> Result<XKeymap> {
+        let setup = conn.setup();
+        let count = setup.max_keycode - setup.min_keycode + 1;
+        let mapping = conn
+            .get_keyboard_mapping(setup.min_keycode, count)?
+            .reply()
+            .context("Failed to read the keyboard mapping")?;
+        Ok(XKeymap::from_mapping(
+            setup.min_keycode,
+            mapping.keysyms_per_keycode,
+            &mapping.keysyms,
+        ))
+    }
+
+    fn send_key(&self, keycode: u8, pressed: bool) -> Result<()> {
+        let event = if pressed {
+            xproto::KEY_PRESS_EVENT
+        } else {
+            xproto::KEY_RELEASE_EVENT
+        };
+        self.conn
+            .xtest_fake_input(event, keycode, x11rb::CURRENT_TIME, self.root, 0, 0, 0)?;
+        Ok(())
+    }
+
+    fn tap(&self, keycode: u8, shift: bool) -> Result<()> {
+        let shift = if shift { self.keymap.shift } else { None };
+        if let Some(shift) = shift {
+            self.send_key(shift, true)?;
+        }
+        self.send_key(keycode, true)?;
+        self.send_key(keycode, false)?;
+        if let Some(shift) = shift {
+            self.send_key(shift, false)?;
+        }
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    /// Translate an evdev keycode to the server's numbering
+    fn x_keycode(keycode: u16) -> Result<u8> {
+        match u8::try_from(keycode.saturating_add(X_KEYCODE_OFFSET)) {
+            Ok(keycode) => Ok(keycode),
+            Err(_) => bail!("Keycode {} is out of range for X11", keycode),
+        }
+    }
+}
+
+impl KeyboardHardware for XTestKeyboardHardware {
+    fn type_text(&mut self, text: &str) -> Result<Vec<TypedChar>> {
+        debug!("Typing text via XTEST: '{}'", text);
+
+        let mut typed = Vec::with_capacity(text.len());
+        let mut sent = 0;
+        let mut spare = SpareBindings::new(&self.conn, &self.keymap);
+        for c in text.chars() {
+            let keycode = match self.keymap.lookup(c) {
+                Some(key) => Some(key),
+                None if self.keymap.can_type(c) => spare
+                    .keycode(Keysym::from_char(c))?
+                    .map(|keycode| (keycode, false)),
+                None => None,
+            };
+            let emitted = match keycode {
+                Some((keycode, shift)) => {
+                    self.tap(keycode, shift)?;
+                    true
+                }
+                None => {
+                    warn!("Unsupported character: '{}'", c);
+                    false
+                }
+            };
+            typed.push(TypedChar {
+                intended: c,
+                emitted,
+            });
+
+            if emitted {
+                sent += 1;
+                self.cadence.pace_chars(sent);
+            }
+        }
+
+        Ok(typed)
+    }
+
+    fn press_backspace(&mut self) -> Result<()> {
+        self.press_key(crate::input_event::KEY_BACKSPACE)
+    }
+
+    fn press_enter(&mut self) -> Result<()> {
+        self.press_key(crate::input_event::KEY_ENTER)
+    }
+
+    fn press_key(&mut self, keycode: u16) -> Result<()> {
+        self.press_combo(&[keycode])
+    }
+
+    fn press_combo(&mut self, keycodes: &[u16]) -> Result<()> {
+        let keycodes = keycodes
+            .iter()
+            .map(|&keycode| Self::x_keycode(keycode))
+            .collect::<Result<Vec<_>>>()?;
+        for &keycode in &keycodes {
+            self.send_key(keycode, true)?;
+        }
+        for &keycode in keycodes.iter().rev() {
+            self.send_key(keycode, false)?;
+        }
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    fn can_type(&self, c: char) -> bool {
+        self.keymap.can_type(c)
+    }
+
+    fn press_backspaces(&mut self, count: usize) -> Result<()> {
+        for pressed in 1..=count {
+            self.press_backspace()?;
+            self.cadence.pace_backspaces(pressed);
+        }
+        Ok(())
+    }
+
+    fn set_cadence(&mut self, cadence: Cadence) {
+        self.cadence = cadence;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{Child, Command};
+    use x11rb::protocol::xproto::{CreateWindowAux, EventMask, InputFocus, WindowClass};
+    use x11rb::protocol::Event;
+
+    /// Keysyms for keycodes 8.. of a small US-like mapping, two per keycode
+    fn sample_mapping() -> Vec<u32> {
+        let mut keysyms = vec![0; 2 * 60];
+        let mut set = |keycode: usize, syms: [u32; 2]| {
+            keysyms[2 * (keycode - 8)..2 * (keycode - 8) + 2].copy_from_slice(&syms);
+        };
+        set(36, [0xff0d, 0]); // Return
+        set(38, ['a' as u32, 'A' as u32]);
+        set(50, [0xffe1, 0]); // Shift_L
+        set(11, ['2' as u32, '@' as u32]);
+        set(60, [0xffb1, 0xff9c]); // KP_1 / KP_End
+        keysyms
+    }
+
+    #[test]
+    fn test_keymap_lookup() {
+        let keymap = XKeymap::from_mapping(8, 2, &sample_mapping());
+        assert_eq!(keymap.lookup('a'), Some((38, false)));
+        assert_eq!(keymap.lookup('A'), Some((38, true)));
+        assert_eq!(keymap.lookup('@'), Some((11, true)));
+        assert_eq!(keymap.lookup('\n'), Some((36, false)));
+        // Keypad keys must not stand in for the digit row
+        assert_eq!(keymap.lookup('1'), None);
+        assert_eq!(keymap.shift, Some(50));
+        assert_eq!(keymap.spare, vec![67, 66, 65, 64]);
+    }
+
+    #[test]
+    fn test_spare_keycodes_take_turns() {
+        let euro = Keysym::from_char('€');
+        let pound = Keysym::from_char('£');
+        let yen = Keysym::from_char('¥');
+        let mut slots = SpareSlots::new(&[67, 66]);
+        assert_eq!(slots.assign(euro), Some((67, true)));
+        assert_eq!(slots.assign(pound), Some((66, true)));
+        // Bound keysyms are typed without remapping
+        assert_eq!(slots.assign(euro), Some((67, false)));
+        // The keycode bound longest ago is reused first
+        assert_eq!(slots.assign(yen), Some((67, true)));
+        assert_eq!(slots.assign(euro), Some((66, true)));
+        assert_eq!(slots.bound().collect::<Vec<_>>(), vec![67, 66]);
+
+        assert_eq!(SpareSlots::new(&[]).assign(euro), None);
+    }
+
+    #[test]
+    fn test_unmapped_characters_need_a_spare_keycode() {
+        let keymap = XKeymap::from_mapping(8, 2, &sample_mapping());
+        assert!(keymap.can_type('€'));
+
+        let full: Vec<u32> = sample_mapping()
+            .into_iter()
+            .map(|sym| if sym == 0 { 0xff1b } else { sym })
+            .collect();
+        let keymap = XKeymap::from_mapping(8, 2, &full);
+        assert!(keymap.spare.is_empty());
+        assert!(!keymap.can_type('€'));
+        assert!(keymap.can_type('a'));
+    }
+
+    struct Xvfb(Child);
+
+    impl Drop for Xvfb {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    /// Types into a window on a private Xvfb server and reads back the key
+    /// events. Needs `Xvfb` on PATH: `cargo test -- --ignored xtest`
+    #[test]
+    #[ignore]
+    fn test_xtest_types_into_xvfb() {
+        let display = ":97";
+        let _server = Xvfb(Command::new("Xvfb").arg(display).spawn().unwrap());
+        let socket = std::path::Path::new("/tmp/.X11-unix/X97");
+        for _ in 0..50 {
+            if socket.exists() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        // A focused window to receive the typed keys
+        let (conn, screen) = x11rb::connect(Some(display)).unwrap();
+        let root = conn.setup().roots[screen].root;
+        let window = conn.generate_id().unwrap();
+        conn.create_window(
+            0,
+            window,
+            root,
+            0,
+            0,
+            100,
+            100,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            0,
+            &CreateWindowAux::new().event_mask(EventMask::KEY_PRESS),
+        )
+        .unwrap();
+        conn.map_window(window).unwrap();
+        conn.set_input_focus(InputFocus::POINTER_ROOT, window, x11rb::CURRENT_TIME)
+            .unwrap();
+        conn.get_input_focus().unwrap().reply().unwrap();
+
+        let mut hardware = XTestKeyboardHardware::connect(Some(display)).unwrap();
+        let typed = hardware.type_text("Hi a€").unwrap();
+        assert!(typed.iter().all(|t| t.emitted));
+        hardware.press_backspace().unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+
+        let mut presses = Vec::new();
+        while let Some(event) = conn.poll_for_event().unwrap() {
+            if let Event::KeyPress(key) = event {
+                presses.push(key.detail);
+            }
+        }
+        let keymap = hardware.keymap.clone();
+        let shift = keymap.shift.unwrap();
+        let expected = [
+            shift,
+            keymap.lookup('H').unwrap().0,
+            keymap.lookup('i').unwrap().0,
+            keymap.lookup(' ').unwrap().0,
+            keymap.lookup('a').unwrap().0,
+            keymap.spare[0],
+            // KEY_BACKSPACE in the server's numbering
+            22,
+        ];
+        assert_eq!(presses, expected);
+    }
+}
@@ -14,7 +14,7 @@
       {
         defaultPackage = naersk-lib.buildPackage ./.;
         devShell = with pkgs; mkShell {
-          buildInputs = [ cargo rustc rustfmt rust-analyzer pre-commit rustPackages.clippy alsa-lib ];
+          buildInputs = [ cargo rustc rustfmt rust-analyzer pre-commit rustPackages.clippy alsa-lib xorg.xvfb ];
           RUST_SRC_PATH = rustPlatform.rustLibSrc;
         };
       }